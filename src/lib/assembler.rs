@@ -0,0 +1,832 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::{AddressingMode, InstructionType, OpcodeInfo, Variant, OPCODES, OPCODES_65C02};
+use crate::memory::CpuBus;
+
+/// What went wrong on a line of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyErrorKind {
+    /// The text does not parse
+    Syntax(String),
+    UnknownInstruction(String),
+    UnknownDirective(String),
+    /// The instruction has no opcode for the operand as written
+    InvalidAddressingMode(InstructionType),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    /// A value does not fit in its operand or data byte
+    OutOfRange(i64),
+    /// A branch destination is more than 128 bytes away
+    BranchOutOfRange(i64),
+}
+
+/// Error with the 1-based line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AssemblyErrorKind::Syntax(message) => write!(f, "{}", message),
+            AssemblyErrorKind::UnknownInstruction(name) => write!(f, "unknown instruction {}", name),
+            AssemblyErrorKind::UnknownDirective(name) => write!(f, "unknown directive .{}", name),
+            AssemblyErrorKind::InvalidAddressingMode(mnemonic) => {
+                write!(f, "{} does not support this addressing mode", mnemonic)
+            }
+            AssemblyErrorKind::UndefinedSymbol(name) => write!(f, "undefined symbol {}", name),
+            AssemblyErrorKind::DuplicateSymbol(name) => write!(f, "{} is already defined", name),
+            AssemblyErrorKind::OutOfRange(value) => write!(f, "value {} is out of range", value),
+            AssemblyErrorKind::BranchOutOfRange(offset) => {
+                write!(f, "branch destination is {} bytes away, the limit is -128 to 127", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// Bytes assembled at consecutive addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub start: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// Output of the assembler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// One segment per `.org`
+    pub segments: Vec<Segment>,
+    /// Labels and constants. Local labels are named `scope@label`, negative
+    /// constants are kept as 16 bit two's complement.
+    pub symbols: HashMap<String, usize>,
+}
+
+impl Program {
+    /// Write every segment to memory
+    pub fn load<B: CpuBus>(&self, bus: &mut B) {
+        for segment in &self.segments {
+            for (i, &byte) in segment.bytes.iter().enumerate() {
+                bus.write((segment.start + i) & 0xFFFF, byte);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    /// `*`, the address of the current line
+    Pc,
+    /// `-`, `~`, `<` low byte or `>` high byte
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Operand as written, before the addressing mode is chosen
+#[derive(Debug, Clone)]
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(Expr),
+    Direct(Expr),
+    IndexedX(Expr),
+    IndexedY(Expr),
+    Indirect(Expr),
+    IndirectX(Expr),
+    IndirectY(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum DataItem {
+    Value(Expr),
+    Text(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction(InstructionType, Operand),
+    Org(Expr),
+    /// `.byte` or `.word` items and their width in bytes
+    Data(usize, Vec<DataItem>),
+    Constant(String, Expr),
+}
+
+struct SourceLine {
+    number: usize,
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+/// Two-pass assembler for a subset of the ca65 syntax: labels, `@local`
+/// labels scoped to the previous label, `name = value` constants, `.org`,
+/// `.byte` and `.word`, and expressions with `*` for the current address.
+pub struct Assembler {
+    opcodes: &'static [OpcodeInfo; 256],
+}
+
+impl Assembler {
+    pub fn new(variant: Variant) -> Assembler {
+        Assembler {
+            opcodes: if variant == Variant::Cmos65C02 { &OPCODES_65C02 } else { &OPCODES },
+        }
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<Program, AssemblyError> {
+        let lines = self.parse(source)?;
+
+        // First pass: addresses of the labels and sizes of the instructions.
+        // Operands not known yet take the absolute form.
+        let mut symbols: HashMap<String, i64> = HashMap::new();
+        let mut constants = Vec::new();
+        let mut modes = vec![None; lines.len()];
+        let mut pc: i64 = 0;
+        for (i, line) in lines.iter().enumerate() {
+            let error = |kind| AssemblyError { line: line.number, kind };
+            if let Some(label) = &line.label {
+                define(&mut symbols, label, pc).map_err(error)?;
+            }
+            match &line.statement {
+                Some(Statement::Org(expr)) => {
+                    pc = origin(eval(expr, &symbols, pc, true).map_err(error)?.unwrap()).map_err(error)?;
+                }
+                Some(Statement::Data(width, items)) => pc += data_size(*width, items) as i64,
+                Some(Statement::Constant(name, expr)) => match eval(expr, &symbols, pc, false).map_err(error)? {
+                    Some(value) => define(&mut symbols, name, value).map_err(error)?,
+                    None => constants.push((line.number, name, expr, pc)),
+                },
+                Some(Statement::Instruction(mnemonic, operand)) => {
+                    let (mode, opcode) = self.choose_mode(*mnemonic, operand, &symbols, pc).map_err(error)?;
+                    modes[i] = Some((mode, opcode));
+                    pc += mode.bytes() as i64;
+                }
+                None => {}
+            }
+        }
+
+        // Constants referring to later labels or constants
+        while !constants.is_empty() {
+            let count = constants.len();
+            let mut unresolved = Vec::new();
+            for (number, name, expr, pc) in constants {
+                let error = |kind| AssemblyError { line: number, kind };
+                match eval(expr, &symbols, pc, false).map_err(error)? {
+                    Some(value) => define(&mut symbols, name, value).map_err(error)?,
+                    None => unresolved.push((number, name, expr, pc)),
+                }
+            }
+            if unresolved.len() == count {
+                let (number, _, expr, pc) = unresolved[0];
+                let kind = eval(expr, &symbols, pc, true).unwrap_err();
+                return Err(AssemblyError { line: number, kind });
+            }
+            constants = unresolved;
+        }
+
+        // Second pass: encode with every symbol known
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut pc: i64 = 0;
+        let mut new_segment = true;
+        for (i, line) in lines.iter().enumerate() {
+            let error = |kind| AssemblyError { line: line.number, kind };
+            let mut bytes = Vec::new();
+            match &line.statement {
+                Some(Statement::Org(expr)) => {
+                    pc = origin(eval(expr, &symbols, pc, true).map_err(error)?.unwrap()).map_err(error)?;
+                    new_segment = true;
+                }
+                Some(Statement::Data(width, items)) => {
+                    for item in items {
+                        match item {
+                            DataItem::Text(text) => bytes.extend_from_slice(text),
+                            DataItem::Value(expr) => {
+                                let value = eval(expr, &symbols, pc, true).map_err(error)?.unwrap();
+                                if *width == 1 {
+                                    bytes.push(byte(value).map_err(error)?);
+                                } else {
+                                    bytes.extend_from_slice(&word(value).map_err(error)?.to_le_bytes());
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(Statement::Instruction(_, operand)) => {
+                    let (mode, opcode) = modes[i].unwrap();
+                    bytes.push(opcode);
+                    self.encode_operand(mode, operand, &symbols, pc, &mut bytes).map_err(error)?;
+                }
+                Some(Statement::Constant(..)) | None => {}
+            }
+            if !bytes.is_empty() {
+                if new_segment {
+                    segments.push(Segment { start: pc as usize, bytes: Vec::new() });
+                    new_segment = false;
+                }
+                pc += bytes.len() as i64;
+                segments.last_mut().unwrap().bytes.extend(bytes);
+            }
+        }
+
+        let symbols = symbols.into_iter().map(|(name, value)| (name, value as usize & 0xFFFF)).collect();
+        Ok(Program { segments, symbols })
+    }
+
+    /// Split the source into labels and statements, giving local labels their full name
+    fn parse(&self, source: &str) -> Result<Vec<SourceLine>, AssemblyError> {
+        let mut lines = Vec::new();
+        let mut scope = String::new();
+        for (index, text) in source.lines().enumerate() {
+            let number = index + 1;
+            let error = |kind| AssemblyError { line: number, kind };
+            let mut text = strip_comment(text).trim();
+
+            let mut label = None;
+            let mut name_length = identifier_length(text);
+            if name_length > 0 && text[name_length..].starts_with(':') {
+                let name = &text[..name_length];
+                if !name.starts_with('@') {
+                    scope = name.to_string();
+                }
+                label = Some(full_name(&scope, name).map_err(error)?);
+                text = text[name_length + 1..].trim();
+                name_length = identifier_length(text);
+            }
+
+            let statement = if text.is_empty() {
+                None
+            } else if let Some(directive) = text.strip_prefix('.') {
+                let length = identifier_length(directive);
+                let arguments = &directive[length..];
+                Some(match directive[..length].to_ascii_lowercase().as_str() {
+                    "org" => Statement::Org(parse_expr(arguments, &scope).map_err(error)?),
+                    "byte" | "byt" => Statement::Data(1, parse_data(arguments, &scope).map_err(error)?),
+                    "word" | "addr" => Statement::Data(2, parse_data(arguments, &scope).map_err(error)?),
+                    name => return Err(error(AssemblyErrorKind::UnknownDirective(name.to_string()))),
+                })
+            } else if name_length == 0 {
+                return Err(error(AssemblyErrorKind::Syntax(format!("unexpected {}", text))));
+            } else if text[name_length..].trim_start().starts_with('=') {
+                let name = full_name(&scope, &text[..name_length]).map_err(error)?;
+                let value = text[name_length..].trim_start()[1..].trim();
+                Some(Statement::Constant(name, parse_expr(value, &scope).map_err(error)?))
+            } else {
+                let name = &text[..name_length];
+                let mnemonic = self.mnemonic(name)
+                    .ok_or_else(|| error(AssemblyErrorKind::UnknownInstruction(name.to_string())))?;
+                let operand = parse_operand(text[name_length..].trim(), &scope).map_err(error)?;
+                Some(Statement::Instruction(mnemonic, operand))
+            };
+            lines.push(SourceLine { number, label, statement });
+        }
+        Ok(lines)
+    }
+
+    /// Instruction of the variant with this name, in any case
+    fn mnemonic(&self, name: &str) -> Option<InstructionType> {
+        let name = name.to_ascii_uppercase();
+        self.opcodes.iter().map(|info| info.mnemonic).find(|mnemonic| mnemonic.to_string() == name)
+    }
+
+    /// Opcode of an instruction in an addressing mode, documented opcodes first
+    fn opcode(&self, mnemonic: InstructionType, mode: AddressingMode) -> Option<u8> {
+        let matches = |opcode: &u8| {
+            let info = &self.opcodes[*opcode as usize];
+            info.mnemonic == mnemonic && info.addressing_mode == mode
+        };
+        (0..=0xFF).filter(|&opcode| OpcodeInfo::documented(opcode)).find(matches)
+            .or_else(|| (0..=0xFF).find(matches))
+    }
+
+    /// Pick the addressing mode for an operand. Zero page forms are used when
+    /// the value is already known to fit, or when there is no absolute form.
+    fn choose_mode(&self, mnemonic: InstructionType, operand: &Operand, symbols: &HashMap<String, i64>,
+                   pc: i64) -> Result<(AddressingMode, u8), AssemblyErrorKind> {
+        use AddressingMode::*;
+        let (expr, modes): (Option<&Expr>, &[AddressingMode]) = match operand {
+            Operand::None => (None, &[Implied, Accumulator]),
+            Operand::Accumulator => (None, &[Accumulator]),
+            Operand::Immediate(_) => (None, &[Immediate]),
+            Operand::Direct(expr) => (Some(expr), &[Relative, ZeroPage, Absolute]),
+            Operand::IndexedX(expr) => (Some(expr), &[ZeroPageX, AbsoluteX]),
+            Operand::IndexedY(expr) => (Some(expr), &[ZeroPageY, AbsoluteY]),
+            Operand::Indirect(_) => (None, &[Indirect, ZeroPageIndirect]),
+            Operand::IndirectX(_) => (None, &[IndirectX, AbsoluteIndexedIndirect]),
+            Operand::IndirectY(_) => (None, &[IndirectY]),
+        };
+        let small = match expr {
+            Some(expr) => matches!(eval(expr, symbols, pc, false)?, Some(0..=0xFF)),
+            None => false,
+        };
+        let candidates: Vec<(AddressingMode, u8)> = modes.iter()
+            .filter_map(|&mode| self.opcode(mnemonic, mode).map(|opcode| (mode, opcode)))
+            .collect();
+        let choice = match candidates.as_slice() {
+            [] => return Err(AssemblyErrorKind::InvalidAddressingMode(mnemonic)),
+            [(Relative, _), ..] => candidates[0],
+            [zero_page, absolute] if !small && zero_page.0.bytes() == 2 && absolute.0.bytes() == 3 => *absolute,
+            _ => candidates[0],
+        };
+        Ok(choice)
+    }
+
+    fn encode_operand(&self, mode: AddressingMode, operand: &Operand, symbols: &HashMap<String, i64>,
+                      pc: i64, bytes: &mut Vec<u8>) -> Result<(), AssemblyErrorKind> {
+        let expr = match operand {
+            Operand::None | Operand::Accumulator => return Ok(()),
+            Operand::Immediate(expr) | Operand::Direct(expr) | Operand::IndexedX(expr)
+                | Operand::IndexedY(expr) | Operand::Indirect(expr) | Operand::IndirectX(expr)
+                | Operand::IndirectY(expr) => expr,
+        };
+        let value = eval(expr, symbols, pc, true)?.unwrap();
+        match mode {
+            AddressingMode::Immediate => bytes.push(byte(value)?),
+            AddressingMode::Relative => {
+                let offset = value - (pc + 2);
+                if !(-128..=127).contains(&offset) {
+                    return Err(AssemblyErrorKind::BranchOutOfRange(offset));
+                }
+                bytes.push(offset as u8);
+            }
+            _ if mode.bytes() == 2 => {
+                if !(0..=0xFF).contains(&value) {
+                    return Err(AssemblyErrorKind::OutOfRange(value));
+                }
+                bytes.push(value as u8);
+            }
+            _ => bytes.extend_from_slice(&word(value)?.to_le_bytes()),
+        }
+        Ok(())
+    }
+}
+
+fn define(symbols: &mut HashMap<String, i64>, name: &str, value: i64) -> Result<(), AssemblyErrorKind> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(AssemblyErrorKind::DuplicateSymbol(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Value of an expression, None when it uses a symbol not defined yet.
+/// When `strict`, undefined symbols are an error instead.
+fn eval(expr: &Expr, symbols: &HashMap<String, i64>, pc: i64, strict: bool) -> Result<Option<i64>, AssemblyErrorKind> {
+    let value = match expr {
+        Expr::Number(value) => *value,
+        Expr::Pc => pc,
+        Expr::Symbol(name) => match symbols.get(name) {
+            Some(value) => *value,
+            None if strict => return Err(AssemblyErrorKind::UndefinedSymbol(name.clone())),
+            None => return Ok(None),
+        },
+        Expr::Unary(op, operand) => {
+            let Some(value) = eval(operand, symbols, pc, strict)? else { return Ok(None) };
+            match op {
+                '-' => value.wrapping_neg(),
+                '~' => !value,
+                '<' => value & 0xFF,
+                _ => (value >> 8) & 0xFF,
+            }
+        }
+        Expr::Binary(op, left, right) => {
+            let left = eval(left, symbols, pc, strict)?;
+            let right = eval(right, symbols, pc, strict)?;
+            let (Some(left), Some(right)) = (left, right) else { return Ok(None) };
+            match *op {
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" if right == 0 => return Err(AssemblyErrorKind::Syntax(String::from("division by zero"))),
+                "/" => left.wrapping_div(right),
+                "&" => left & right,
+                "|" => left | right,
+                "^" => left ^ right,
+                "<<" => left.wrapping_shl(right as u32),
+                _ => left.wrapping_shr(right as u32),
+            }
+        }
+    };
+    Ok(Some(value))
+}
+
+/// Immediate and `.byte` values, negative ones down to -128 included
+fn byte(value: i64) -> Result<u8, AssemblyErrorKind> {
+    if !(-0x80..=0xFF).contains(&value) {
+        return Err(AssemblyErrorKind::OutOfRange(value));
+    }
+    Ok(value as u8)
+}
+
+fn word(value: i64) -> Result<u16, AssemblyErrorKind> {
+    if !(-0x8000..=0xFFFF).contains(&value) {
+        return Err(AssemblyErrorKind::OutOfRange(value));
+    }
+    Ok(value as u16)
+}
+
+/// `.org` addresses, which can't be negative
+fn origin(value: i64) -> Result<i64, AssemblyErrorKind> {
+    if !(0..=0xFFFF).contains(&value) {
+        return Err(AssemblyErrorKind::OutOfRange(value));
+    }
+    Ok(value)
+}
+
+fn data_size(width: usize, items: &[DataItem]) -> usize {
+    items.iter().map(|item| match item {
+        DataItem::Text(text) => text.len(),
+        DataItem::Value(_) => width,
+    }).sum()
+}
+
+/// Text before a `;` that is not in a string or character
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &text[..i],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Length of the label or symbol name at the start of the text, `@` prefix included
+fn identifier_length(text: &str) -> usize {
+    let start = if text.starts_with('@') { 1 } else { 0 };
+    let rest = &text[start..];
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    start + rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len())
+}
+
+/// Name of a symbol, local ones prefixed with the label they belong to
+fn full_name(scope: &str, name: &str) -> Result<String, AssemblyErrorKind> {
+    if !name.starts_with('@') {
+        return Ok(name.to_string());
+    }
+    if scope.is_empty() {
+        return Err(AssemblyErrorKind::Syntax(format!("local label {} before any label", name)));
+    }
+    Ok(format!("{}{}", scope, name))
+}
+
+/// Split at the commas outside of parentheses and quotes
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+fn parse_data(text: &str, scope: &str) -> Result<Vec<DataItem>, AssemblyErrorKind> {
+    split_arguments(text).into_iter().map(|item| {
+        if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+            Ok(DataItem::Text(item.as_bytes()[1..item.len() - 1].to_vec()))
+        } else {
+            Ok(DataItem::Value(parse_expr(item, scope)?))
+        }
+    }).collect()
+}
+
+fn parse_operand(text: &str, scope: &str) -> Result<Operand, AssemblyErrorKind> {
+    if text.is_empty() {
+        return Ok(Operand::None);
+    }
+    if text.eq_ignore_ascii_case("a") {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(parse_expr(value, scope)?));
+    }
+
+    let parts = split_arguments(text);
+    let index = match parts.as_slice() {
+        [_] => None,
+        [_, index] if index.eq_ignore_ascii_case("x") => Some('X'),
+        [_, index] if index.eq_ignore_ascii_case("y") => Some('Y'),
+        _ => return Err(AssemblyErrorKind::Syntax(format!("invalid operand {}", text))),
+    };
+    let base = parts[0];
+
+    // Parentheses around the whole base make it indirect, (1+2)*3 is an expression
+    if base.starts_with('(') && closing_paren(base) == Some(base.len() - 1) {
+        let inner = &base[1..base.len() - 1];
+        let inner_parts = split_arguments(inner);
+        return match (inner_parts.as_slice(), index) {
+            ([pointer], None) => Ok(Operand::Indirect(parse_expr(pointer, scope)?)),
+            ([pointer], Some('Y')) => Ok(Operand::IndirectY(parse_expr(pointer, scope)?)),
+            ([pointer, x], None) if x.eq_ignore_ascii_case("x") => Ok(Operand::IndirectX(parse_expr(pointer, scope)?)),
+            _ => Err(AssemblyErrorKind::Syntax(format!("invalid operand {}", text))),
+        };
+    }
+    let expr = parse_expr(base, scope)?;
+    Ok(match index {
+        None => Operand::Direct(expr),
+        Some('X') => Operand::IndexedX(expr),
+        _ => Operand::IndexedY(expr),
+    })
+}
+
+/// Position of the parenthesis closing the one the text starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(&'static str),
+}
+
+fn tokenize(text: &str, scope: &str) -> Result<Vec<Token>, AssemblyErrorKind> {
+    const OPERATORS: [&str; 13] = ["<<", ">>", "+", "-", "*", "/", "&", "|", "^", "~", "<", ">", "("];
+    let syntax = |message: String| AssemblyErrorKind::Syntax(message);
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        let length = if c == '$' || c == '%' || c.is_ascii_digit() {
+            let (radix, digits) = match c {
+                '$' => (16, &rest[1..]),
+                '%' => (2, &rest[1..]),
+                _ => (10, rest),
+            };
+            let length = digits.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(digits.len());
+            let value = i64::from_str_radix(&digits[..length], radix)
+                .map_err(|_| syntax(format!("invalid number {}", &rest[..length + rest.len() - digits.len()])))?;
+            tokens.push(Token::Number(value));
+            length + rest.len() - digits.len()
+        } else if c == '\'' {
+            let mut chars = rest[1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some(value), Some('\'')) => tokens.push(Token::Number(value as i64)),
+                _ => return Err(syntax(format!("invalid character in {}", text))),
+            }
+            2 + rest[1..].chars().next().unwrap().len_utf8()
+        } else if identifier_length(rest) > 0 {
+            let length = identifier_length(rest);
+            tokens.push(Token::Symbol(full_name(scope, &rest[..length])?));
+            length
+        } else if c == ')' {
+            tokens.push(Token::Op(")"));
+            1
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            return Err(syntax(format!("unexpected {} in {}", c, text)));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Binary operators by increasing precedence
+const PRECEDENCE: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+fn parse_expr(text: &str, scope: &str) -> Result<Expr, AssemblyErrorKind> {
+    let tokens = tokenize(text, scope)?;
+    if tokens.is_empty() {
+        return Err(AssemblyErrorKind::Syntax(String::from("missing value")));
+    }
+    let mut position = 0;
+    let expr = parse_binary(&tokens, &mut position, 0)?;
+    if position != tokens.len() {
+        return Err(AssemblyErrorKind::Syntax(format!("unexpected text in {}", text.trim())));
+    }
+    Ok(expr)
+}
+
+fn parse_binary(tokens: &[Token], position: &mut usize, level: usize) -> Result<Expr, AssemblyErrorKind> {
+    if level == PRECEDENCE.len() {
+        return parse_unary(tokens, position);
+    }
+    let mut left = parse_binary(tokens, position, level + 1)?;
+    while let Some(Token::Op(op)) = tokens.get(*position) {
+        if !PRECEDENCE[level].contains(op) {
+            break;
+        }
+        *position += 1;
+        let right = parse_binary(tokens, position, level + 1)?;
+        left = Expr::Binary(op, Box::new(left), Box::new(right));
+    }
+    Ok(left)
+}
+
+fn parse_unary(tokens: &[Token], position: &mut usize) -> Result<Expr, AssemblyErrorKind> {
+    let token = tokens.get(*position).cloned();
+    *position += 1;
+    match token {
+        Some(Token::Number(value)) => Ok(Expr::Number(value)),
+        Some(Token::Symbol(name)) => Ok(Expr::Symbol(name)),
+        // `*` where a value is expected is the current address
+        Some(Token::Op("*")) => Ok(Expr::Pc),
+        Some(Token::Op("(")) => {
+            let expr = parse_binary(tokens, position, 0)?;
+            if tokens.get(*position) != Some(&Token::Op(")")) {
+                return Err(AssemblyErrorKind::Syntax(String::from("missing )")));
+            }
+            *position += 1;
+            Ok(expr)
+        }
+        Some(Token::Op(op)) if ["-", "~", "<", ">"].contains(&op) => {
+            let operand = parse_unary(tokens, position)?;
+            Ok(Expr::Unary(op.chars().next().unwrap(), Box::new(operand)))
+        }
+        Some(Token::Op(op)) => Err(AssemblyErrorKind::Syntax(format!("unexpected {}", op))),
+        None => Err(AssemblyErrorKind::Syntax(String::from("missing value"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CPU, FlatMemory};
+
+    fn assemble(source: &str) -> Program {
+        Assembler::new(Variant::Ricoh2A03).assemble(source).unwrap()
+    }
+
+    fn error(variant: Variant, source: &str) -> (usize, AssemblyErrorKind) {
+        let error = Assembler::new(variant).assemble(source).unwrap_err();
+        (error.line, error.kind)
+    }
+
+    #[test]
+    pub fn test_encoding() {
+        let program = assemble("
+            PPUSTATUS = $2002
+            counter = $10
+            .org $C000
+            reset:  sei                 ; comment
+                    ldx #$FF
+                    txs
+            @wait:  bit PPUSTATUS
+                    bpl @wait
+                    lda counter
+                    sta counter,x
+                    lda table,y
+                    asl
+                    rol a
+                    jmp (vector)
+                    lda (counter),y
+                    sta (counter,x)
+                    ldx counter,y
+                    jsr later
+            later:  bne reset
+            table:  .byte 1, -1, <reset, >reset, \"Hi;\", 'A'
+            vector: .word reset, * + 2
+        ");
+        assert_eq!(program.segments.len(), 1);
+        assert_eq!(program.segments[0].start, 0xC000);
+        assert_eq!(program.segments[0].bytes, [
+            0x78,
+            0xA2, 0xFF,
+            0x9A,
+            0x2C, 0x02, 0x20,
+            0x10, 0xFB,
+            0xA5, 0x10,
+            0x95, 0x10,
+            0xB9, 0x20, 0xC0,
+            0x0A,
+            0x2A,
+            0x6C, 0x28, 0xC0,
+            0xB1, 0x10,
+            0x81, 0x10,
+            0xB6, 0x10,
+            0x20, 0x1E, 0xC0,
+            0xD0, 0xE0,
+            0x01, 0xFF, 0x00, 0xC0, b'H', b'i', b';', b'A',
+            0x00, 0xC0, 0x2A, 0xC0,
+        ]);
+        assert_eq!(program.symbols["reset"], 0xC000);
+        assert_eq!(program.symbols["reset@wait"], 0xC004);
+        assert_eq!(program.symbols["PPUSTATUS"], 0x2002);
+        assert_eq!(program.symbols["vector"], 0xC028);
+    }
+
+    #[test]
+    pub fn test_expressions_and_forward_references() {
+        let program = assemble("
+            size = last - first
+            .org $0200
+            first:  lda #(1 + 2) * 3
+                    lda #%1010 | $F0 & $3C
+                    lda #1 << 4 >> 2
+                    lda #~0 & $FF
+                    lda #size
+                    lda zero,x
+                    lda (1+2)*3
+            zero = 0
+            last:
+            .org $0300
+                    .word size * 2, -1
+        ");
+        assert_eq!(program.segments, [
+            Segment { start: 0x0200, bytes: vec![0xA9, 9, 0xA9, 0x3A, 0xA9, 4, 0xA9, 0xFF, 0xA9, 15,
+                                                 0xBD, 0x00, 0x00, 0xA5, 0x09] },
+            Segment { start: 0x0300, bytes: vec![30, 0, 0xFF, 0xFF] },
+        ]);
+        assert_eq!(program.symbols["size"], 15);
+    }
+
+    #[test]
+    pub fn test_errors() {
+        use AssemblyErrorKind::*;
+        let nes = Variant::Ricoh2A03;
+        assert_eq!(error(nes, "nop\nfoo"), (2, UnknownInstruction(String::from("foo"))));
+        assert_eq!(error(nes, ".org 0\n.res 4"), (2, UnknownDirective(String::from("res"))));
+        assert_eq!(error(nes, "jmp $10,x"), (1, InvalidAddressingMode(InstructionType::JMP)));
+        assert_eq!(error(nes, "stz $10"), (1, UnknownInstruction(String::from("stz"))));
+        assert_eq!(error(nes, "lda missing"), (1, UndefinedSymbol(String::from("missing"))));
+        assert_eq!(error(nes, "a: nop\na: nop"), (2, DuplicateSymbol(String::from("a"))));
+        assert_eq!(error(nes, "lda #256"), (1, OutOfRange(256)));
+        assert_eq!(error(nes, "nop\n.org $10000"), (2, OutOfRange(0x10000)));
+        assert_eq!(error(nes, ".org -1"), (1, OutOfRange(-1)));
+        assert_eq!(error(nes, "lda #-($4000000000000000*2)"), (1, OutOfRange(i64::MIN)));
+        assert_eq!(error(nes, "lda #(-$7FFFFFFFFFFFFFFF-1)/-1"), (1, OutOfRange(i64::MIN)));
+        assert_eq!(error(nes, "x = y\ny = x"), (1, UndefinedSymbol(String::from("y"))));
+        assert_eq!(error(nes, "start:\n.byte 0\n@loop: bne @loop + 200"), (3, BranchOutOfRange(198)));
+        assert_eq!(error(nes, "@loop: nop"), (1, Syntax(String::from("local label @loop before any label"))));
+        assert_eq!(error(nes, "lda #(1"), (1, Syntax(String::from("missing )"))));
+        assert_eq!(Assembler::new(nes).assemble("\n\n  lda #$1G").unwrap_err().to_string(),
+                   "line 3: invalid number $1G");
+    }
+
+    #[test]
+    pub fn test_65c02() {
+        let program = Assembler::new(Variant::Cmos65C02).assemble("
+            .org $0200
+            start:  stz $10
+                    lda ($10)
+                    jmp (start,x)
+                    bra start
+                    nop
+        ").unwrap();
+        assert_eq!(program.segments[0].bytes, [0x64, 0x10, 0xB2, 0x10, 0x7C, 0x00, 0x02, 0x80, 0xF7, 0xEA]);
+    }
+
+    #[test]
+    pub fn test_run() {
+        // 10 times 3 by repeated addition
+        let program = assemble("
+            .org $0600
+            start:  ldx #10
+                    stx $00
+                    ldx #3
+                    stx $01
+                    ldy $00
+                    lda #0
+                    clc
+            @loop:  adc $01
+                    dey
+                    bne @loop
+                    sta $02
+                    .byte $02           ; JAM
+        ");
+        let mut memory = FlatMemory::new();
+        program.load(&mut memory);
+        let mut cpu = CPU::new();
+        cpu.set_pc(0x0600);
+        while !cpu.halted() {
+            cpu.tick(&mut memory);
+        }
+        assert_eq!(memory.memory[0x02], 30);
+    }
+}
@@ -1,4 +1,18 @@
 use crate::Cartridge;
+use crate::memory::CpuBus;
+
+/// Devices sharing the wire-ORed IRQ line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrqSource {
+    /// APU frame counter
+    FrameCounter = 1 << 0,
+    /// APU delta modulation channel
+    Dmc = 1 << 1,
+    /// Cartridge hardware, on top of what the mapper reports itself
+    Mapper = 1 << 2,
+    /// Expansion port and test harnesses
+    External = 1 << 3,
+}
 
 pub struct Bus {
     /// CPU ram
@@ -10,6 +24,12 @@ pub struct Bus {
     pub apu_io_test: Vec<u8>,
     /// Cartridge space
     pub cartridge: Cartridge,
+    /// IrqSource bits of the devices pulling the IRQ line low
+    irq_sources: u8,
+    /// Level of the NMI line, true while asserted
+    nmi_line: bool,
+    /// Set by the edge detector when the NMI line becomes asserted
+    nmi_edge: bool,
 }
 
 impl Bus {
@@ -20,34 +40,146 @@ impl Bus {
             apu_io: vec!(0; 0x18),
             apu_io_test: vec!(0; 8),
             cartridge: Cartridge::new(),
+            irq_sources: 0,
+            nmi_line: false,
+            nmi_edge: false,
+        }
+    }
+
+    pub fn read(&mut self, i: usize) -> u8 {
+        if i <= 0x401F {
+            return self.peek(i);
+        } else {
+            return self.cartridge.read(i);
         }
     }
 
-    pub fn read(&self, i: usize) -> u8 {
+    /// Read without side effects, for debuggers and traces
+    pub fn peek(&self, i: usize) -> u8 {
         if i <= 0x1FFF {
             return self.ram[i & 0x7FF];
         } else if i <= 0x3FFF {
             return  self.ppu_mem[i & 0x7];
         } else if i <= 0x4017 {
-            return self.apu_io[i % 0x17];
+            return self.apu_io[i - 0x4000];
         } else if i <= 0x401F {
             return self.apu_io_test[i & 0x7];
         } else {
-            return self.cartridge.read(i & 0x3FFF);
+            return self.cartridge.peek(i);
         }
     }
 
+    /// Power-on state: RAM gets the pattern commonly seen on real consoles
+    /// and the APU registers are cleared, silencing every channel.
+    pub fn power_on(&mut self) {
+        for (i, byte) in self.ram.iter_mut().enumerate() {
+            *byte = if i & 0x04 == 0 { 0x00 } else { 0xFF };
+        }
+        self.apu_io.iter_mut().for_each(|r| *r = 0);
+    }
+
+    /// Reset button: RAM is kept, the APU channels are silenced through $4015
+    pub fn reset(&mut self) {
+        self.write(0x4015, 0);
+    }
+
+    /// State of the shared IRQ line, true when any device asserts it
+    pub fn irq(&self) -> bool {
+        self.irq_sources != 0 || self.cartridge.irq()
+    }
+
+    /// Assert or release the IRQ line for one device
+    pub fn set_irq(&mut self, source: IrqSource, active: bool) {
+        if active {
+            self.irq_sources |= source as u8;
+        } else {
+            self.irq_sources &= !(source as u8);
+        }
+    }
+
+    /// Drive the NMI line. Only the transition to asserted triggers an NMI.
+    pub fn set_nmi(&mut self, active: bool) {
+        if active && !self.nmi_line {
+            self.nmi_edge = true;
+        }
+        self.nmi_line = active;
+    }
+
+    /// True when the edge detector saw the NMI line become asserted
+    pub fn nmi_edge(&self) -> bool {
+        self.nmi_edge
+    }
+
+    /// Clear the detected NMI edge once the CPU starts servicing it
+    pub fn acknowledge_nmi(&mut self) {
+        self.nmi_edge = false;
+    }
+
     pub fn write(&mut self, i: usize, value: u8) {
         if i <= 0x1FFF {
             self.ram[i & 0x7FF] = value;
         } else if i <= 0x3FFF {
-            self.ppu_mem[i & 0x8] = value;
+            self.ppu_mem[i & 0x7] = value;
+            self.cartridge.ppu_register_write(0x2000 | (i & 0x7), value);
         } else if i <= 0x4017 {
-            self.apu_io[i & 0x18] = value;
+            self.apu_io[i - 0x4000] = value;
         } else if i <= 0x401F {
-            self.apu_io_test[i & 0x8] = value;
+            self.apu_io_test[i & 0x7] = value;
         } else {
-            self.cartridge.write(i & 0x3FFF, value);
+            self.cartridge.write(i, value);
         }
     }
-}
\ No newline at end of file
+}
+
+impl CpuBus for Bus {
+    fn read(&mut self, address: usize) -> u8 {
+        Bus::read(self, address)
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        Bus::write(self, address, value);
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        Bus::peek(self, address)
+    }
+
+    fn tick(&mut self) {
+        self.cartridge.tick();
+    }
+
+    fn irq(&self) -> bool {
+        Bus::irq(self)
+    }
+
+    fn nmi_edge(&self) -> bool {
+        Bus::nmi_edge(self)
+    }
+
+    fn acknowledge_nmi(&mut self) {
+        Bus::acknowledge_nmi(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_peek_side_effects() {
+        // Namco 163, the internal RAM port advances its address on reads
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0x30, 0x10];
+        rom.resize(16 + 2 * 0x4000 + 0x2000, 0);
+        let mut bus = Bus::new();
+        bus.cartridge = Cartridge::from_bytes(&rom).unwrap();
+        bus.write(0xF800, 0x80 | 0x10);
+        bus.write(0x4800, 0x34);
+        bus.write(0x4800, 0x56);
+        bus.write(0xF800, 0x80 | 0x10);
+
+        assert_eq!(bus.peek(0x4800), 0x34);
+        assert_eq!(CpuBus::peek(&bus, 0x4800), 0x34);
+        assert_eq!(bus.read(0x4800), 0x34);
+        assert_eq!(bus.read(0x4800), 0x56);
+    }
+}
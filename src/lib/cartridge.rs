@@ -1,43 +1,463 @@
-struct Mapper {
-    /// Mapper type
-    ntype: u8,
+mod header;
+mod mapper;
+mod nrom;
+mod mmc1;
+mod uxrom;
+mod cnrom;
+mod axrom;
+mod gxrom;
+mod mmc3;
+mod mmc5;
+mod vrc_irq;
+mod vrc4;
+mod vrc6;
+mod n163;
+mod fme7;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub use header::{CartridgeInfo, ConsoleType, HeaderFormat, Timing};
+pub use mapper::Mapper;
+use header::{HEADER_SIZE, TRAINER_SIZE};
+
+/// CPU cycles between automatic save file flushes, about 5 seconds
+pub const DEFAULT_AUTOSAVE_CYCLES: u64 = 5 * 1_789_773;
+
+/// Nametable mirroring set by the cartridge
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    /// All nametables show the first CIRAM page
+    SingleScreenLower,
+    /// All nametables show the second CIRAM page
+    SingleScreenUpper,
 }
 
-impl Mapper {
-    /// Create type 0 mapper
-    pub fn new() -> Mapper {
-        Mapper {
-            ntype: 0,
-         }
+/// Errors produced while loading a ROM image
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file does not start with the "NES\x1A" magic
+    InvalidMagic,
+    /// The file is shorter than the header says it should be
+    Truncated { expected: usize, actual: usize },
+    /// The header declares no PRG ROM
+    NoPrgRom,
+    /// A NES 2.0 exponent-multiplier size does not fit in memory
+    InvalidSize,
+    /// No implementation for the mapper number
+    UnsupportedMapper(u16),
+    /// The save file could not be read or written
+    SaveIo(io::Error),
+    /// Imported save data does not match the size of the cartridge RAM
+    SaveSize { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "could not read ROM: {}", err),
+            CartridgeError::InvalidMagic => write!(f, "not an iNES file (missing NES<EOF> magic)"),
+            CartridgeError::Truncated { expected, actual } => {
+                write!(f, "truncated ROM: expected {} bytes, got {}", expected, actual)
+            }
+            CartridgeError::NoPrgRom => write!(f, "header declares no PRG ROM"),
+            CartridgeError::InvalidSize => write!(f, "header declares an impossible ROM size"),
+            CartridgeError::UnsupportedMapper(n) => write!(f, "mapper {} is not supported", n),
+            CartridgeError::SaveIo(err) => write!(f, "could not access save file: {}", err),
+            CartridgeError::SaveSize { expected, actual } => {
+                write!(f, "save data is {} bytes, the cartridge has {}", actual, expected)
+            }
+        }
     }
+}
+
+impl std::error::Error for CartridgeError {}
 
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> CartridgeError {
+        CartridgeError::Io(err)
+    }
 }
 
 pub struct Cartridge {
-    prg: Vec<u8>,
-    chr: Vec<u8>,
-    mapper: Mapper,
+    mapper: Box<dyn Mapper>,
+    info: CartridgeInfo,
+    /// 512 byte trainer, empty if not present
+    trainer: Vec<u8>,
+    /// Where battery backed RAM is kept, None to keep it in memory only
+    save_path: Option<PathBuf>,
+    /// Save data as last read from or written to the save file
+    saved: Vec<u8>,
+    /// CPU cycles between automatic flushes, 0 to only flush on drop
+    autosave_interval: u64,
+    autosave_counter: u64,
 }
 
 impl Cartridge {
     pub fn new() -> Cartridge {
+        let info = CartridgeInfo {
+            format: HeaderFormat::INes,
+            mapper: 0,
+            submapper: 0,
+            prg_rom_size: 0x4000,
+            chr_rom_size: 0,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0x2000,
+            chr_nvram_size: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            console: ConsoleType::Nes,
+            timing: Timing::Ntsc,
+        };
         Cartridge {
-        /// NROM
-            prg: vec![0; 0x3FFF],
-            chr: vec![0; 0x1FFF],
-            mapper: Mapper::new(),
+            // NROM
+            mapper: mapper::create(&info, vec![0; 0x4000], vec![0; 0x2000]).unwrap(),
+            info,
+            trainer: Vec::new(),
+            save_path: None,
+            saved: Vec::new(),
+            autosave_interval: DEFAULT_AUTOSAVE_CYCLES,
+            autosave_counter: 0,
+        }
+    }
+
+    /// Load an iNES or NES 2.0 ROM image from disk.
+    /// Battery backed RAM is restored from a .sav file next to the ROM if there is one.
+    pub fn load(&mut self, file_path: String) -> Result<(), CartridgeError> {
+        let data = fs::read(&file_path)?;
+        let mut cartridge = Cartridge::from_bytes(&data)?;
+        cartridge.autosave_interval = self.autosave_interval;
+        if cartridge.has_battery() {
+            cartridge.set_save_path(Path::new(&file_path).with_extension("sav"))?;
+        }
+        *self = cartridge;
+        Ok(())
+    }
+
+    /// Parse an iNES or NES 2.0 ROM image.
+    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, CartridgeError> {
+        let info = CartridgeInfo::parse(data)?;
+
+        let expected = info.file_size()?;
+        if data.len() < expected {
+            return Err(CartridgeError::Truncated { expected, actual: data.len() });
+        }
+
+        let trainer_size = if info.trainer { TRAINER_SIZE } else { 0 };
+        let prg_start = HEADER_SIZE + trainer_size;
+        let chr_start = prg_start + info.prg_rom_size;
+
+        let chr = if info.chr_rom_size == 0 {
+            // Badly headed NES 2.0 images may leave out the CHR RAM size
+            let size = info.chr_ram_size + info.chr_nvram_size;
+            vec![0; if size == 0 { 0x2000 } else { size }]
+        } else {
+            data[chr_start..chr_start + info.chr_rom_size].to_vec()
+        };
+
+        Ok(Cartridge {
+            mapper: mapper::create(&info, data[prg_start..chr_start].to_vec(), chr)?,
+            trainer: data[HEADER_SIZE..prg_start].to_vec(),
+            info,
+            save_path: None,
+            saved: Vec::new(),
+            autosave_interval: DEFAULT_AUTOSAVE_CYCLES,
+            autosave_counter: 0,
+        })
+    }
+
+    /// Keep battery backed RAM in a file, loading it now if the file exists
+    pub fn set_save_path<P: Into<PathBuf>>(&mut self, path: P) -> Result<(), CartridgeError> {
+        let path = path.into();
+        match fs::read(&path) {
+            Ok(data) => {
+                self.import_save(&data)?;
+                self.saved = data;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(CartridgeError::SaveIo(err)),
+        }
+        self.save_path = Some(path);
+        Ok(())
+    }
+
+    /// File battery backed RAM is flushed to
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    /// CPU cycles between automatic flushes of the save file, 0 disables them
+    pub fn set_autosave_interval(&mut self, cycles: u64) {
+        self.autosave_interval = cycles;
+        self.autosave_counter = 0;
+    }
+
+    /// Copy of the battery backed RAM, empty for cartridges without a battery
+    pub fn export_save(&self) -> Vec<u8> {
+        if !self.has_battery() {
+            return Vec::new();
+        }
+        self.mapper.save_ram()
+    }
+
+    /// Replace the battery backed RAM with previously exported data
+    pub fn import_save(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let expected = self.export_save().len();
+        if data.len() != expected {
+            return Err(CartridgeError::SaveSize { expected, actual: data.len() });
         }
+        if expected > 0 {
+            self.mapper.load_save_ram(data);
+        }
+        Ok(())
+    }
+
+    /// Write battery backed RAM to the save file if it changed since the last flush
+    pub fn flush_save(&mut self) -> Result<(), CartridgeError> {
+        let Some(path) = &self.save_path else {
+            return Ok(());
+        };
+        let data = self.export_save();
+        if data.is_empty() || data == self.saved {
+            return Ok(());
+        }
+        fs::write(path, &data).map_err(CartridgeError::SaveIo)?;
+        self.saved = data;
+        Ok(())
+    }
+
+    /// Header information of the loaded ROM
+    pub fn info(&self) -> &CartridgeInfo {
+        &self.info
     }
 
-    pub fn load(&mut self, file_path: String) {
+    /// Mapper number
+    pub fn mapper_number(&self) -> u16 {
+        self.info.mapper
+    }
+
+    /// NES 2.0 submapper number
+    pub fn submapper_number(&self) -> u8 {
+        self.info.submapper
+    }
+
+    /// Current nametable mirroring, may be changed by the mapper at runtime
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.info.battery
+    }
 
+    pub fn has_trainer(&self) -> bool {
+        !self.trainer.is_empty()
+    }
+
+    pub fn has_chr_ram(&self) -> bool {
+        self.info.chr_rom_size == 0
     }
 
     pub fn write(&mut self, i: usize, value: u8) {
-        self.prg[i] = value;
+        self.mapper.cpu_write(i, value);
+    }
+
+    pub fn read(&mut self, i: usize) -> u8 {
+        return self.mapper.cpu_read(i);
+    }
+
+    /// Read without side effects, for debuggers and traces
+    pub fn peek(&self, i: usize) -> u8 {
+        return self.mapper.cpu_peek(i);
     }
 
-    pub fn read(&self, i: usize) -> u8 {
-        return self.prg[i];
+    pub fn ppu_write(&mut self, i: usize, value: u8) {
+        self.mapper.ppu_write(i, value);
     }
-}
\ No newline at end of file
+
+    pub fn ppu_read(&mut self, i: usize) -> u8 {
+        return self.mapper.ppu_read(i);
+    }
+
+    /// CIRAM page the PPU should use for a nametable address
+    pub fn ciram_page(&self, i: usize) -> usize {
+        self.mapper.ciram_page(i)
+    }
+
+    /// Nametable read, None when the PPU should read CIRAM
+    pub fn nametable_read(&mut self, i: usize) -> Option<u8> {
+        self.mapper.nametable_read(i)
+    }
+
+    /// Nametable write, false when the PPU should write CIRAM
+    pub fn nametable_write(&mut self, i: usize, value: u8) -> bool {
+        self.mapper.nametable_write(i, value)
+    }
+
+    /// Let the mapper see CPU writes to the PPU registers
+    pub fn ppu_register_write(&mut self, i: usize, value: u8) {
+        self.mapper.ppu_register_write(i, value);
+    }
+
+    /// True while the cartridge asserts the CPU IRQ line
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    /// Clock the mapper once per CPU cycle
+    pub fn tick(&mut self) {
+        self.mapper.cpu_tick();
+
+        if self.autosave_interval > 0 && self.save_path.is_some() {
+            self.autosave_counter += 1;
+            if self.autosave_counter >= self.autosave_interval {
+                self.autosave_counter = 0;
+                if let Err(err) = self.flush_save() {
+                    eprintln!("{}", err);
+                }
+            }
+        }
+    }
+
+    /// Notify the mapper of a rendered scanline
+    pub fn scanline(&mut self) {
+        self.mapper.scanline();
+    }
+
+    /// Expansion audio level from the mapper
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_save() {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Cartridge, CartridgeError, Mirroring};
+
+    fn rom(prg_banks: u8, chr_banks: u8, flags6: u8, flags7: u8) -> Vec<u8> {
+        let mut data = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, flags6, flags7];
+        data.resize(16, 0);
+        if flags6 & 0x04 == 0x04 {
+            data.resize(data.len() + 512, 0xEE);
+        }
+        data.resize(data.len() + prg_banks as usize * 0x4000, 0x11);
+        data.resize(data.len() + chr_banks as usize * 0x2000, 0x22);
+        data
+    }
+
+    #[test]
+    pub fn test_load_header() {
+        let mut cart = Cartridge::from_bytes(&rom(2, 1, 0x03, 0x00)).unwrap();
+        assert_eq!(cart.info().prg_rom_size, 0x8000);
+        assert_eq!(cart.info().chr_rom_size, 0x2000);
+        assert_eq!(cart.mapper_number(), 0);
+        assert_eq!(cart.mirroring(), Mirroring::Vertical);
+        assert!(cart.has_battery());
+        assert!(!cart.has_trainer());
+        assert!(!cart.has_chr_ram());
+        assert_eq!(cart.read(0x8000), 0x11);
+    }
+
+    #[test]
+    pub fn test_load_trainer_and_chr_ram() {
+        let mut cart = Cartridge::from_bytes(&rom(1, 0, 0x0C, 0)).unwrap();
+        assert!(cart.has_trainer());
+        assert!(cart.has_chr_ram());
+        assert_eq!(cart.mirroring(), Mirroring::FourScreen);
+        assert_eq!(cart.info().chr_ram_size, 0x2000);
+        assert_eq!(cart.read(0x8000), 0x11);
+    }
+
+    #[test]
+    pub fn test_load_errors() {
+        assert!(matches!(Cartridge::from_bytes(b"NOPE"), Err(CartridgeError::InvalidMagic)));
+        assert!(matches!(Cartridge::from_bytes(&rom(0, 0, 0, 0)), Err(CartridgeError::NoPrgRom)));
+        assert!(matches!(Cartridge::from_bytes(&rom(1, 1, 0xF0, 0xF0)),
+                         Err(CartridgeError::UnsupportedMapper(0xFF))));
+
+        let mut data = rom(2, 1, 0, 0);
+        data.truncate(0x5000);
+        match Cartridge::from_bytes(&data) {
+            Err(CartridgeError::Truncated { expected, actual }) => {
+                assert_eq!(expected, 16 + 0x8000 + 0x2000);
+                assert_eq!(actual, 0x5000);
+            }
+            _ => panic!("expected truncated error"),
+        }
+    }
+
+    #[test]
+    pub fn test_import_export_save() {
+        let mut cart = Cartridge::from_bytes(&rom(1, 1, 0x02, 0)).unwrap();
+        cart.write(0x6000, 0x42);
+        let data = cart.export_save();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0], 0x42);
+
+        let mut other = Cartridge::from_bytes(&rom(1, 1, 0x02, 0)).unwrap();
+        other.import_save(&data).unwrap();
+        assert_eq!(other.read(0x6000), 0x42);
+        assert!(matches!(other.import_save(&[0; 16]),
+                         Err(CartridgeError::SaveSize { expected: 0x2000, actual: 16 })));
+
+        // Without a battery there is nothing to keep
+        let cart = Cartridge::from_bytes(&rom(1, 1, 0x00, 0)).unwrap();
+        assert!(cart.export_save().is_empty());
+    }
+
+    #[test]
+    pub fn test_save_file() {
+        let dir = std::env::temp_dir().join(format!("rustynes-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.nes");
+        let save_path = dir.join("game.sav");
+        fs::write(&rom_path, rom(1, 1, 0x02, 0)).unwrap();
+        let mut save = vec![0; 0x2000];
+        save[0] = 0x42;
+        fs::write(&save_path, &save).unwrap();
+
+        let mut cart = Cartridge::new();
+        cart.load(rom_path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(cart.save_path(), Some(save_path.as_path()));
+        assert_eq!(cart.read(0x6000), 0x42);
+
+        // Periodic flushes only write when the RAM changed
+        cart.set_autosave_interval(10);
+        fs::remove_file(&save_path).unwrap();
+        for _ in 0..10 {
+            cart.tick();
+        }
+        assert!(!save_path.exists());
+        cart.write(0x6001, 0x43);
+        for _ in 0..10 {
+            cart.tick();
+        }
+        assert_eq!(fs::read(&save_path).unwrap()[1], 0x43);
+
+        // Dropping the cartridge flushes the rest
+        cart.write(0x6002, 0x44);
+        drop(cart);
+        assert_eq!(fs::read(&save_path).unwrap()[2], 0x44);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+use super::mapper::{bus_conflict, has_bus_conflicts};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7, AxROM.
+///
+/// Switches a 32 KB PRG bank and selects which CIRAM page
+/// fills all four nametables.
+pub struct Axrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    bus_conflicts: bool,
+    /// Bits 0-2 select the PRG bank, bit 4 the nametable page
+    register: u8,
+}
+
+impl Axrom {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Axrom {
+            prg,
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            bus_conflicts: has_bus_conflicts(info, false),
+            register: 0,
+        })
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address < 0x8000 {
+            return 0;
+        }
+        let bank = (self.register & 0x07) as usize;
+        self.prg[(bank * PRG_BANK_SIZE + (address & 0x7FFF)) % self.prg.len()]
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address >= 0x8000 {
+            self.register = bus_conflict(self.bus_conflicts, self.cpu_peek(address), value);
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[address & 0x1FFF]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            self.chr[address & 0x1FFF] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Axrom;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::Mirroring;
+
+    #[test]
+    pub fn test_axrom() {
+        let prg: Vec<u8> = (0..0x40000).map(|i| (i / 0x8000) as u8).collect();
+        let mut axrom = Axrom::create(&test_info(7, 0x40000, 0, 0), prg, vec![0; 0x2000]);
+        assert_eq!(axrom.cpu_read(0xFFFF), 0);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+        axrom.cpu_write(0x8000, 0x15);
+        assert_eq!(axrom.cpu_read(0x8000), 5);
+        assert_eq!(axrom.cpu_read(0xFFFF), 5);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    pub fn test_axrom_bus_conflicts() {
+        // Bank 0 is filled with $13, the other banks hold their number
+        let prg: Vec<u8> = (0..0x40000).map(|i| if i < 0x8000 { 0x13 } else { (i / 0x8000) as u8 }).collect();
+        let mut info = test_info(7, 0x40000, 0, 0);
+        info.submapper = 2;
+        let mut axrom = Axrom::create(&info, prg, vec![0; 0x2000]);
+        axrom.cpu_write(0x8000, 0x15);
+        assert_eq!(axrom.cpu_read(0x8000), 1);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
@@ -0,0 +1,98 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+use super::mapper::{bus_conflict, has_bus_conflicts};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 3, CNROM.
+///
+/// PRG is fixed like NROM, writes to $8000-$FFFF select an 8 KB CHR bank.
+pub struct Cnrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Cnrom {
+            prg,
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            mirroring: info.mirroring,
+            bus_conflicts: has_bus_conflicts(info, true),
+            chr_bank: 0,
+        })
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        (self.chr_bank as usize * CHR_BANK_SIZE + (address & 0x1FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address < 0x8000 {
+            return 0;
+        }
+        self.prg[(address - 0x8000) % self.prg.len()]
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address >= 0x8000 {
+            self.chr_bank = bus_conflict(self.bus_conflicts, self.cpu_peek(address), value);
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cnrom;
+    use crate::cartridge::mapper::test_info;
+
+    #[test]
+    pub fn test_cnrom_chr_banks() {
+        let chr: Vec<u8> = (0..0x8000).map(|i| (i / 0x2000) as u8).collect();
+        let mut cnrom = Cnrom::create(&test_info(3, 0x4000, 0x8000, 0), vec![0xFF; 0x4000], chr);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 0);
+        cnrom.cpu_write(0x8000, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 3);
+        assert_eq!(cnrom.cpu_read(0xC000), 0xFF);
+        // CHR ROM is read only
+        cnrom.ppu_write(0x0000, 0x42);
+        assert_eq!(cnrom.ppu_read(0x0000), 3);
+    }
+
+    #[test]
+    pub fn test_cnrom_bus_conflicts() {
+        let chr: Vec<u8> = (0..0x8000).map(|i| (i / 0x2000) as u8).collect();
+        let mut prg = vec![0xFF; 0x4000];
+        prg[0x0123] = 0x01;
+        let mut cnrom = Cnrom::create(&test_info(3, 0x4000, 0x8000, 0), prg.clone(), chr.clone());
+        cnrom.cpu_write(0x8123, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 1);
+
+        let mut info = test_info(3, 0x4000, 0x8000, 0);
+        info.submapper = 1;
+        let mut cnrom = Cnrom::create(&info, prg, chr);
+        cnrom.cpu_write(0x8123, 3);
+        assert_eq!(cnrom.ppu_read(0x0000), 3);
+    }
+}
@@ -0,0 +1,483 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+/// The 5B runs its tone, noise and envelope generators at CPU clock / 16
+const AUDIO_PRESCALER: u8 = 16;
+/// Output level of a channel at full volume, about as loud as an APU pulse
+const AUDIO_SCALE: f32 = 0.113;
+
+/// Sunsoft 5B programmable sound generator, a YM2149F (AY-3-8910) variant
+/// with three square wave tones, one noise generator and one envelope.
+struct Psg {
+    register: u8,
+    tone_periods: [u16; 3],
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_period: u8,
+    noise_counter: u8,
+    /// 17 bit linear feedback shift register
+    noise_shift: u32,
+    /// Register 7, tone disable in bits 0-2 and noise disable in bits 3-5
+    mixer: u8,
+    /// Registers 8-10, 4 bit volume or envelope mode in bit 4
+    volumes: [u8; 3],
+
+    envelope_period: u16,
+    envelope_counter: u16,
+    envelope_shape: u8,
+    /// Position within the current 32 step ramp
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+
+    prescaler: u8,
+    /// 5 bit logarithmic volume to linear output, 1.5 dB per step
+    levels: [f32; 32],
+}
+
+impl Psg {
+    fn new() -> Psg {
+        let mut levels = [0.0; 32];
+        for (i, level) in levels.iter_mut().enumerate().skip(1) {
+            *level = 10f32.powf((i as f32 - 31.0) * 1.5 / 20.0);
+        }
+        Psg {
+            register: 0,
+            tone_periods: [0; 3],
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            mixer: 0,
+            volumes: [0; 3],
+            envelope_period: 0,
+            envelope_counter: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+            prescaler: AUDIO_PRESCALER,
+            levels,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.register {
+            0 | 2 | 4 => {
+                let channel = (self.register >> 1) as usize;
+                self.tone_periods[channel] = (self.tone_periods[channel] & 0x0F00) | value as u16;
+            }
+            1 | 3 | 5 => {
+                let channel = (self.register >> 1) as usize;
+                self.tone_periods[channel] =
+                    (self.tone_periods[channel] & 0x00FF) | ((value & 0x0F) as u16) << 8;
+            }
+            6 => self.noise_period = value & 0x1F,
+            7 => self.mixer = value,
+            8..=10 => self.volumes[(self.register - 8) as usize] = value & 0x1F,
+            11 => self.envelope_period = (self.envelope_period & 0xFF00) | value as u16,
+            12 => self.envelope_period = (self.envelope_period & 0x00FF) | (value as u16) << 8,
+            13 => {
+                self.envelope_shape = value & 0x0F;
+                self.envelope_step = 0;
+                self.envelope_counter = 0;
+                self.envelope_attack = value & 0x04 == 0x04;
+                self.envelope_holding = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        // End of a ramp, the shape decides what comes next
+        let shape = self.envelope_shape;
+        let cont = shape & 0x08 == 0x08;
+        let alternate = shape & 0x02 == 0x02;
+        let hold = shape & 0x01 == 0x01;
+        if !cont {
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if hold {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    /// Clock once per CPU cycle
+    fn tick(&mut self) {
+        self.prescaler -= 1;
+        if self.prescaler != 0 {
+            return;
+        }
+        self.prescaler = AUDIO_PRESCALER;
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_periods[channel] {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        // Noise runs at half the tone rate
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period.max(1) * 2 {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 0x01;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+
+        self.envelope_counter += 1;
+        if self.envelope_counter >= self.envelope_period {
+            self.envelope_counter = 0;
+            self.clock_envelope();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise_shift & 0x01 == 0x01;
+        let mut sum = 0.0;
+        for channel in 0..3 {
+            let tone_off = self.mixer & (0x01 << channel) != 0;
+            let noise_off = self.mixer & (0x08 << channel) != 0;
+            if (tone_off || self.tone_outputs[channel]) && (noise_off || noise) {
+                let volume = self.volumes[channel];
+                let level = if volume & 0x10 == 0x10 {
+                    self.envelope_level()
+                } else if volume == 0 {
+                    0
+                } else {
+                    volume << 1 | 0x01
+                };
+                sum += self.levels[level as usize];
+            }
+        }
+        sum
+    }
+}
+
+/// Mapper 69, Sunsoft FME-7 and the 5B, which adds a PSG to the FME-7.
+///
+/// Registers are written indirectly: the command number goes to $8000 and
+/// its parameter to $A000. The 5B sound registers use $C000 and $E000 the same way.
+pub struct Fme7 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    /// Command 8: bank at $6000, RAM select and RAM enable
+    prg_6000: u8,
+    prg_banks: [u8; 3],
+    mirroring: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_pending: bool,
+
+    psg: Psg,
+}
+
+impl Fme7 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Fme7 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_6000: 0,
+            prg_banks: [0, 0, 0],
+            mirroring: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_pending: false,
+            psg: Psg::new(),
+        })
+    }
+
+    fn prg_offset(&self, address: usize) -> usize {
+        let bank = match (address >> 13) & 0x03 {
+            // NES 2.0 allows less PRG than one bank, it is then mirrored
+            3 => (self.prg.len() / PRG_BANK_SIZE).max(1) - 1,
+            n => self.prg_banks[n] as usize,
+        };
+        (bank * PRG_BANK_SIZE + (address & 0x1FFF)) % self.prg.len()
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let bank = self.chr_banks[(address >> 10) & 0x07] as usize;
+        (bank * CHR_BANK_SIZE + (address & 0x03FF)) % self.chr.len()
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.prg_6000 & 0x40 == 0x40
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_selected() && self.prg_6000 & 0x80 == 0x80 && !self.prg_ram.is_empty()
+    }
+
+    fn ram_offset(&self, address: usize) -> usize {
+        let bank = (self.prg_6000 & 0x3F) as usize;
+        (bank * PRG_BANK_SIZE + (address & 0x1FFF)) % self.prg_ram.len()
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0..=7 => self.chr_banks[self.command as usize] = value,
+            8 => self.prg_6000 = value,
+            9..=11 => self.prg_banks[(self.command - 9) as usize] = value & 0x3F,
+            12 => self.mirroring = value & 0x03,
+            13 => {
+                self.irq_enabled = value & 0x01 == 0x01;
+                self.irq_counter_enabled = value & 0x80 == 0x80;
+                self.irq_pending = false;
+            }
+            14 => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address >= 0x8000 {
+            return self.prg[self.prg_offset(address)];
+        }
+        if address >= 0x6000 {
+            if !self.ram_selected() {
+                let bank = (self.prg_6000 & 0x3F) as usize;
+                return self.prg[(bank * PRG_BANK_SIZE + (address & 0x1FFF)) % self.prg.len()];
+            }
+            if self.ram_enabled() {
+                return self.prg_ram[self.ram_offset(address)];
+            }
+        }
+        0
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.ram_enabled() => {
+                let offset = self.ram_offset(address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.psg.register = value & 0x0F,
+            0xE000..=0xFFFF => self.psg.write(value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.psg.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.psg.output() * AUDIO_SCALE
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fme7;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::{Mapper, Mirroring};
+
+    fn create() -> Box<dyn Mapper> {
+        let prg: Vec<u8> = (0..0x40000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..0x40000).map(|i| (i / 0x400) as u8).collect();
+        Fme7::create(&test_info(69, 0x40000, 0x40000, 0x2000), prg, chr)
+    }
+
+    fn command(fme7: &mut Box<dyn Mapper>, command: u8, value: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xA000, value);
+    }
+
+    fn psg(fme7: &mut Box<dyn Mapper>, register: u8, value: u8) {
+        fme7.cpu_write(0xC000, register);
+        fme7.cpu_write(0xE000, value);
+    }
+
+    #[test]
+    pub fn test_banks() {
+        let mut fme7 = create();
+        command(&mut fme7, 9, 3);
+        command(&mut fme7, 10, 4);
+        command(&mut fme7, 11, 5);
+        command(&mut fme7, 7, 0x21);
+        assert_eq!(fme7.cpu_read(0x8000), 3);
+        assert_eq!(fme7.cpu_read(0xA000), 4);
+        assert_eq!(fme7.cpu_read(0xC000), 5);
+        assert_eq!(fme7.cpu_read(0xE000), 31);
+        assert_eq!(fme7.ppu_read(0x1C00), 0x21);
+
+        command(&mut fme7, 12, 1);
+        assert_eq!(fme7.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    pub fn test_prg_6000() {
+        let mut fme7 = create();
+        command(&mut fme7, 8, 0x06);
+        assert_eq!(fme7.cpu_read(0x6000), 6);
+
+        // RAM selected but disabled
+        command(&mut fme7, 8, 0x40);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 0);
+
+        command(&mut fme7, 8, 0xC0);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    pub fn test_small_prg() {
+        let prg: Vec<u8> = (0..0x1000).map(|i| (i >> 8) as u8).collect();
+        let mut fme7 = Fme7::create(&test_info(69, 0x1000, 0x2000, 0), prg, vec![0; 0x2000]);
+        assert_eq!(fme7.cpu_read(0xE100), 0x01);
+        assert_eq!(fme7.cpu_read(0xF100), 0x01);
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut fme7 = create();
+        command(&mut fme7, 14, 0x01);
+        command(&mut fme7, 15, 0x00);
+        command(&mut fme7, 13, 0x81);
+        fme7.cpu_tick();
+        assert!(!fme7.irq());
+        fme7.cpu_tick();
+        assert!(fme7.irq());
+        command(&mut fme7, 13, 0x81);
+        assert!(!fme7.irq());
+
+        // The counter keeps running with IRQs disabled
+        command(&mut fme7, 14, 0x01);
+        command(&mut fme7, 13, 0x80);
+        fme7.cpu_tick();
+        fme7.cpu_tick();
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    pub fn test_psg_tone() {
+        let mut fme7 = create();
+        assert_eq!(fme7.audio_output(), 0.0);
+
+        // Tone A only, period 1 toggles every prescaler clock
+        psg(&mut fme7, 0, 1);
+        psg(&mut fme7, 7, 0x3E);
+        psg(&mut fme7, 8, 0x0F);
+        let mut levels = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..16 {
+                fme7.cpu_tick();
+            }
+            levels.push(fme7.audio_output());
+        }
+        assert!(levels[0] > 0.1);
+        assert_eq!(levels[1], 0.0);
+        assert_eq!(levels[0], levels[2]);
+
+        // Each volume step is 3 dB
+        psg(&mut fme7, 7, 0x3F);
+        let full = fme7.audio_output();
+        psg(&mut fme7, 8, 0x0E);
+        let ratio = full / fme7.audio_output();
+        assert!((ratio - 1.413).abs() < 0.01);
+    }
+
+    #[test]
+    pub fn test_psg_envelope() {
+        let mut fme7 = create();
+        // Channel A on constant output, envelope attack then hold at the top
+        psg(&mut fme7, 7, 0x3F);
+        psg(&mut fme7, 8, 0x10);
+        psg(&mut fme7, 11, 1);
+        psg(&mut fme7, 13, 0x0D);
+        let start = fme7.audio_output();
+        assert_eq!(start, 0.0);
+        for _ in 0..32 * 16 {
+            fme7.cpu_tick();
+        }
+        let top = fme7.audio_output();
+        assert!((top - 0.113).abs() < 0.001);
+        for _ in 0..64 * 16 {
+            fme7.cpu_tick();
+        }
+        assert_eq!(fme7.audio_output(), top);
+    }
+}
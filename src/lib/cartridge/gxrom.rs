@@ -0,0 +1,96 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+use super::mapper::{bus_conflict, has_bus_conflicts};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 66, GxROM and MHROM.
+///
+/// One register selects a 32 KB PRG bank with bits 4-5
+/// and an 8 KB CHR bank with bits 0-1.
+pub struct Gxrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    register: u8,
+}
+
+impl Gxrom {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Gxrom {
+            prg,
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            mirroring: info.mirroring,
+            bus_conflicts: has_bus_conflicts(info, true),
+            register: 0,
+        })
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let bank = (self.register & 0x03) as usize;
+        (bank * CHR_BANK_SIZE + (address & 0x1FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address < 0x8000 {
+            return 0;
+        }
+        let bank = ((self.register >> 4) & 0x03) as usize;
+        self.prg[(bank * PRG_BANK_SIZE + (address & 0x7FFF)) % self.prg.len()]
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address >= 0x8000 {
+            self.register = bus_conflict(self.bus_conflicts, self.cpu_peek(address), value);
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Gxrom;
+    use crate::cartridge::mapper::test_info;
+
+    #[test]
+    pub fn test_gxrom() {
+        // Bank 0 is all ones, so the write goes through the bus conflict unchanged
+        let prg: Vec<u8> = (0..0x20000).map(|i| if i < 0x8000 { 0xFF } else { (i / 0x8000) as u8 }).collect();
+        let chr: Vec<u8> = (0..0x8000).map(|i| (i / 0x2000) as u8).collect();
+        let mut gxrom = Gxrom::create(&test_info(66, 0x20000, 0x8000, 0), prg, chr);
+        gxrom.cpu_write(0x8000, 0x21);
+        assert_eq!(gxrom.cpu_read(0x8000), 2);
+        assert_eq!(gxrom.cpu_read(0xFFFF), 2);
+        assert_eq!(gxrom.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    pub fn test_gxrom_bus_conflicts() {
+        // Bank 0 is filled with $31, the other banks hold their number
+        let prg: Vec<u8> = (0..0x20000).map(|i| if i < 0x8000 { 0x31 } else { (i / 0x8000) as u8 }).collect();
+        let chr: Vec<u8> = (0..0x8000).map(|i| (i / 0x2000) as u8).collect();
+        let mut gxrom = Gxrom::create(&test_info(66, 0x20000, 0x8000, 0), prg, chr);
+        gxrom.cpu_write(0x8000, 0x23);
+        assert_eq!(gxrom.cpu_read(0x8000), 2);
+        assert_eq!(gxrom.ppu_read(0x0000), 1);
+    }
+}
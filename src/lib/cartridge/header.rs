@@ -0,0 +1,269 @@
+use super::{CartridgeError, Mirroring};
+
+/// Size of the iNES header
+pub const HEADER_SIZE: usize = 16;
+/// Size of the optional trainer placed between header and PRG ROM
+pub const TRAINER_SIZE: usize = 512;
+/// PRG ROM bank size in the header
+const PRG_BANK_SIZE: usize = 0x4000;
+/// CHR ROM bank size in the header
+const CHR_BANK_SIZE: usize = 0x2000;
+/// PRG RAM bank size in an iNES 1.0 header
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+
+/// Header flavour of the ROM image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+}
+
+/// Console the ROM targets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    /// Extended console type from byte 13 of a NES 2.0 header
+    Extended(u8),
+}
+
+/// CPU/PPU timing the ROM expects
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// Everything the ROM header tells about the cartridge board.
+/// All sizes are in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeInfo {
+    pub format: HeaderFormat,
+    /// Mapper number, 8 bits for iNES and 12 bits for NES 2.0
+    pub mapper: u16,
+    /// Submapper number, always 0 for iNES
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    /// Volatile PRG RAM at $6000-$7FFF
+    pub prg_ram_size: usize,
+    /// Battery backed PRG RAM
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub console: ConsoleType,
+    pub timing: Timing,
+}
+
+impl CartridgeInfo {
+    /// Parse the 16 byte header at the start of a ROM image.
+    pub fn parse(data: &[u8]) -> Result<CartridgeInfo, CartridgeError> {
+        if data.len() < 4 || &data[0..4] != b"NES\x1A" {
+            return Err(CartridgeError::InvalidMagic);
+        }
+        if data.len() < HEADER_SIZE {
+            return Err(CartridgeError::Truncated { expected: HEADER_SIZE, actual: data.len() });
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+
+        let mirroring = if flags6 & 0x08 == 0x08 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 == 0x01 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0x02 == 0x02;
+        let trainer = flags6 & 0x04 == 0x04;
+
+        let info = if flags7 & 0x0C == 0x08 {
+            CartridgeInfo::parse_nes20(data, mirroring, battery, trainer)?
+        } else {
+            CartridgeInfo::parse_ines(data, mirroring, battery, trainer)
+        };
+
+        if info.prg_rom_size == 0 {
+            return Err(CartridgeError::NoPrgRom);
+        }
+        Ok(info)
+    }
+
+    fn parse_ines(data: &[u8], mirroring: Mirroring, battery: bool, trainer: bool) -> CartridgeInfo {
+        let flags6 = data[6];
+        // Old dumps tools wrote garbage like "DiskDude!" to bytes 7-15,
+        // in which case the upper mapper nibble can't be trusted.
+        let flags7 = if data[12..16].iter().any(|&b| b != 0) { 0 } else { data[7] };
+
+        let console = match flags7 & 0x03 {
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Nes,
+        };
+
+        let prg_ram = (data[8].max(1)) as usize * PRG_RAM_BANK_SIZE;
+        let (prg_ram_size, prg_nvram_size) = if battery { (0, prg_ram) } else { (prg_ram, 0) };
+        let chr_rom_size = data[5] as usize * CHR_BANK_SIZE;
+
+        CartridgeInfo {
+            format: HeaderFormat::INes,
+            mapper: ((flags7 & 0xF0) | (flags6 >> 4)) as u16,
+            submapper: 0,
+            prg_rom_size: data[4] as usize * PRG_BANK_SIZE,
+            chr_rom_size,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size: if chr_rom_size == 0 { CHR_BANK_SIZE } else { 0 },
+            chr_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer,
+            console,
+            timing: if data[9] & 0x01 == 0x01 { Timing::Pal } else { Timing::Ntsc },
+        }
+    }
+
+    fn parse_nes20(data: &[u8], mirroring: Mirroring, battery: bool, trainer: bool)
+        -> Result<CartridgeInfo, CartridgeError> {
+        let flags6 = data[6];
+        let flags7 = data[7];
+
+        let console = match flags7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(data[13] & 0x0F),
+        };
+        let timing = match data[12] & 0x03 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        };
+
+        Ok(CartridgeInfo {
+            format: HeaderFormat::Nes20,
+            mapper: ((data[8] & 0x0F) as u16) << 8 | (flags7 & 0xF0) as u16 | (flags6 >> 4) as u16,
+            submapper: data[8] >> 4,
+            prg_rom_size: rom_size(data[4], data[9] & 0x0F, PRG_BANK_SIZE)?,
+            chr_rom_size: rom_size(data[5], data[9] >> 4, CHR_BANK_SIZE)?,
+            prg_ram_size: shift_size(data[10] & 0x0F),
+            prg_nvram_size: shift_size(data[10] >> 4),
+            chr_ram_size: shift_size(data[11] & 0x0F),
+            chr_nvram_size: shift_size(data[11] >> 4),
+            mirroring,
+            battery,
+            trainer,
+            console,
+            timing,
+        })
+    }
+
+    /// Size of the whole image the header describes, header included.
+    /// Exponent-multiplier sizes can add up to more than fits in memory.
+    pub fn file_size(&self) -> Result<usize, CartridgeError> {
+        let trainer = if self.trainer { TRAINER_SIZE } else { 0 };
+        (HEADER_SIZE + trainer).checked_add(self.prg_rom_size)
+            .and_then(|size| size.checked_add(self.chr_rom_size))
+            .ok_or(CartridgeError::InvalidSize)
+    }
+}
+
+/// NES 2.0 ROM size from the LSB byte and MSB nibble.
+/// An MSB nibble of $F switches to exponent-multiplier notation.
+fn rom_size(lsb: u8, msb: u8, bank_size: usize) -> Result<usize, CartridgeError> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        return 1usize.checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or(CartridgeError::InvalidSize);
+    }
+    Ok((((msb as usize) << 8) | lsb as usize) * bank_size)
+}
+
+/// NES 2.0 RAM sizes are stored as shift counts, 64 << shift, 0 meaning none.
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CartridgeInfo, ConsoleType, HeaderFormat, Timing};
+    use crate::cartridge::{CartridgeError, Mirroring};
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut data = b"NES\x1A".to_vec();
+        data.extend_from_slice(&bytes);
+        data
+    }
+
+    #[test]
+    pub fn test_ines() {
+        let info = CartridgeInfo::parse(&header([2, 0, 0x12, 0x10, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(info.format, HeaderFormat::INes);
+        assert_eq!(info.mapper, 0x11);
+        assert_eq!(info.prg_rom_size, 0x8000);
+        assert_eq!(info.chr_rom_size, 0);
+        assert_eq!(info.chr_ram_size, 0x2000);
+        assert_eq!(info.prg_nvram_size, 0x2000);
+        assert_eq!(info.prg_ram_size, 0);
+        assert_eq!(info.mirroring, Mirroring::Horizontal);
+        assert_eq!(info.timing, Timing::Pal);
+    }
+
+    #[test]
+    pub fn test_ines_garbage_tail() {
+        let mut data = header([1, 1, 0x10, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data[12..16].copy_from_slice(b"Dude");
+        let info = CartridgeInfo::parse(&data).unwrap();
+        assert_eq!(info.mapper, 1);
+    }
+
+    #[test]
+    pub fn test_nes20() {
+        let info = CartridgeInfo::parse(&header([
+            0x02, 0x01, 0x41, 0x5B, 0x31, 0x10, 0x07, 0x90, 0x03, 0x0C, 0x00, 0x00,
+        ])).unwrap();
+        assert_eq!(info.format, HeaderFormat::Nes20);
+        assert_eq!(info.mapper, 0x154);
+        assert_eq!(info.submapper, 3);
+        assert_eq!(info.prg_rom_size, 0x8000);
+        assert_eq!(info.chr_rom_size, 0x101 * 0x2000);
+        assert_eq!(info.prg_ram_size, 64 << 7);
+        assert_eq!(info.prg_nvram_size, 0);
+        assert_eq!(info.chr_ram_size, 0);
+        assert_eq!(info.chr_nvram_size, 64 << 9);
+        assert_eq!(info.mirroring, Mirroring::Vertical);
+        assert_eq!(info.timing, Timing::Dendy);
+        assert_eq!(info.console, ConsoleType::Extended(0x0C));
+    }
+
+    #[test]
+    pub fn test_nes20_exponent_size() {
+        // 2^10 * 3
+        let info = CartridgeInfo::parse(&header([
+            0x29, 0x00, 0x00, 0x08, 0x00, 0x0F, 0, 0, 0, 0, 0, 0,
+        ])).unwrap();
+        assert_eq!(info.prg_rom_size, 3 << 10);
+        assert_eq!(info.file_size().unwrap(), 16 + (3 << 10));
+
+        // 2^63 of PRG and as much CHR overflow once added up
+        let info = CartridgeInfo::parse(&header([
+            0xFC, 0xFC, 0x00, 0x08, 0x00, 0xFF, 0, 0, 0, 0, 0, 0,
+        ])).unwrap();
+        assert_eq!(info.prg_rom_size, 1 << 63);
+        assert!(matches!(info.file_size(), Err(CartridgeError::InvalidSize)));
+    }
+}
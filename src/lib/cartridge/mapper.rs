@@ -0,0 +1,172 @@
+use super::{CartridgeError, CartridgeInfo, Mirroring};
+use super::nrom::Nrom;
+use super::mmc1::Mmc1;
+use super::uxrom::Uxrom;
+use super::cnrom::Cnrom;
+use super::axrom::Axrom;
+use super::gxrom::Gxrom;
+use super::mmc3::Mmc3;
+use super::mmc5::Mmc5;
+use super::vrc4::Vrc4;
+use super::vrc6::Vrc6;
+use super::n163::N163;
+use super::fme7::Fme7;
+
+/// Board logic of a cartridge.
+///
+/// CPU addresses are full addresses in $4020-$FFFF and PPU addresses are
+/// full addresses in $0000-$1FFF, or $2000-$2FFF for nametables.
+pub trait Mapper {
+    /// CPU read from cartridge space, with the side effects some registers have
+    fn cpu_read(&mut self, address: usize) -> u8 {
+        self.cpu_peek(address)
+    }
+
+    /// What a CPU read would return, without its side effects
+    fn cpu_peek(&self, address: usize) -> u8;
+
+    /// CPU write to cartridge space, usually a mapper register
+    fn cpu_write(&mut self, address: usize, value: u8);
+
+    /// PPU pattern table read
+    fn ppu_read(&mut self, address: usize) -> u8;
+
+    /// PPU pattern table write, ignored unless the board has CHR RAM
+    fn ppu_write(&mut self, address: usize, value: u8);
+
+    /// Current nametable mirroring
+    fn mirroring(&self) -> Mirroring;
+
+    /// CIRAM page (0-1, or 0-3 with four screen VRAM) backing a nametable address
+    fn ciram_page(&self, address: usize) -> usize {
+        let quadrant = (address >> 10) & 0x03;
+        match self.mirroring() {
+            Mirroring::Horizontal => quadrant >> 1,
+            Mirroring::Vertical => quadrant & 0x01,
+            Mirroring::FourScreen => quadrant,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        }
+    }
+
+    /// PPU nametable read. The PPU reads CIRAM when this returns None,
+    /// mappers with their own nametable memory return the value instead.
+    fn nametable_read(&mut self, _address: usize) -> Option<u8> {
+        None
+    }
+
+    /// PPU nametable write. Returns false to let the PPU write CIRAM.
+    fn nametable_write(&mut self, _address: usize, _value: u8) -> bool {
+        false
+    }
+
+    /// CPU write to a PPU register, for mappers snooping on $2000-$2007
+    fn ppu_register_write(&mut self, _address: usize, _value: u8) {
+    }
+
+    /// True while the mapper pulls the CPU IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called once per CPU cycle
+    fn cpu_tick(&mut self) {
+    }
+
+    /// Called once per rendered scanline
+    fn scanline(&mut self) {
+    }
+
+    /// Current level of the expansion audio channels, mixed after the APU
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Memory a battery keeps between sessions on boards that have one
+    fn save_ram(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore memory with data of the same length save_ram returns
+    fn load_save_ram(&mut self, _data: &[u8]) {
+    }
+}
+
+/// Value seen by a discrete logic board when the CPU writes over ROM.
+/// On boards with bus conflicts the ROM drives the data bus at the same
+/// time as the CPU, so only bits that are 0 in either side survive.
+pub fn bus_conflict(enabled: bool, rom_value: u8, value: u8) -> u8 {
+    if enabled {
+        rom_value & value
+    } else {
+        value
+    }
+}
+
+/// Whether a discrete logic board has bus conflicts. NES 2.0 submapper 1
+/// marks boards without them and 2 boards with them; iNES images and
+/// submapper 0 get the usual behaviour of the board.
+pub fn has_bus_conflicts(info: &CartridgeInfo, board_default: bool) -> bool {
+    match info.submapper {
+        1 => false,
+        2 => true,
+        _ => board_default,
+    }
+}
+
+/// Creates a mapper from the header and the PRG and CHR data.
+/// CHR data is RAM when the header has no CHR ROM.
+type Constructor = fn(&CartridgeInfo, Vec<u8>, Vec<u8>) -> Box<dyn Mapper>;
+
+/// Supported boards by mapper number
+const MAPPERS: &[(u16, Constructor)] = &[
+    (0, Nrom::create),
+    (1, Mmc1::create),
+    (2, Uxrom::create),
+    (3, Cnrom::create),
+    (4, Mmc3::create),
+    (5, Mmc5::create),
+    (7, Axrom::create),
+    (19, N163::create),
+    (21, Vrc4::create),
+    (22, Vrc4::create),
+    (23, Vrc4::create),
+    (24, Vrc6::create),
+    (25, Vrc4::create),
+    (26, Vrc6::create),
+    (66, Gxrom::create),
+    (69, Fme7::create),
+];
+
+/// Create the mapper the header asks for.
+pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>)
+    -> Result<Box<dyn Mapper>, CartridgeError> {
+    match MAPPERS.iter().find(|(n, _)| *n == info.mapper) {
+        Some((_, constructor)) => Ok(constructor(info, prg, chr)),
+        None => Err(CartridgeError::UnsupportedMapper(info.mapper)),
+    }
+}
+
+/// Header for a plain iNES board, used by the mapper tests
+#[cfg(test)]
+pub fn test_info(mapper: u16, prg_rom_size: usize, chr_rom_size: usize, prg_ram_size: usize)
+    -> CartridgeInfo {
+    use super::{ConsoleType, HeaderFormat, Timing};
+
+    CartridgeInfo {
+        format: HeaderFormat::INes,
+        mapper,
+        submapper: 0,
+        prg_rom_size,
+        chr_rom_size,
+        prg_ram_size,
+        prg_nvram_size: 0,
+        chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+        chr_nvram_size: 0,
+        mirroring: Mirroring::Vertical,
+        battery: false,
+        trainer: false,
+        console: ConsoleType::Nes,
+        timing: Timing::Ntsc,
+    }
+}
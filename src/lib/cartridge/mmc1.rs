@@ -0,0 +1,331 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 1, Nintendo MMC1 (SxROM boards).
+///
+/// Registers are loaded one bit at a time through a 5 bit shift register.
+/// SUROM/SXROM use bit 4 of the CHR registers to select a 256 KB PRG half,
+/// SOROM/SXROM use bits 2-3 to select an 8 KB PRG RAM bank.
+pub struct Mmc1 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    /// Serial shift register, filled from bit 4 down
+    shift: u8,
+    /// Number of bits written to the shift register
+    shift_count: u8,
+    /// Control register: mirroring, PRG and CHR bank modes
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    /// CHR register used for the upper PRG/RAM bank bits, selected by PPU A12
+    high_chr_bank: u8,
+
+    /// CPU cycle counter for ignoring writes on consecutive cycles
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Mmc1 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            high_chr_bank: 0,
+            cycle: 0,
+            last_write: None,
+        })
+    }
+
+    /// Load a completed shift register value into the register picked by A13-A14
+    fn write_register(&mut self, address: usize, value: u8) {
+        match address & 0x6000 {
+            0x0000 => self.control = value,
+            0x2000 => self.chr_bank0 = value,
+            0x4000 => self.chr_bank1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    /// 256 KB outer PRG bank on 512 KB boards
+    fn prg_outer_bank(&self) -> usize {
+        if self.prg.len() > 0x40000 {
+            (self.upper_chr_value() as usize & 0x10) >> 4
+        } else {
+            0
+        }
+    }
+
+    /// CHR register value whose upper bits drive the PRG and RAM bank lines.
+    /// In 8 KB CHR mode this is always CHR bank 0.
+    fn upper_chr_value(&self) -> u8 {
+        if self.control & 0x10 == 0 {
+            self.chr_bank0
+        } else {
+            self.high_chr_bank
+        }
+    }
+
+    fn prg_offset(&self, address: usize) -> usize {
+        let outer = self.prg_outer_bank() << 4;
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let bank = match (self.control >> 2) & 0x03 {
+            0 | 1 => outer | (bank & 0x0E) | ((address >> 14) & 0x01),
+            2 => if address < 0xC000 { outer } else { outer | bank },
+            _ => if address < 0xC000 { outer | bank } else { outer | 0x0F },
+        };
+        (bank * PRG_BANK_SIZE + (address & 0x3FFF)) % self.prg.len()
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0 && !self.prg_ram.is_empty()
+    }
+
+    fn prg_ram_offset(&self, address: usize) -> usize {
+        let banks = self.prg_ram.len() / PRG_RAM_BANK_SIZE;
+        let bank = match banks {
+            0 | 1 => 0,
+            2 => ((self.upper_chr_value() >> 3) & 0x01) as usize,
+            _ => ((self.upper_chr_value() >> 2) & 0x03) as usize,
+        };
+        (bank * PRG_RAM_BANK_SIZE + (address & 0x1FFF)) % self.prg_ram.len()
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let bank = if self.control & 0x10 == 0 {
+            ((self.chr_bank0 & 0x1E) as usize) | ((address >> 12) & 0x01)
+        } else if address < 0x1000 {
+            self.chr_bank0 as usize
+        } else {
+            self.chr_bank1 as usize
+        };
+        (bank * CHR_BANK_SIZE + (address & 0x0FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address >= 0x8000 {
+            return self.prg[self.prg_offset(address)];
+        }
+        if address >= 0x6000 && self.prg_ram_enabled() {
+            return self.prg_ram[self.prg_ram_offset(address)];
+        }
+        0
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        if address < 0x8000 {
+            if self.prg_ram_enabled() {
+                let offset = self.prg_ram_offset(address);
+                self.prg_ram[offset] = value;
+            }
+            return;
+        }
+
+        // The MMC1 ignores the second write of read-modify-write instructions.
+        // A CPU running instructions at once makes both writes in the same cycle.
+        let consecutive = self.last_write == Some(self.cycle.wrapping_sub(1))
+            || self.last_write == Some(self.cycle);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+
+        if value & 0x80 == 0x80 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+
+        self.shift = (self.shift >> 1) | ((value & 0x01) << 4);
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+            self.write_register(address, self.shift);
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.high_chr_bank = if address & 0x1000 == 0 { self.chr_bank0 } else { self.chr_bank1 };
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc1;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::{Mapper, Mirroring};
+
+    /// PRG where every byte holds its 16 KB bank number
+    fn prg(banks: usize) -> Vec<u8> {
+        (0..banks * 0x4000).map(|i| (i / 0x4000) as u8).collect()
+    }
+
+    /// Serially write a 5 bit value, one CPU cycle apart
+    fn write(mapper: &mut Box<dyn Mapper>, address: usize, value: u8) {
+        for i in 0..5 {
+            mapper.cpu_write(address, (value >> i) & 0x01);
+            mapper.cpu_tick();
+            mapper.cpu_tick();
+        }
+    }
+
+    #[test]
+    pub fn test_prg_modes() {
+        let mut mmc1 = Mmc1::create(&test_info(1, 0x40000, 0x2000, 0x2000), prg(16), vec![0; 0x2000]);
+        // Power on state fixes the last bank at $C000
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+
+        write(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+
+        // Fix first bank, switch $C000
+        write(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 5);
+
+        // 32 KB mode ignores the low bit
+        write(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x8000), 4);
+        assert_eq!(mmc1.cpu_read(0xC000), 5);
+    }
+
+    #[test]
+    pub fn test_reset_and_mirroring() {
+        let mut mmc1 = Mmc1::create(&test_info(1, 0x20000, 0x2000, 0x2000), prg(8), vec![0; 0x2000]);
+        write(&mut mmc1, 0x8000, 0x02);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+        write(&mut mmc1, 0x8000, 0x01);
+        assert_eq!(mmc1.mirroring(), Mirroring::SingleScreenUpper);
+
+        // A half written value is dropped by a reset write
+        mmc1.cpu_write(0x8000, 0x01);
+        mmc1.cpu_tick();
+        mmc1.cpu_tick();
+        mmc1.cpu_write(0x8000, 0x80);
+        mmc1.cpu_tick();
+        mmc1.cpu_tick();
+        // Reset also switches back to fixing the last bank at $C000
+        assert_eq!(mmc1.cpu_read(0xC000), 7);
+        write(&mut mmc1, 0x8000, 0x03);
+        assert_eq!(mmc1.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    pub fn test_consecutive_writes_ignored() {
+        let mut mmc1 = Mmc1::create(&test_info(1, 0x20000, 0x2000, 0x2000), prg(8), vec![0; 0x2000]);
+        // Both writes of a read-modify-write land on back to back cycles
+        for _ in 0..5 {
+            mmc1.cpu_write(0xE000, 0x01);
+            mmc1.cpu_tick();
+            mmc1.cpu_write(0xE000, 0x00);
+            mmc1.cpu_tick();
+            mmc1.cpu_tick();
+        }
+        assert_eq!(mmc1.cpu_read(0x8000), 7);
+
+        // Both writes in the same cycle, as made by the instant CPU mode
+        for i in 0..5 {
+            let bit = (0x03 >> i) & 0x01;
+            mmc1.cpu_write(0xE000, bit);
+            mmc1.cpu_write(0xE000, bit ^ 0x01);
+            mmc1.cpu_tick();
+            mmc1.cpu_tick();
+        }
+        assert_eq!(mmc1.cpu_read(0x8000), 3);
+    }
+
+    #[test]
+    pub fn test_chr_banks() {
+        let chr: Vec<u8> = (0..0x20000).map(|i| (i / 0x1000) as u8).collect();
+        let mut mmc1 = Mmc1::create(&test_info(1, 0x20000, 0x20000, 0x2000), prg(8), chr);
+        write(&mut mmc1, 0xA000, 3);
+        assert_eq!(mmc1.ppu_read(0x0000), 2);
+        assert_eq!(mmc1.ppu_read(0x1000), 3);
+
+        write(&mut mmc1, 0x8000, 0x1C);
+        write(&mut mmc1, 0xC000, 9);
+        assert_eq!(mmc1.ppu_read(0x0000), 3);
+        assert_eq!(mmc1.ppu_read(0x1000), 9);
+    }
+
+    #[test]
+    pub fn test_surom_and_prg_ram() {
+        let mut mmc1 = Mmc1::create(&test_info(1, 0x80000, 0, 0x2000), prg(32), vec![0; 0x2000]);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+        write(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x8000), 16);
+        assert_eq!(mmc1.cpu_read(0xC000), 31);
+
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+        write(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        write(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    pub fn test_sxrom_ram_banks() {
+        let mut mmc1 = Mmc1::create(&test_info(1, 0x20000, 0, 0x8000), prg(8), vec![0; 0x2000]);
+        for bank in 0..4u8 {
+            write(&mut mmc1, 0xA000, bank << 2);
+            mmc1.cpu_write(0x6000, bank + 1);
+        }
+        for bank in 0..4u8 {
+            write(&mut mmc1, 0xA000, bank << 2);
+            assert_eq!(mmc1.cpu_read(0x6000), bank + 1);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+/// CPU cycles A12 has to stay low before a rising edge clocks the counter
+const A12_FILTER_CYCLES: u64 = 3;
+
+/// Mapper 4, Nintendo MMC3 (TxROM boards).
+///
+/// The scanline counter is clocked by rising edges of PPU A12, which happen
+/// once per scanline when backgrounds use $0000 and sprites use $1000.
+/// MMC3A (submapper 4) only fires when the counter is decremented to zero or
+/// reloaded through $C001, MMC3B/C fire whenever the counter is zero after a clock.
+pub struct Mmc3 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    four_screen: bool,
+    old_irq_behaviour: bool,
+
+    /// Bank select: target register, PRG mode and CHR inversion
+    bank_select: u8,
+    /// R0-R7 bank registers
+    banks: [u8; 8],
+    horizontal: bool,
+    /// PRG RAM enable and write protect
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    /// Last value of PPU A12
+    a12: bool,
+    /// CPU cycle when A12 last went low
+    a12_low_cycle: u64,
+    cycle: u64,
+}
+
+impl Mmc3 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Mmc3 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            four_screen: info.mirroring == Mirroring::FourScreen,
+            old_irq_behaviour: info.submapper == 4,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal: info.mirroring == Mirroring::Horizontal,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_cycle: 0,
+            cycle: 0,
+        })
+    }
+
+    fn prg_offset(&self, address: usize) -> usize {
+        // With a single bank of PRG both fixed banks mirror it
+        let last = (self.prg.len() / PRG_BANK_SIZE).max(2) - 1;
+        let swap = self.bank_select & 0x40 == 0x40;
+        let bank = match (address >> 13) & 0x03 {
+            0 => if swap { last - 1 } else { self.banks[6] as usize },
+            1 => self.banks[7] as usize,
+            2 => if swap { self.banks[6] as usize } else { last - 1 },
+            _ => last,
+        };
+        (bank * PRG_BANK_SIZE + (address & 0x1FFF)) % self.prg.len()
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let mut slot = (address >> 10) & 0x07;
+        if self.bank_select & 0x80 == 0x80 {
+            slot ^= 0x04;
+        }
+        let bank = match slot {
+            0 => self.banks[0] & 0xFE,
+            1 => self.banks[0] | 0x01,
+            2 => self.banks[1] & 0xFE,
+            3 => self.banks[1] | 0x01,
+            n => self.banks[n - 2],
+        } as usize;
+        (bank * CHR_BANK_SIZE + (address & 0x03FF)) % self.chr.len()
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        self.prg_ram_protect & 0x80 == 0x80 && !self.prg_ram.is_empty()
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_readable() && self.prg_ram_protect & 0x40 == 0
+    }
+
+    /// Clock the scanline counter
+    fn clock_counter(&mut self) {
+        let before = self.irq_counter;
+        let reloaded = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fire = if self.old_irq_behaviour {
+            self.irq_counter == 0 && (before != 0 || reloaded)
+        } else {
+            self.irq_counter == 0
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    /// Watch PPU A12 for filtered rising edges
+    fn watch_a12(&mut self, address: usize) {
+        let a12 = address & 0x1000 == 0x1000;
+        if a12 && !self.a12 && self.cycle - self.a12_low_cycle >= A12_FILTER_CYCLES {
+            self.clock_counter();
+        }
+        if !a12 && self.a12 {
+            self.a12_low_cycle = self.cycle;
+        }
+        self.a12 = a12;
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address >= 0x8000 {
+            return self.prg[self.prg_offset(address)];
+        }
+        if address >= 0x6000 && self.prg_ram_readable() {
+            return self.prg_ram[(address - 0x6000) % self.prg_ram.len()];
+        }
+        0
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        if address < 0x8000 {
+            if self.prg_ram_writable() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        let even = address & 0x01 == 0;
+        match (address & 0xE000, even) {
+            (0x8000, true) => self.bank_select = value,
+            (0x8000, false) => self.banks[(self.bank_select & 0x07) as usize] = value,
+            (0xA000, true) => self.horizontal = value & 0x01 == 0x01,
+            (0xA000, false) => self.prg_ram_protect = value,
+            (0xC000, true) => self.irq_latch = value,
+            (0xC000, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.watch_a12(address);
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        self.watch_a12(address);
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc3;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::{Mapper, Mirroring};
+
+    fn create(submapper: u8) -> Box<dyn Mapper> {
+        let prg: Vec<u8> = (0..0x20000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..0x20000).map(|i| (i / 0x400) as u8).collect();
+        let mut info = test_info(4, 0x20000, 0x20000, 0x2000);
+        info.submapper = submapper;
+        Mmc3::create(&info, prg, chr)
+    }
+
+    /// Produce one A12 rise like a rendered scanline does
+    fn scanline(mmc3: &mut Box<dyn Mapper>) {
+        mmc3.ppu_read(0x0000);
+        for _ in 0..10 {
+            mmc3.cpu_tick();
+        }
+        mmc3.ppu_read(0x1000);
+    }
+
+    #[test]
+    pub fn test_prg_banks() {
+        let mut mmc3 = create(0);
+        mmc3.cpu_write(0x8000, 0x06);
+        mmc3.cpu_write(0x8001, 3);
+        mmc3.cpu_write(0x8000, 0x07);
+        mmc3.cpu_write(0x8001, 4);
+        assert_eq!(mmc3.cpu_read(0x8000), 3);
+        assert_eq!(mmc3.cpu_read(0xA000), 4);
+        assert_eq!(mmc3.cpu_read(0xC000), 14);
+        assert_eq!(mmc3.cpu_read(0xE000), 15);
+
+        mmc3.cpu_write(0x8000, 0x40);
+        assert_eq!(mmc3.cpu_read(0x8000), 14);
+        assert_eq!(mmc3.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    pub fn test_chr_banks() {
+        let mut mmc3 = create(0);
+        for (register, bank) in [(0, 8), (1, 11), (2, 20), (3, 21), (4, 22), (5, 23)] {
+            mmc3.cpu_write(0x8000, register);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        assert_eq!(mmc3.ppu_read(0x0000), 8);
+        assert_eq!(mmc3.ppu_read(0x0400), 9);
+        assert_eq!(mmc3.ppu_read(0x0800), 10);
+        assert_eq!(mmc3.ppu_read(0x0C00), 11);
+        assert_eq!(mmc3.ppu_read(0x1C00), 23);
+
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc3.ppu_read(0x0000), 20);
+        assert_eq!(mmc3.ppu_read(0x1400), 9);
+    }
+
+    #[test]
+    pub fn test_mirroring_and_prg_ram() {
+        let mut mmc3 = create(0);
+        mmc3.cpu_write(0xA000, 0x01);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.cpu_write(0xA000, 0x00);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+
+        mmc3.cpu_write(0x6000, 0x42);
+        mmc3.cpu_write(0xA001, 0xC0);
+        mmc3.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+        mmc3.cpu_write(0xA001, 0x00);
+        assert_eq!(mmc3.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    pub fn test_small_prg() {
+        let prg: Vec<u8> = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+        let mut mmc3 = Mmc3::create(&test_info(4, 0x2000, 0x2000, 0), prg, vec![0; 0x2000]);
+        for swap in [0x00, 0x40] {
+            mmc3.cpu_write(0x8000, swap);
+            for address in [0x8100, 0xA100, 0xC100, 0xE100] {
+                assert_eq!(mmc3.cpu_read(address), 0x01);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_scanline_irq() {
+        let mut mmc3 = create(0);
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+
+        // Rises closer together than the M2 filter are ignored
+        mmc3.cpu_write(0xE001, 0);
+        mmc3.ppu_read(0x0000);
+        mmc3.ppu_read(0x1000);
+        mmc3.ppu_read(0x0000);
+        mmc3.ppu_read(0x1000);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    pub fn test_latch_zero_irq_revisions() {
+        // MMC3B/C keep firing on every clock with a zero latch
+        let mut mmc3 = create(0);
+        mmc3.cpu_write(0xC000, 0);
+        mmc3.cpu_write(0xE001, 0);
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        mmc3.cpu_write(0xE000, 0);
+        mmc3.cpu_write(0xE001, 0);
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+
+        // MMC3A only fires after a reload
+        let mut mmc3 = create(4);
+        mmc3.cpu_write(0xC000, 0);
+        mmc3.cpu_write(0xE001, 0);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        mmc3.cpu_write(0xC001, 0);
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+}
@@ -0,0 +1,724 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const EXRAM_SIZE: usize = 0x400;
+
+/// PPU reads of a scanline: 32 background tiles, 8 sprites and
+/// 2 prefetched tiles of 4 reads each, followed by 2 dummy reads.
+const BG_FETCHES: usize = 32 * 4;
+const SPRITE_FETCHES: usize = 8 * 4;
+/// CPU cycles without PPU reads before the MMC5 decides rendering stopped
+const IDLE_CYCLES: u8 = 3;
+
+/// What a nametable quadrant is mapped to by $5105
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Nametable {
+    Ciram(usize),
+    ExRam,
+    Fill,
+}
+
+/// Which PPU fetch the MMC5 thinks is on the bus
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Fetch {
+    Background,
+    Sprite,
+}
+
+/// Mapper 5, Nintendo MMC5 (ExROM boards).
+///
+/// The MMC5 has no scanline input from the PPU. Like the real chip it finds
+/// the start of a scanline by watching for three reads of the same nametable
+/// address in a row, the two dummy fetches at the end of a line followed by
+/// the first fetch of the next, and counts PPU reads from there to tell
+/// background, sprite and split screen fetches apart.
+pub struct Mmc5 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    exram: Vec<u8>,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    /// $5105, two bits per nametable quadrant
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    /// $5113-$5117
+    prg_banks: [u8; 5],
+    /// $5120-$5127, used by sprites in 8x16 mode
+    chr_banks_a: [u16; 8],
+    /// $5128-$512B, used by backgrounds in 8x16 mode
+    chr_banks_b: [u16; 4],
+    /// $5130, upper CHR bank bits
+    chr_upper: u8,
+    /// Last CHR register set written, used outside of 8x16 rendering
+    last_chr_b: bool,
+
+    /// $5200-$5202
+    split_control: u8,
+    split_scroll: u8,
+    split_page: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    /// Cleared by reading $5204
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    /// Snooped $2000 bit 5
+    sprites_8x16: bool,
+    /// Snooped $2001 bits 3-4
+    rendering: bool,
+
+    /// Last nametable address read by the PPU and how many times in a row
+    last_nametable: usize,
+    nametable_repeats: u8,
+    /// Index of the current PPU read within the scanline
+    fetches: usize,
+    /// CPU cycles since the last PPU read
+    idle: u8,
+    /// ExRAM byte fetched with the current tile in extended attribute mode
+    tile_exram: u8,
+    /// Current tile comes from the split region
+    in_split: bool,
+}
+
+impl Mmc5 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Mmc5 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            exram: vec![0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            last_chr_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_page: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            sprites_8x16: false,
+            rendering: false,
+            last_nametable: 0,
+            nametable_repeats: 0,
+            fetches: 0,
+            idle: 0,
+            tile_exram: 0,
+            in_split: false,
+        })
+    }
+
+    /// Index in `prg_banks` of the register for an 8 KB slot of $8000-$FFFF,
+    /// and the size of its banks in 8 KB units
+    fn prg_register(&self, slot: usize) -> (usize, usize) {
+        match (self.prg_mode, slot) {
+            (0, _) => (4, 4),
+            (1, 0 | 1) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0 | 1) => (2, 2),
+            (2, 2) => (3, 1),
+            (2, _) => (4, 1),
+            (_, slot) => (slot + 1, 1),
+        }
+    }
+
+    /// Map a CPU address to ROM (true) or RAM (false) and an offset into it
+    fn prg_target(&self, address: usize) -> (bool, usize) {
+        if address < 0x8000 {
+            let bank = (self.prg_banks[0] & 0x07) as usize;
+            return (false, bank * PRG_BANK_SIZE + (address & 0x1FFF));
+        }
+        let slot = (address - 0x8000) / PRG_BANK_SIZE;
+        let (index, size) = self.prg_register(slot);
+        let register = self.prg_banks[index];
+        // $5117 always maps ROM, whatever slots it covers
+        let rom = register & 0x80 == 0x80 || index == 4;
+        let bank = ((register & 0x7F) as usize & !(size - 1)) | (slot % size);
+        let bank = if rom { bank } else { bank & 0x07 };
+        (rom, bank * PRG_BANK_SIZE + (address & 0x1FFF))
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01] && !self.prg_ram.is_empty()
+    }
+
+    fn nametable(&self, address: usize) -> Nametable {
+        let quadrant = (address >> 10) & 0x03;
+        match (self.nametable_mapping >> (quadrant * 2)) & 0x03 {
+            0 => Nametable::Ciram(0),
+            1 => Nametable::Ciram(1),
+            2 => Nametable::ExRam,
+            _ => Nametable::Fill,
+        }
+    }
+
+    /// Fetch type of the current PPU read
+    fn fetch_type(&self) -> Fetch {
+        if self.fetches >= BG_FETCHES && self.fetches < BG_FETCHES + SPRITE_FETCHES {
+            Fetch::Sprite
+        } else {
+            Fetch::Background
+        }
+    }
+
+    /// Tile column being fetched, counting the two prefetched tiles as 0-1
+    fn fetch_column(&self) -> usize {
+        if self.fetches < BG_FETCHES {
+            (self.fetches / 4 + 2) & 0x1F
+        } else {
+            ((self.fetches - BG_FETCHES - SPRITE_FETCHES) / 4) & 0x1F
+        }
+    }
+
+    /// Vertical position inside the split region for the current fetch
+    fn split_y(&self) -> usize {
+        // Prefetched tiles belong to the next scanline
+        let line = if self.fetches < BG_FETCHES { self.scanline } else { self.scanline.wrapping_add(1) };
+        (self.split_scroll as usize + line as usize) % 240
+    }
+
+    fn split_active(&self) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode > 1 || !self.in_frame
+            || self.fetch_type() != Fetch::Background {
+            return false;
+        }
+        let column = self.fetch_column();
+        let tile = (self.split_control & 0x1F) as usize;
+        if self.split_control & 0x40 == 0x40 {
+            column >= tile
+        } else {
+            column < tile
+        }
+    }
+
+    /// Watch PPU reads for the scanline detection and fetch counter
+    fn observe_read(&mut self, address: usize) {
+        self.idle = 0;
+        if (0x2000..0x3000).contains(&address) && address == self.last_nametable {
+            self.nametable_repeats = self.nametable_repeats.saturating_add(1);
+        } else {
+            self.nametable_repeats = 0;
+        }
+        self.last_nametable = address;
+
+        if self.nametable_repeats == 2 {
+            self.new_scanline();
+        } else {
+            self.fetches += 1;
+        }
+    }
+
+    fn new_scanline(&mut self) {
+        self.fetches = 0;
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+            return;
+        }
+        self.scanline = self.scanline.wrapping_add(1);
+        if self.scanline == self.irq_compare {
+            self.irq_pending = true;
+        }
+    }
+
+    /// Use the sprite (A) register set for this CHR access
+    fn use_chr_a(&self) -> bool {
+        if self.sprites_8x16 && self.in_frame && self.rendering {
+            self.fetch_type() == Fetch::Sprite
+        } else {
+            !self.last_chr_b
+        }
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let slot = (address >> 10) & 0x07;
+        let (bank, size) = if self.use_chr_a() {
+            match self.chr_mode {
+                0 => (self.chr_banks_a[7], 8),
+                1 => (self.chr_banks_a[(slot & 0x04) | 0x03], 4),
+                2 => (self.chr_banks_a[(slot & 0x06) | 0x01], 2),
+                _ => (self.chr_banks_a[slot], 1),
+            }
+        } else {
+            match self.chr_mode {
+                0 | 1 => (self.chr_banks_b[3], 8 >> self.chr_mode),
+                2 => (self.chr_banks_b[(slot & 0x02) | 0x01], 2),
+                _ => (self.chr_banks_b[slot & 0x03], 1),
+            }
+        };
+        let base = bank as usize * size * CHR_BANK_SIZE;
+        (base + (address & (size * CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+
+    /// Replicate a 2 bit palette number into a whole attribute byte
+    fn attribute_byte(palette: u8) -> u8 {
+        let palette = palette & 0x03;
+        palette | palette << 2 | palette << 4 | palette << 6
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, address: usize) -> u8 {
+        let value = self.cpu_peek(address);
+        // Reading the status acknowledges the IRQ
+        if address == 0x5204 {
+            self.irq_pending = false;
+        }
+        value
+    }
+
+    fn cpu_peek(&self, address: usize) -> u8 {
+        match address {
+            0x5204 => (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6,
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[address - 0x5C00],
+            0x6000..=0xFFFF => {
+                let (rom, offset) = self.prg_target(address);
+                if rom {
+                    self.prg[offset % self.prg.len()]
+                } else if self.prg_ram.is_empty() {
+                    0
+                } else {
+                    self.prg_ram[offset % self.prg_ram.len()]
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        match address {
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 => self.prg_ram_protect[0] = value & 0x03,
+            0x5103 => self.prg_ram_protect[1] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[address - 0x5113] = value,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[address - 0x5120] = (self.chr_upper as u16) << 8 | value as u16;
+                self.last_chr_b = false;
+            }
+            0x5128..=0x512B => {
+                self.chr_banks_b[address - 0x5128] = (self.chr_upper as u16) << 8 | value as u16;
+                self.last_chr_b = true;
+            }
+            0x5130 => self.chr_upper = value & 0x03,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_page = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 == 0x80,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                match self.exram_mode {
+                    // Only writable while rendering in the nametable modes
+                    0 | 1 => self.exram[address - 0x5C00] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[address - 0x5C00] = value,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF => {
+                let (rom, offset) = self.prg_target(address);
+                if !rom && self.prg_ram_writable() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[offset % len] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.observe_read(address);
+        let offset = if self.in_split && self.fetch_type() == Fetch::Background {
+            let page = self.split_page as usize * 0x1000;
+            // Fine Y comes from the split scroll instead of the PPU
+            page + (address & 0x0FF8) + (self.split_y() & 0x07)
+        } else if self.exram_mode == 1 && self.fetch_type() == Fetch::Background && self.in_frame {
+            let bank = (self.chr_upper as usize) << 6 | (self.tile_exram & 0x3F) as usize;
+            bank * 0x1000 + (address & 0x0FFF)
+        } else {
+            self.chr_offset(address)
+        };
+        self.chr[offset % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x50 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn ciram_page(&self, address: usize) -> usize {
+        match self.nametable(address) {
+            Nametable::Ciram(page) => page,
+            _ => 0,
+        }
+    }
+
+    fn nametable_read(&mut self, address: usize) -> Option<u8> {
+        self.observe_read(address);
+        let offset = address & 0x03FF;
+        let attribute = offset >= 0x3C0;
+        let background = self.fetch_type() == Fetch::Background && self.in_frame;
+
+        if background && !attribute && self.split_active() {
+            self.in_split = true;
+            let row = self.split_y() / 8;
+            Some(self.exram[row * 32 + self.fetch_column()])
+        } else if background && attribute && self.in_split {
+            let row = self.split_y() / 32;
+            let byte = self.exram[0x3C0 + row * 8 + self.fetch_column() / 4];
+            // Pick the quadrant the PPU will shift out of the attribute byte
+            let shift = ((self.split_y() & 0x10) >> 2) | (self.fetch_column() & 0x02);
+            Some(Mmc5::attribute_byte(byte >> shift))
+        } else {
+            if !attribute {
+                self.in_split = false;
+            }
+            let exattr = self.exram_mode == 1 && background;
+            if exattr && !attribute {
+                self.tile_exram = self.exram[offset];
+            }
+            match self.nametable(address) {
+                _ if exattr && attribute => Some(Mmc5::attribute_byte(self.tile_exram >> 6)),
+                Nametable::Ciram(_) => None,
+                Nametable::ExRam => Some(if self.exram_mode <= 1 { self.exram[offset] } else { 0 }),
+                Nametable::Fill => Some(if attribute {
+                    Mmc5::attribute_byte(self.fill_attribute)
+                } else {
+                    self.fill_tile
+                }),
+            }
+        }
+    }
+
+    fn nametable_write(&mut self, address: usize, value: u8) -> bool {
+        match self.nametable(address) {
+            Nametable::Ciram(_) => false,
+            Nametable::ExRam => {
+                if self.exram_mode <= 1 {
+                    self.exram[address & 0x03FF] = value;
+                }
+                true
+            }
+            Nametable::Fill => true,
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: usize, value: u8) {
+        match address {
+            0x2000 => self.sprites_8x16 = value & 0x20 == 0x20,
+            0x2001 => {
+                self.rendering = value & 0x18 != 0;
+                if !self.rendering {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.idle < IDLE_CYCLES {
+            self.idle += 1;
+            if self.idle == IDLE_CYCLES {
+                self.in_frame = false;
+            }
+        }
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc5;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::Mapper;
+
+    fn create() -> Box<dyn Mapper> {
+        let prg: Vec<u8> = (0..0x40000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..0x40000).map(|i| (i / 0x400) as u8).collect();
+        Mmc5::create(&test_info(5, 0x40000, 0x40000, 0x10000), prg, chr)
+    }
+
+    /// Dummy nametable reads at the end of the pre-render line
+    fn prerender(mmc5: &mut Box<dyn Mapper>) {
+        mmc5.nametable_read(0x2002);
+        mmc5.nametable_read(0x2002);
+    }
+
+    /// Perform the PPU reads of one rendered scanline with 8x16 sprites
+    /// at pattern $1000, ending with the two dummy nametable reads
+    fn scanline(mmc5: &mut Box<dyn Mapper>) -> (Vec<u8>, Vec<u8>) {
+        let mut background = Vec::new();
+        let mut sprites = Vec::new();
+        for tile in 0..32 {
+            mmc5.nametable_read(0x2002 + tile);
+            mmc5.nametable_read(0x23C0 + tile / 4);
+            background.push(mmc5.ppu_read(0x0000));
+            mmc5.ppu_read(0x0008);
+        }
+        for _ in 0..8 {
+            mmc5.nametable_read(0x2000);
+            mmc5.nametable_read(0x2000);
+            sprites.push(mmc5.ppu_read(0x1000));
+            mmc5.ppu_read(0x1008);
+        }
+        for tile in 0..2 {
+            mmc5.nametable_read(0x2000 + tile);
+            mmc5.nametable_read(0x23C0);
+            mmc5.ppu_read(0x0000);
+            mmc5.ppu_read(0x0008);
+        }
+        prerender(mmc5);
+        (background, sprites)
+    }
+
+    #[test]
+    pub fn test_prg_modes() {
+        let mut mmc5 = create();
+        // Power on: mode 3 with $5117 = $FF at $E000
+        assert_eq!(mmc5.cpu_read(0xE000), 31);
+
+        mmc5.cpu_write(0x5100, 0);
+        mmc5.cpu_write(0x5117, 0x85);
+        assert_eq!(mmc5.cpu_read(0x8000), 4);
+        assert_eq!(mmc5.cpu_read(0xE000), 7);
+
+        mmc5.cpu_write(0x5100, 1);
+        mmc5.cpu_write(0x5115, 0x8B);
+        assert_eq!(mmc5.cpu_read(0x8000), 10);
+        assert_eq!(mmc5.cpu_read(0xA000), 11);
+        assert_eq!(mmc5.cpu_read(0xC000), 4);
+        // $5117 maps ROM even with bit 7 clear, $C000 included
+        mmc5.cpu_write(0x5117, 0x07);
+        assert_eq!(mmc5.cpu_read(0xC000), 6);
+        assert_eq!(mmc5.cpu_read(0xE000), 7);
+        mmc5.cpu_write(0x5117, 0x85);
+
+        mmc5.cpu_write(0x5100, 2);
+        mmc5.cpu_write(0x5116, 0x93);
+        assert_eq!(mmc5.cpu_read(0xC000), 0x13);
+        assert_eq!(mmc5.cpu_read(0xE000), 5);
+
+        mmc5.cpu_write(0x5100, 3);
+        mmc5.cpu_write(0x5114, 0x81);
+        assert_eq!(mmc5.cpu_read(0x8000), 1);
+        assert_eq!(mmc5.cpu_read(0xA000), 11);
+    }
+
+    #[test]
+    pub fn test_prg_ram() {
+        let mut mmc5 = create();
+        mmc5.cpu_write(0x5113, 2);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+
+        mmc5.cpu_write(0x5102, 2);
+        mmc5.cpu_write(0x5103, 1);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0x42);
+
+        // RAM banked into the ROM area
+        mmc5.cpu_write(0x5114, 0x02);
+        assert_eq!(mmc5.cpu_read(0x8000), 0x42);
+        mmc5.cpu_write(0x5113, 0);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    pub fn test_multiplier() {
+        let mut mmc5 = create();
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_read(0x5205), (20000 & 0xFF) as u8);
+        assert_eq!(mmc5.cpu_read(0x5206), (20000 >> 8) as u8);
+    }
+
+    #[test]
+    pub fn test_chr_modes() {
+        let mut mmc5 = create();
+        mmc5.cpu_write(0x5101, 3);
+        mmc5.cpu_write(0x5122, 0x30);
+        assert_eq!(mmc5.ppu_read(0x0800), 0x30);
+
+        mmc5.cpu_write(0x5101, 1);
+        mmc5.cpu_write(0x5130, 1);
+        mmc5.cpu_write(0x5127, 0x02);
+        assert_eq!(mmc5.ppu_read(0x1400), ((0x102 * 4 + 1) & 0xFF) as u8);
+
+        // Last written set is used outside 8x16 rendering
+        mmc5.cpu_write(0x5130, 0);
+        mmc5.cpu_write(0x512B, 0x05);
+        assert_eq!(mmc5.ppu_read(0x0400), 21);
+        assert_eq!(mmc5.ppu_read(0x1400), 21);
+    }
+
+    #[test]
+    pub fn test_8x16_sprite_banks() {
+        let mut mmc5 = create();
+        mmc5.cpu_write(0x5101, 0);
+        mmc5.cpu_write(0x5127, 1);
+        mmc5.cpu_write(0x512B, 2);
+        mmc5.ppu_register_write(0x2000, 0x20);
+        mmc5.ppu_register_write(0x2001, 0x18);
+
+        prerender(&mut mmc5);
+        scanline(&mut mmc5);
+        let (background, sprites) = scanline(&mut mmc5);
+        assert!(background.iter().all(|&b| b == 16));
+        assert!(sprites.iter().all(|&b| b == 12));
+    }
+
+    #[test]
+    pub fn test_scanline_irq() {
+        let mut mmc5 = create();
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5.cpu_write(0x5203, 3);
+        mmc5.cpu_write(0x5204, 0x80);
+
+        prerender(&mut mmc5);
+        scanline(&mut mmc5);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x40);
+        scanline(&mut mmc5);
+        scanline(&mut mmc5);
+        assert!(!mmc5.irq());
+        scanline(&mut mmc5);
+        assert!(mmc5.irq());
+        // Peeking leaves the IRQ pending, only a read acknowledges it
+        assert_eq!(mmc5.cpu_peek(0x5204), 0xC0);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+        assert!(!mmc5.irq());
+
+        // No PPU reads for a few cycles ends the frame
+        for _ in 0..3 {
+            mmc5.cpu_tick();
+        }
+        assert_eq!(mmc5.cpu_read(0x5204), 0x00);
+    }
+
+    #[test]
+    pub fn test_nametables() {
+        let mut mmc5 = create();
+        // Quadrants: CIRAM 0, CIRAM 1, ExRAM, fill
+        mmc5.cpu_write(0x5105, 0b11_10_01_00);
+        assert_eq!(mmc5.ciram_page(0x2000), 0);
+        assert_eq!(mmc5.ciram_page(0x2400), 1);
+        assert_eq!(mmc5.nametable_read(0x2000), None);
+
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C10, 0x77);
+        assert_eq!(mmc5.cpu_read(0x5C10), 0x77);
+        // ExRAM reads as zero nametable while in CPU RAM mode
+        assert_eq!(mmc5.nametable_read(0x2810), Some(0));
+        mmc5.cpu_write(0x5104, 0);
+        assert_eq!(mmc5.nametable_read(0x2810), Some(0x77));
+        assert!(mmc5.nametable_write(0x2811, 0x66));
+        assert_eq!(mmc5.nametable_read(0x2811), Some(0x66));
+
+        mmc5.cpu_write(0x5106, 0x12);
+        mmc5.cpu_write(0x5107, 0x02);
+        assert_eq!(mmc5.nametable_read(0x2C00), Some(0x12));
+        assert_eq!(mmc5.nametable_read(0x2FC0), Some(0xAA));
+    }
+
+    #[test]
+    pub fn test_extended_attributes() {
+        let mut mmc5 = create();
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5.cpu_write(0x5104, 2);
+        for tile in 0..32 {
+            mmc5.cpu_write(0x5C00 + tile, 0xC0 | 0x03);
+        }
+        mmc5.cpu_write(0x5104, 1);
+        prerender(&mut mmc5);
+        scanline(&mut mmc5);
+
+        assert_eq!(mmc5.nametable_read(0x2002), None);
+        assert_eq!(mmc5.nametable_read(0x23C0), Some(0xFF));
+        // 4 KB bank 3 is 1 KB bank 12
+        assert_eq!(mmc5.ppu_read(0x0000), 12);
+    }
+
+    #[test]
+    pub fn test_split_screen() {
+        let mut mmc5 = create();
+        mmc5.ppu_register_write(0x2001, 0x18);
+        mmc5.cpu_write(0x5104, 2);
+        for column in 0..32 {
+            mmc5.cpu_write(0x5C00 + column, 0x40 + column as u8);
+        }
+        mmc5.cpu_write(0x5104, 0);
+        // Left split of 4 tiles from CHR page 2
+        mmc5.cpu_write(0x5200, 0x84);
+        mmc5.cpu_write(0x5202, 2);
+        prerender(&mut mmc5);
+        scanline(&mut mmc5);
+
+        // First fetch of the line is tile column 2, inside the split
+        assert_eq!(mmc5.nametable_read(0x2002), Some(0x42));
+        mmc5.nametable_read(0x23C0);
+        assert_eq!(mmc5.ppu_read(0x0420), 9);
+        mmc5.ppu_read(0x0428);
+        assert_eq!(mmc5.nametable_read(0x2003), Some(0x43));
+        mmc5.nametable_read(0x23C0);
+        mmc5.ppu_read(0x0000);
+        mmc5.ppu_read(0x0008);
+        // Column 4 is outside the split
+        assert_eq!(mmc5.nametable_read(0x2004), None);
+    }
+}
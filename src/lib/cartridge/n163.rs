@@ -0,0 +1,403 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const INTERNAL_RAM_SIZE: usize = 0x80;
+/// Channel registers live at the top of the internal RAM, 8 bytes each
+const CHANNEL_BASE: usize = 0x40;
+/// CPU cycles between two channel updates
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+/// Output level per step of a channel sample times its volume
+const AUDIO_SCALE: f32 = 0.0014;
+
+/// Mapper 19, Namco 163.
+///
+/// 128 bytes of internal RAM hold both the wavetable samples and the
+/// registers of up to 8 sound channels. Boards with a battery keep this RAM
+/// along with the PRG RAM, so it is part of the save data.
+/// CHR banks pointing at CIRAM for pattern data are not supported.
+pub struct N163 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+
+    prg_banks: [u8; 3],
+    /// Eight 1K pattern banks and four nametable banks
+    chr_banks: [u8; 12],
+    /// $E800 bits 6-7, CHR RAM disable for each pattern table
+    chr_ram_disable: u8,
+    /// $F800, PRG RAM write protect and internal RAM address port
+    write_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    ram: [u8; INTERNAL_RAM_SIZE],
+    /// Internal RAM address and auto increment flag, reads advance it too
+    ram_address: u8,
+    sound_disabled: bool,
+    /// Cycles until the next channel update
+    update_divider: u8,
+    /// Channel updated next, counting down from 7
+    channel: usize,
+    outputs: [i16; 8],
+}
+
+impl N163 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(N163 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            prg_banks: [0, 0, 0],
+            chr_banks: [0; 12],
+            chr_ram_disable: 0,
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            ram: [0; INTERNAL_RAM_SIZE],
+            ram_address: 0,
+            sound_disabled: false,
+            update_divider: CHANNEL_UPDATE_CYCLES,
+            channel: 7,
+            outputs: [0; 8],
+        })
+    }
+
+    fn prg_offset(&self, address: usize) -> usize {
+        let bank = match (address >> 13) & 0x03 {
+            // NES 2.0 allows less PRG than one bank, it is then mirrored
+            3 => (self.prg.len() / PRG_BANK_SIZE).max(1) - 1,
+            n => self.prg_banks[n] as usize,
+        };
+        (bank * PRG_BANK_SIZE + (address & 0x1FFF)) % self.prg.len()
+    }
+
+    fn chr_offset(&self, slot: usize, address: usize) -> usize {
+        (self.chr_banks[slot] as usize * CHR_BANK_SIZE + (address & 0x03FF)) % self.chr.len()
+    }
+
+    /// Bank numbers $E0-$FF select CIRAM for nametables
+    fn nametable_bank(&self, address: usize) -> u8 {
+        self.chr_banks[8 + ((address >> 10) & 0x03)]
+    }
+
+    fn prg_ram_writable(&self, address: usize) -> bool {
+        let window = (address - 0x6000) >> 11;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << window) == 0
+    }
+
+    fn ram_port_read(&mut self) -> u8 {
+        let value = self.ram[(self.ram_address & 0x7F) as usize];
+        self.advance_ram_address();
+        value
+    }
+
+    fn ram_port_write(&mut self, value: u8) {
+        self.ram[(self.ram_address & 0x7F) as usize] = value;
+        self.advance_ram_address();
+    }
+
+    fn advance_ram_address(&mut self) {
+        let address = self.ram_address;
+        if address & 0x80 == 0x80 {
+            self.ram_address = 0x80 | (address.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    /// Number of enabled channels, from the top of channel 7's volume register
+    fn active_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    /// Advance one channel's phase and sample its wavetable
+    fn update_channel(&mut self, channel: usize) {
+        let base = CHANNEL_BASE + channel * 8;
+        let regs = &self.ram[base..base + 8];
+        let frequency = regs[0] as u32 | (regs[2] as u32) << 8 | ((regs[4] & 0x03) as u32) << 16;
+        let length = (256 - (regs[4] & 0xFC) as u32) << 16;
+        let mut phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        let wave_address = regs[6] as u32;
+        let volume = (regs[7] & 0x0F) as i16;
+
+        phase = (phase + frequency) % length;
+        let sample_address = ((phase >> 16) + wave_address) & 0xFF;
+        let byte = self.ram[(sample_address >> 1) as usize];
+        let sample = if sample_address & 0x01 == 0 { byte & 0x0F } else { byte >> 4 };
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+    }
+}
+
+impl Mapper for N163 {
+    fn cpu_read(&mut self, address: usize) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.ram_port_read(),
+            _ => self.cpu_peek(address),
+        }
+    }
+
+    fn cpu_peek(&self, address: usize) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.ram[(self.ram_address & 0x7F) as usize],
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(address - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg[self.prg_offset(address)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        match address {
+            0x4800..=0x4FFF => self.ram_port_write(value),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((value & 0x7F) as u16) << 8;
+                self.irq_enabled = value & 0x80 == 0x80;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.prg_ram_writable(address) => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) % len] = value;
+            }
+            0x8000..=0xDFFF => self.chr_banks[(address - 0x8000) >> 11] = value,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                self.sound_disabled = value & 0x40 == 0x40;
+            }
+            0xE800..=0xEFFF => {
+                self.prg_banks[1] = value & 0x3F;
+                self.chr_ram_disable = value & 0xC0;
+            }
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            0xF800..=0xFFFF => {
+                self.write_protect = value;
+                self.ram_address = value;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[self.chr_offset((address >> 10) & 0x07, address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        let disabled = if address < 0x1000 { 0x40 } else { 0x80 };
+        if self.chr_ram && self.chr_ram_disable & disabled == 0 {
+            let offset = self.chr_offset((address >> 10) & 0x07, address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Vertical
+    }
+
+    fn ciram_page(&self, address: usize) -> usize {
+        (self.nametable_bank(address) & 0x01) as usize
+    }
+
+    fn nametable_read(&mut self, address: usize) -> Option<u8> {
+        if self.nametable_bank(address) >= 0xE0 {
+            return None;
+        }
+        Some(self.chr[self.chr_offset(8 + ((address >> 10) & 0x03), address)])
+    }
+
+    fn nametable_write(&mut self, address: usize, _value: u8) -> bool {
+        // Nametables in CHR ROM are read only
+        self.nametable_bank(address) < 0xE0
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        self.update_divider -= 1;
+        if self.update_divider == 0 {
+            self.update_divider = CHANNEL_UPDATE_CYCLES;
+            let channel = self.channel;
+            self.update_channel(channel);
+            self.channel = if channel <= 8 - self.active_channels() { 7 } else { channel - 1 };
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            return 0.0;
+        }
+        // The chip plays one channel at a time, which averages out to this
+        let active = self.active_channels();
+        let sum: i16 = self.outputs[8 - active..].iter().sum();
+        sum as f32 / active as f32 * AUDIO_SCALE
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        let mut data = self.prg_ram.clone();
+        data.extend_from_slice(&self.ram);
+        data
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        let (prg_ram, ram) = data.split_at(self.prg_ram.len());
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.ram.copy_from_slice(ram);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::N163;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::Mapper;
+
+    fn create() -> Box<dyn Mapper> {
+        let prg: Vec<u8> = (0..0x40000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..0x40000).map(|i| (i / 0x400) as u8).collect();
+        N163::create(&test_info(19, 0x40000, 0x40000, 0x2000), prg, chr)
+    }
+
+    #[test]
+    pub fn test_banks() {
+        let mut n163 = create();
+        n163.cpu_write(0xE000, 3);
+        n163.cpu_write(0xE800, 4);
+        n163.cpu_write(0xF000, 5);
+        assert_eq!(n163.cpu_read(0x8000), 3);
+        assert_eq!(n163.cpu_read(0xA000), 4);
+        assert_eq!(n163.cpu_read(0xC000), 5);
+        assert_eq!(n163.cpu_read(0xE000), 31);
+
+        n163.cpu_write(0xB800, 0x21);
+        assert_eq!(n163.ppu_read(0x1C00), 0x21);
+
+        // Nametables from CHR ROM or CIRAM
+        n163.cpu_write(0xC000, 0x10);
+        n163.cpu_write(0xC800, 0xE1);
+        assert_eq!(n163.nametable_read(0x2000), Some(0x10));
+        assert!(n163.nametable_write(0x2000, 0));
+        assert_eq!(n163.nametable_read(0x2400), None);
+        assert_eq!(n163.ciram_page(0x2400), 1);
+    }
+
+    #[test]
+    pub fn test_prg_ram_protect() {
+        let mut n163 = create();
+        n163.cpu_write(0x6000, 0x42);
+        assert_eq!(n163.cpu_read(0x6000), 0);
+        n163.cpu_write(0xF800, 0x41);
+        n163.cpu_write(0x6000, 0x42);
+        n163.cpu_write(0x6800, 0x43);
+        assert_eq!(n163.cpu_read(0x6000), 0);
+        assert_eq!(n163.cpu_read(0x6800), 0x43);
+    }
+
+    #[test]
+    pub fn test_small_prg() {
+        let prg: Vec<u8> = (0..0x1000).map(|i| (i >> 8) as u8).collect();
+        let mut n163 = N163::create(&test_info(19, 0x1000, 0x2000, 0), prg, vec![0; 0x2000]);
+        assert_eq!(n163.cpu_read(0xE100), 0x01);
+        assert_eq!(n163.cpu_read(0xF100), 0x01);
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut n163 = create();
+        n163.cpu_write(0x5000, 0xFD);
+        n163.cpu_write(0x5800, 0xFF);
+        n163.cpu_tick();
+        assert!(!n163.irq());
+        n163.cpu_tick();
+        assert!(n163.irq());
+        assert_eq!(n163.cpu_read(0x5000), 0xFF);
+        n163.cpu_write(0x5800, 0x7F);
+        assert!(!n163.irq());
+    }
+
+    #[test]
+    pub fn test_internal_ram_port() {
+        let mut n163 = create();
+        n163.cpu_write(0xF800, 0x80 | 0x7E);
+        n163.cpu_write(0x4800, 0x11);
+        n163.cpu_write(0x4800, 0x22);
+        n163.cpu_write(0x4800, 0x33);
+        n163.cpu_write(0xF800, 0x7E);
+        assert_eq!(n163.cpu_read(0x4800), 0x11);
+        assert_eq!(n163.cpu_read(0x4800), 0x11);
+        n163.cpu_write(0xF800, 0xFF);
+        assert_eq!(n163.cpu_peek(0x4800), 0x22);
+        assert_eq!(n163.cpu_peek(0x4800), 0x22);
+        assert_eq!(n163.cpu_read(0x4800), 0x22);
+        assert_eq!(n163.cpu_read(0x4800), 0x33);
+    }
+
+    #[test]
+    pub fn test_wavetable_channel() {
+        let mut n163 = create();
+        // A four sample wave at $00 alternating between levels F and 0
+        n163.cpu_write(0xF800, 0x80);
+        n163.cpu_write(0x4800, 0x0F);
+        n163.cpu_write(0x4800, 0x0F);
+        // Channel 7 alone: one sample per update, length 4, volume 15
+        n163.cpu_write(0xF800, 0x80 | 0x78);
+        for value in [0x00, 0x00, 0x00, 0x00, 0xFD, 0x00, 0x00, 0x0F] {
+            n163.cpu_write(0x4800, value);
+        }
+
+        let mut levels = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..15 {
+                n163.cpu_tick();
+            }
+            levels.push(n163.audio_output());
+        }
+        assert!(levels[0] < 0.0);
+        assert!(levels[1] > 0.0);
+        assert_eq!(levels[0], levels[2]);
+        assert_eq!(levels[1], levels[3]);
+
+        n163.cpu_write(0xE000, 0x40);
+        assert_eq!(n163.audio_output(), 0.0);
+    }
+
+    #[test]
+    pub fn test_save_ram() {
+        let mut n163 = create();
+        n163.cpu_write(0xF800, 0x40);
+        n163.cpu_write(0x6000, 0x12);
+        n163.cpu_write(0xF800, 0x80 | 0x10);
+        n163.cpu_write(0x4800, 0x34);
+        let data = n163.save_ram();
+        assert_eq!(data.len(), 0x2000 + 0x80);
+        assert_eq!(data[0], 0x12);
+        assert_eq!(data[0x2010], 0x34);
+
+        let mut other = create();
+        other.load_save_ram(&data);
+        assert_eq!(other.cpu_read(0x6000), 0x12);
+        other.cpu_write(0xF800, 0x10);
+        assert_eq!(other.cpu_read(0x4800), 0x34);
+    }
+}
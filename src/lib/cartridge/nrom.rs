@@ -0,0 +1,106 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+
+/// Mapper 0, no bank switching.
+///
+/// 16 KB boards (NROM-128) mirror their single bank in $8000-$BFFF and
+/// $C000-$FFFF, 32 KB boards (NROM-256) fill the whole range.
+/// Family BASIC adds 2-4 KB of PRG RAM mirrored through $6000-$7FFF.
+pub struct Nrom {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Nrom {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            mirroring: info.mirroring,
+        })
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address >= 0x8000 {
+            return self.prg[(address - 0x8000) % self.prg.len()];
+        }
+        if address >= 0x6000 && !self.prg_ram.is_empty() {
+            return self.prg_ram[(address - 0x6000) % self.prg_ram.len()];
+        }
+        0
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if (0x6000..0x8000).contains(&address) && !self.prg_ram.is_empty() {
+            let len = self.prg_ram.len();
+            self.prg_ram[(address - 0x6000) % len] = value;
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[address & 0x1FFF]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            self.chr[address & 0x1FFF] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Nrom;
+    use crate::cartridge::mapper::test_info;
+
+    fn prg(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i >> 8) as u8).collect()
+    }
+
+    #[test]
+    pub fn test_nrom_128() {
+        let mut nrom = Nrom::create(&test_info(0, 0x4000, 0x2000, 0), prg(0x4000), vec![0x22; 0x2000]);
+        assert_eq!(nrom.cpu_read(0x8100), 0x01);
+        assert_eq!(nrom.cpu_read(0xC100), 0x01);
+        assert_eq!(nrom.cpu_read(0xFFFF), 0x3F);
+        // No PRG RAM, and the ROM is read only
+        nrom.cpu_write(0x6000, 0x55);
+        nrom.cpu_write(0x8100, 0x55);
+        assert_eq!(nrom.cpu_read(0x6000), 0);
+        assert_eq!(nrom.cpu_read(0x8100), 0x01);
+        // CHR ROM is read only
+        nrom.ppu_write(0x0010, 0x55);
+        assert_eq!(nrom.ppu_read(0x0010), 0x22);
+    }
+
+    #[test]
+    pub fn test_nrom_256_with_prg_ram() {
+        let mut nrom = Nrom::create(&test_info(0, 0x8000, 0x2000, 0x800), prg(0x8000), vec![0; 0x2000]);
+        assert_eq!(nrom.cpu_read(0x8000), 0x00);
+        assert_eq!(nrom.cpu_read(0xC000), 0x40);
+        assert_eq!(nrom.cpu_read(0xFFFF), 0x7F);
+
+        nrom.cpu_write(0x6001, 0x55);
+        assert_eq!(nrom.cpu_read(0x6001), 0x55);
+        assert_eq!(nrom.cpu_read(0x6801), 0x55);
+        assert_eq!(nrom.cpu_read(0x7801), 0x55);
+    }
+}
@@ -0,0 +1,110 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+use super::mapper::{bus_conflict, has_bus_conflicts};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 2, UxROM.
+///
+/// $8000-$BFFF is a switchable 16 KB bank, $C000-$FFFF is fixed to the last bank.
+pub struct Uxrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Uxrom {
+            prg,
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            mirroring: info.mirroring,
+            bus_conflicts: has_bus_conflicts(info, true),
+            prg_bank: 0,
+        })
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address < 0x8000 {
+            return 0;
+        }
+        let bank = if address < 0xC000 {
+            self.prg_bank as usize
+        } else {
+            // NES 2.0 allows less PRG than one bank, it is then mirrored
+            (self.prg.len() / PRG_BANK_SIZE).max(1) - 1
+        };
+        self.prg[(bank * PRG_BANK_SIZE + (address & 0x3FFF)) % self.prg.len()]
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address >= 0x8000 {
+            self.prg_bank = bus_conflict(self.bus_conflicts, self.cpu_peek(address), value);
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[address & 0x1FFF]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            self.chr[address & 0x1FFF] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Uxrom;
+    use crate::cartridge::mapper::test_info;
+
+    #[test]
+    pub fn test_uxrom_banks() {
+        let prg: Vec<u8> = (0..0x20000).map(|i| (i / 0x4000) as u8).collect();
+        let mut uxrom = Uxrom::create(&test_info(2, 0x20000, 0, 0), prg, vec![0; 0x2000]);
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+        assert_eq!(uxrom.cpu_read(0xC000), 7);
+        // Written over the fixed bank, whose $07 bytes let the value through
+        uxrom.cpu_write(0xC000, 5);
+        assert_eq!(uxrom.cpu_read(0xBFFF), 5);
+        assert_eq!(uxrom.cpu_read(0xFFFF), 7);
+
+        uxrom.ppu_write(0x0123, 0x42);
+        assert_eq!(uxrom.ppu_read(0x0123), 0x42);
+    }
+
+    #[test]
+    pub fn test_uxrom_small_prg() {
+        let prg: Vec<u8> = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+        let mut uxrom = Uxrom::create(&test_info(2, 0x2000, 0, 0), prg, vec![0; 0x2000]);
+        assert_eq!(uxrom.cpu_read(0x8100), 0x01);
+        assert_eq!(uxrom.cpu_read(0xC100), 0x01);
+        assert_eq!(uxrom.cpu_read(0xFFFF), 0x1F);
+    }
+
+    #[test]
+    pub fn test_uxrom_bus_conflicts() {
+        // Every bank holds its number, except the fixed bank which holds $03
+        let prg: Vec<u8> = (0..0x20000).map(|i| if i >= 0x1C000 { 3 } else { (i / 0x4000) as u8 }).collect();
+        let mut uxrom = Uxrom::create(&test_info(2, 0x20000, 0, 0), prg.clone(), vec![0; 0x2000]);
+        uxrom.cpu_write(0xC000, 0x06);
+        assert_eq!(uxrom.cpu_read(0x8000), 2);
+
+        // Submapper 1 boards have no bus conflicts
+        let mut info = test_info(2, 0x20000, 0, 0);
+        info.submapper = 1;
+        let mut uxrom = Uxrom::create(&info, prg, vec![0; 0x2000]);
+        uxrom.cpu_write(0xC000, 0x06);
+        assert_eq!(uxrom.cpu_read(0x8000), 6);
+    }
+}
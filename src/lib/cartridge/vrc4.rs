@@ -0,0 +1,286 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+use super::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mappers 21, 22, 23 and 25, Konami VRC2 and VRC4.
+///
+/// The boards wire different CPU address lines to the two register select
+/// pins. The submapper picks the wiring, submapper 0 ORs together the lines
+/// of all variants sharing the mapper number, which works for most games.
+pub struct Vrc4 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    /// VRC2 has no IRQ, no PRG swap mode and only two mirroring modes
+    vrc2: bool,
+    /// VRC2a ignores the low bit of the CHR bank numbers
+    chr_shift: u8,
+    /// Address bits driving register select pin 0 and 1
+    select_masks: [usize; 2],
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    /// VRC2 boards without PRG RAM have a one bit latch at $6000
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        let (vrc2, select_masks) = match (info.mapper, info.submapper) {
+            (21, 1) => (false, [0x02, 0x04]),
+            (21, 2) => (false, [0x40, 0x80]),
+            (21, _) => (false, [0x42, 0x84]),
+            (22, _) => (true, [0x02, 0x01]),
+            (23, 1) => (false, [0x01, 0x02]),
+            (23, 2) => (false, [0x04, 0x08]),
+            (23, 3) => (true, [0x01, 0x02]),
+            (23, _) => (false, [0x05, 0x0A]),
+            (25, 1) => (false, [0x02, 0x01]),
+            (25, 2) => (false, [0x08, 0x04]),
+            (25, 3) => (true, [0x02, 0x01]),
+            (_, _) => (false, [0x0A, 0x05]),
+        };
+        Box::new(Vrc4 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            vrc2,
+            chr_shift: if info.mapper == 22 { 1 } else { 0 },
+            select_masks,
+            prg_banks: [0, 0],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            latch: 0,
+            irq: VrcIrq::new(),
+        })
+    }
+
+    /// Translate a CPU address to the canonical $x000-$x003 register number
+    fn register(&self, address: usize) -> usize {
+        let pin0 = (address & self.select_masks[0] != 0) as usize;
+        let pin1 = (address & self.select_masks[1] != 0) as usize;
+        (address & 0xF000) | pin1 << 1 | pin0
+    }
+
+    fn prg_offset(&self, address: usize) -> usize {
+        // With a single bank of PRG both fixed banks mirror it
+        let second_last = (self.prg.len() / PRG_BANK_SIZE).max(2) - 2;
+        let bank = match ((address >> 13) & 0x03, self.prg_swap) {
+            (0, false) | (2, true) => self.prg_banks[0] as usize,
+            (0, true) | (2, false) => second_last,
+            (1, _) => self.prg_banks[1] as usize,
+            _ => second_last + 1,
+        };
+        (bank * PRG_BANK_SIZE + (address & 0x1FFF)) % self.prg.len()
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let bank = (self.chr_banks[(address >> 10) & 0x07] >> self.chr_shift) as usize;
+        (bank * CHR_BANK_SIZE + (address & 0x03FF)) % self.chr.len()
+    }
+
+    fn write_chr(&mut self, register: usize, value: u8) {
+        // $B000 holds banks 0-1, $C000 banks 2-3 and so on, low nibble first
+        let bank = ((register >> 12) - 0x0B) * 2 + ((register >> 1) & 0x01);
+        let current = self.chr_banks[bank];
+        self.chr_banks[bank] = if register & 0x01 == 0 {
+            (current & 0x1F0) | (value & 0x0F) as u16
+        } else {
+            let high = if self.vrc2 { value & 0x0F } else { value & 0x1F };
+            (current & 0x0F) | (high as u16) << 4
+        };
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address >= 0x8000 {
+            return self.prg[self.prg_offset(address)];
+        }
+        if address >= 0x6000 {
+            if !self.prg_ram.is_empty() {
+                return self.prg_ram[(address - 0x6000) % self.prg_ram.len()];
+            }
+            if self.vrc2 && address < 0x7000 {
+                return self.latch;
+            }
+        }
+        0
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        if address < 0x8000 {
+            if !self.prg_ram.is_empty() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) % len] = value;
+            } else if self.vrc2 && address < 0x7000 {
+                self.latch = value & 0x01;
+            }
+            return;
+        }
+
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = value & 0x01,
+            0x9000 | 0x9001 => self.mirroring = value & 0x03,
+            0x9002 | 0x9003 => self.prg_swap = value & 0x02 == 0x02,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            0xB000..=0xEFFF => self.write_chr(register, value),
+            _ if self.vrc2 => {}
+            0xF000 => self.irq.write_latch_low(value),
+            0xF001 => self.irq.write_latch_high(value),
+            0xF002 => self.irq.write_control(value),
+            _ => self.irq.acknowledge(),
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vrc4;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::{Mapper, Mirroring};
+
+    fn create(mapper: u16, submapper: u8) -> Box<dyn Mapper> {
+        let prg: Vec<u8> = (0..0x40000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..0x40000).map(|i| (i / 0x400) as u8).collect();
+        let mut info = test_info(mapper, 0x40000, 0x40000, 0x2000);
+        info.submapper = submapper;
+        Vrc4::create(&info, prg, chr)
+    }
+
+    #[test]
+    pub fn test_prg_banks() {
+        let mut vrc = create(21, 1);
+        vrc.cpu_write(0x8000, 3);
+        vrc.cpu_write(0xA000, 4);
+        assert_eq!(vrc.cpu_read(0x8000), 3);
+        assert_eq!(vrc.cpu_read(0xA000), 4);
+        assert_eq!(vrc.cpu_read(0xC000), 30);
+        assert_eq!(vrc.cpu_read(0xE000), 31);
+
+        // $9002 on VRC4a is A2
+        vrc.cpu_write(0x9004, 0x02);
+        assert_eq!(vrc.cpu_read(0x8000), 30);
+        assert_eq!(vrc.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    pub fn test_small_prg() {
+        let prg: Vec<u8> = (0..0x2000).map(|i| (i >> 8) as u8).collect();
+        let mut vrc4 = Vrc4::create(&test_info(21, 0x2000, 0x2000, 0), prg, vec![0; 0x2000]);
+        for address in [0x8100, 0xA100, 0xC100, 0xE100] {
+            assert_eq!(vrc4.cpu_read(address), 0x01);
+        }
+    }
+
+    #[test]
+    pub fn test_wiring_variants() {
+        // Register $B003 (CHR bank 1 high) on each wiring
+        for (mapper, submapper, address) in [
+            (21, 1, 0xB006), (21, 2, 0xB0C0), (21, 0, 0xB006), (21, 0, 0xB0C0),
+            (23, 1, 0xB003), (23, 2, 0xB00C), (23, 0, 0xB00C),
+            (25, 1, 0xB003), (25, 2, 0xB00C), (25, 0, 0xB003),
+        ] {
+            let mut vrc = create(mapper, submapper);
+            vrc.cpu_write(address, 0x01);
+            assert_eq!(vrc.ppu_read(0x0400), 16, "mapper {} submapper {}", mapper, submapper);
+        }
+    }
+
+    #[test]
+    pub fn test_chr_banks() {
+        let mut vrc = create(23, 1);
+        vrc.cpu_write(0xE002, 0x05);
+        vrc.cpu_write(0xE003, 0x02);
+        assert_eq!(vrc.ppu_read(0x1C00), 0x25);
+
+        // VRC2a drops the low bit
+        let mut vrc = create(22, 0);
+        vrc.cpu_write(0xB000, 0x05);
+        assert_eq!(vrc.ppu_read(0x0000), 2);
+    }
+
+    #[test]
+    pub fn test_mirroring() {
+        let mut vrc = create(25, 1);
+        vrc.cpu_write(0x9000, 1);
+        assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+        vrc.cpu_write(0x9000, 3);
+        assert_eq!(vrc.mirroring(), Mirroring::SingleScreenUpper);
+
+        let mut vrc = create(23, 3);
+        vrc.cpu_write(0x9000, 3);
+        assert_eq!(vrc.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut vrc = create(25, 2);
+        vrc.cpu_write(0xF000, 0x0E);
+        vrc.cpu_write(0xF008, 0x0F);
+        vrc.cpu_write(0xF004, 0x06);
+        vrc.cpu_tick();
+        assert!(!vrc.irq());
+        vrc.cpu_tick();
+        assert!(vrc.irq());
+        vrc.cpu_write(0xF00C, 0);
+        assert!(!vrc.irq());
+
+        // VRC2 has no IRQ counter
+        let mut vrc = create(23, 3);
+        vrc.cpu_write(0xF000, 0x0F);
+        vrc.cpu_write(0xF001, 0x0F);
+        vrc.cpu_write(0xF002, 0x06);
+        vrc.cpu_tick();
+        assert!(!vrc.irq());
+    }
+}
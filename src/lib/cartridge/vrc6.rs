@@ -0,0 +1,401 @@
+use super::{CartridgeInfo, Mapper, Mirroring};
+use super::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+/// Output level per step of the summed expansion channels, matched to the APU pulses
+const AUDIO_SCALE: f32 = 0.00752;
+
+/// VRC6 pulse channel with 8 duty cycles and a digitized mode
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    /// Ignore the duty cycle and output the volume constantly
+    digitized: bool,
+    period: u16,
+    enabled: bool,
+    divider: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse { volume: 0, duty: 0, digitized: false, period: 0, enabled: false, divider: 0, step: 15 }
+    }
+
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.volume = value & 0x0F;
+                self.duty = (value >> 4) & 0x07;
+                self.digitized = value & 0x80 == 0x80;
+            }
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.enabled = value & 0x80 == 0x80;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.divider == 0 {
+            self.divider = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// VRC6 sawtooth channel, an accumulator that ramps up over 7 steps
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    divider: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Sawtooth {
+        Sawtooth { rate: 0, period: 0, enabled: false, divider: 0, step: 0, accumulator: 0 }
+    }
+
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.enabled = value & 0x80 == 0x80;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.period >> shift;
+        // The rate is added on every second clock, the 14th clock resets
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 0x01 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// Mappers 24 and 26, Konami VRC6.
+///
+/// Mapper 26 (VRC6b) swaps the A0 and A1 register select lines.
+/// Only the common CHR banking modes of $B003 are handled, nametables always
+/// come from CIRAM with the mirroring selected by bits 2-3.
+pub struct Vrc6 {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    swap_lines: bool,
+
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    /// PPU banking mode, mirroring and PRG RAM enable
+    banking: u8,
+    irq: VrcIrq,
+
+    pulses: [Pulse; 2],
+    saw: Sawtooth,
+    /// Halt and frequency scaling for all three channels
+    audio_control: u8,
+}
+
+impl Vrc6 {
+    pub fn create(info: &CartridgeInfo, prg: Vec<u8>, chr: Vec<u8>) -> Box<dyn Mapper> {
+        Box::new(Vrc6 {
+            prg,
+            prg_ram: vec![0; info.prg_ram_size + info.prg_nvram_size],
+            chr,
+            chr_ram: info.chr_rom_size == 0,
+            swap_lines: info.mapper == 26,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            banking: 0,
+            irq: VrcIrq::new(),
+            pulses: [Pulse::new(), Pulse::new()],
+            saw: Sawtooth::new(),
+            audio_control: 0,
+        })
+    }
+
+    /// Translate a CPU address to the canonical $x000-$x003 register number
+    fn register(&self, address: usize) -> usize {
+        let lines = address & 0x03;
+        let lines = if self.swap_lines { (lines >> 1) | (lines & 0x01) << 1 } else { lines };
+        (address & 0xF000) | lines
+    }
+
+    fn prg_offset(&self, address: usize) -> usize {
+        let offset = match address {
+            0x8000..=0xBFFF => self.prg_16k as usize * 2 * PRG_BANK_SIZE + (address & 0x3FFF),
+            0xC000..=0xDFFF => self.prg_8k as usize * PRG_BANK_SIZE + (address & 0x1FFF),
+            _ => self.prg.len() - PRG_BANK_SIZE + (address & 0x1FFF),
+        };
+        offset % self.prg.len()
+    }
+
+    fn chr_offset(&self, address: usize) -> usize {
+        let slot = (address >> 10) & 0x07;
+        let bank = match (self.banking & 0x03, slot) {
+            (0, _) => self.chr_banks[slot] as usize,
+            (1, _) => self.chr_banks[slot >> 1] as usize * 2 + (slot & 0x01),
+            (_, 0..=3) => self.chr_banks[slot] as usize,
+            (_, _) => self.chr_banks[4 + ((slot - 4) >> 1)] as usize * 2 + (slot & 0x01),
+        };
+        (bank * CHR_BANK_SIZE + (address & 0x03FF)) % self.chr.len()
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.banking & 0x80 == 0x80 && !self.prg_ram.is_empty()
+    }
+
+    /// Divider shift from the $9003 frequency scaling bits
+    fn frequency_shift(&self) -> u8 {
+        if self.audio_control & 0x04 == 0x04 {
+            8
+        } else if self.audio_control & 0x02 == 0x02 {
+            4
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek(&self, address: usize) -> u8 {
+        if address >= 0x8000 {
+            return self.prg[self.prg_offset(address)];
+        }
+        if address >= 0x6000 && self.prg_ram_enabled() {
+            return self.prg_ram[(address - 0x6000) % self.prg_ram.len()];
+        }
+        0
+    }
+
+    fn cpu_write(&mut self, address: usize, value: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        if address < 0x8000 {
+            if self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_16k = value & 0x0F,
+            0x9003 => self.audio_control = value & 0x07,
+            0x9000..=0x9002 => self.pulses[0].write(register & 0x03, value),
+            0xA000..=0xA002 => self.pulses[1].write(register & 0x03, value),
+            0xB000..=0xB002 => self.saw.write(register & 0x03, value),
+            0xB003 => self.banking = value,
+            0xC000..=0xC003 => self.prg_8k = value & 0x1F,
+            0xD000..=0xE003 => {
+                let bank = ((register >> 12) - 0x0D) * 4 + (register & 0x03);
+                self.chr_banks[bank] = value;
+            }
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: usize) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) {
+        if self.chr_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+        if self.audio_control & 0x01 == 0 {
+            let shift = self.frequency_shift();
+            self.pulses[0].clock(shift);
+            self.pulses[1].clock(shift);
+            self.saw.clock(shift);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        sum as f32 * AUDIO_SCALE
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.prg_ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vrc6;
+    use crate::cartridge::mapper::test_info;
+    use crate::cartridge::{Mapper, Mirroring};
+
+    fn create(mapper: u16) -> Box<dyn Mapper> {
+        let prg: Vec<u8> = (0..0x40000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..0x40000).map(|i| (i / 0x400) as u8).collect();
+        Vrc6::create(&test_info(mapper, 0x40000, 0x40000, 0x2000), prg, chr)
+    }
+
+    #[test]
+    pub fn test_prg_banks() {
+        let mut vrc6 = create(24);
+        vrc6.cpu_write(0x8000, 2);
+        vrc6.cpu_write(0xC000, 9);
+        assert_eq!(vrc6.cpu_read(0x8000), 4);
+        assert_eq!(vrc6.cpu_read(0xA000), 5);
+        assert_eq!(vrc6.cpu_read(0xC000), 9);
+        assert_eq!(vrc6.cpu_read(0xE000), 31);
+
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_read(0x6000), 0);
+        vrc6.cpu_write(0xB003, 0x80);
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    pub fn test_chr_banks_and_mirroring() {
+        let mut vrc6 = create(26);
+        // $D001 and $D002 trade places on VRC6b
+        vrc6.cpu_write(0xD002, 0x11);
+        vrc6.cpu_write(0xE003, 0x22);
+        assert_eq!(vrc6.ppu_read(0x0400), 0x11);
+        assert_eq!(vrc6.ppu_read(0x1C00), 0x22);
+
+        // 2K banks
+        vrc6.cpu_write(0xB003, 0x01 | 0x04);
+        assert_eq!(vrc6.ppu_read(0x0800), 0x22);
+        assert_eq!(vrc6.ppu_read(0x0C00), 0x23);
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut vrc6 = create(24);
+        vrc6.cpu_write(0xF000, 0xFE);
+        vrc6.cpu_write(0xF001, 0x06);
+        vrc6.cpu_tick();
+        assert!(!vrc6.irq());
+        vrc6.cpu_tick();
+        assert!(vrc6.irq());
+        vrc6.cpu_write(0xF002, 0);
+        assert!(!vrc6.irq());
+    }
+
+    #[test]
+    pub fn test_pulse() {
+        let mut vrc6 = create(24);
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // Digitized mode outputs the volume regardless of duty
+        vrc6.cpu_write(0x9000, 0x8F);
+        vrc6.cpu_write(0x9002, 0x80);
+        assert!(vrc6.audio_output() > 0.0);
+
+        // Duty 0 is high for one step in sixteen
+        vrc6.cpu_write(0x9000, 0x0F);
+        vrc6.cpu_write(0x9001, 0);
+        let mut high = 0;
+        for _ in 0..16 {
+            vrc6.cpu_tick();
+            if vrc6.audio_output() > 0.0 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 1);
+
+        // Halt freezes the channels
+        vrc6.cpu_write(0x9003, 0x01);
+        let level = vrc6.audio_output();
+        for _ in 0..16 {
+            vrc6.cpu_tick();
+            assert_eq!(vrc6.audio_output(), level);
+        }
+    }
+
+    #[test]
+    pub fn test_sawtooth() {
+        let mut vrc6 = create(24);
+        vrc6.cpu_write(0xB000, 0x10);
+        vrc6.cpu_write(0xB002, 0x80);
+        let mut levels = Vec::new();
+        for _ in 0..14 {
+            vrc6.cpu_tick();
+            levels.push(vrc6.audio_output());
+        }
+        // Six additions of 16 ramp the output up to 96 >> 3, then it resets
+        assert_eq!(levels[11], 12.0 * 0.00752);
+        assert_eq!(levels[13], 0.0);
+        assert!(levels.windows(2).take(12).all(|w| w[0] <= w[1]));
+    }
+}
@@ -0,0 +1,133 @@
+/// CPU cycles per scanline times 3, the prescaler counts down by 3 every cycle
+const PRESCALER_PERIOD: i16 = 341;
+
+/// IRQ counter shared by the Konami VRC4, VRC6 and VRC7.
+///
+/// In scanline mode a prescaler divides the CPU clock by 113.667 to
+/// approximate scanlines, in cycle mode the counter is clocked every CPU cycle.
+/// The counter counts up and fires when it overflows from $FF.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | (value & 0x0F);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | (value << 4);
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.pending = false;
+        self.enable_after_ack = value & 0x01 == 0x01;
+        self.enabled = value & 0x02 == 0x02;
+        self.cycle_mode = value & 0x04 == 0x04;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Clock once per CPU cycle
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += PRESCALER_PERIOD;
+            self.clock();
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VrcIrq;
+
+    #[test]
+    pub fn test_cycle_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFD);
+        irq.write_control(0x07);
+        irq.tick();
+        irq.tick();
+        assert!(!irq.pending());
+        irq.tick();
+        assert!(irq.pending());
+
+        irq.acknowledge();
+        assert!(!irq.pending());
+        for _ in 0..3 {
+            irq.tick();
+        }
+        assert!(irq.pending());
+    }
+
+    #[test]
+    pub fn test_scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFF);
+        irq.write_control(0x02);
+        // 341 / 3 rounded up
+        for _ in 0..113 {
+            irq.tick();
+        }
+        assert!(!irq.pending());
+        irq.tick();
+        assert!(irq.pending());
+
+        // Acknowledging without the E bit disables the counter
+        irq.acknowledge();
+        for _ in 0..1000 {
+            irq.tick();
+        }
+        assert!(!irq.pending());
+    }
+}
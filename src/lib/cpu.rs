@@ -1,24 +1,42 @@
 
 mod instruction;
-pub use instruction::{InstructionType, Instruction, AddressingMode};
-use crate::bus::Bus;
-
-#[derive(Copy, Clone)]
-enum StatusFlags {
-    /// carry flag
-    C = (1 << 0),
-    /// zero flag
-    Z = (1 << 1),
-    /// interrupt disable
-    I = (1 << 2),
-    /// decimal mode
-    D = (1 << 3),
-    /// break command
-    B = (1 << 4),
-    /// overflow flag
-    V = (1 << 5),
-    /// Negative flag
-    N = (1 << 6),
+mod status;
+mod cycle;
+mod trace;
+pub use trace::TraceHook;
+pub use instruction::{InstructionType, OpcodeInfo, AddressingMode, OPCODES, OPCODES_65C02};
+pub use status::Status;
+use crate::memory::CpuBus;
+use crate::disassembler::Disassembler;
+
+/// Page holding the stack
+const STACK_PAGE: usize = 0x0100;
+/// Address of the NMI vector
+const NMI_VECTOR: usize = 0xFFFA;
+/// Address of the reset vector
+const RESET_VECTOR: usize = 0xFFFC;
+/// Address of the IRQ and BRK vector
+const IRQ_VECTOR: usize = 0xFFFE;
+/// Cycles taken by a BRK, IRQ or NMI sequence
+const INTERRUPT_CYCLES: u8 = 7;
+/// An NMI detected while at least this many cycles of a BRK or IRQ
+/// sequence are left takes over its vector
+const HIJACK_CYCLES: u8 = 3;
+/// Interrupts are polled on the second to last cycle of an instruction,
+/// when this many cycles are left
+const POLL_CYCLES: u8 = 1;
+/// Magic constant ORed into A by XAA and LAX immediate, varying between chips
+pub const DEFAULT_MAGIC: u8 = 0xEE;
+
+/// CPU models sharing this core
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Variant {
+    /// NES CPU: an NMOS 6502 without decimal mode
+    Ricoh2A03,
+    /// NMOS 6502 with decimal mode, N, V and Z coming from the binary result
+    Nmos6502,
+    /// CMOS 65C02 with its extra instructions and the NMOS bugs fixed
+    Cmos65C02,
 }
 
 /// 6502 CPU emulator
@@ -31,51 +49,218 @@ pub struct CPU {
     y: u8,
     /// Program counter
     pc: usize,
-    /// Stack pointer
+    /// Stack pointer, offset into the stack page
     sp: usize,
     /// Processor status flag register
-    status: u8,
+    status: Status,
     /// Cycles left for current instruction
     pub cycles: u8,
     /// Address pointed by the addressing mode
     address: usize,
     /// Relative address for branching
     branch_address: usize,
+    /// NMI seen by the last poll, serviced when the instruction is done
+    nmi_pending: bool,
+    /// IRQ seen by the last poll
+    irq_pending: bool,
+    /// Cycles left when the current instruction polls the interrupt lines
+    poll_cycle: Option<u8>,
+    /// I flag as it was before CLI, SEI or PLP, which change it after polling
+    delayed_i: Option<bool>,
+    /// The cycles left belong to a BRK or IRQ sequence an NMI can hijack
+    hijackable: bool,
+    /// Stopped by a JAM opcode, only reset brings the CPU back
+    halted: bool,
+    /// Magic constant used by XAA
+    xaa_magic: u8,
+    /// Magic constant used by LAX immediate
+    lxa_magic: u8,
+    /// Run each bus access on its own cycle instead of all on the first
+    cycle_accurate: bool,
+    /// Cycle of the current instruction in cycle accurate mode, 0 between instructions
+    step: u8,
+    /// Instruction run by cycle accurate mode
+    opcode: &'static OpcodeInfo,
+    /// The sequence run by cycle accurate mode is an IRQ or NMI, not BRK
+    hardware_interrupt: bool,
+    /// Pointer or base address kept between cycles
+    pointer: usize,
+    /// Operand byte kept between cycles
+    data: u8,
+    variant: Variant,
+    /// Opcode table of the variant
+    opcodes: &'static [OpcodeInfo; 256],
+    /// ADC and SBC honour the D flag
+    decimal_enabled: bool,
+    /// Cycles run since the CPU was created
+    cycle_count: u64,
+    /// Called with a trace line before each instruction
+    trace: Option<TraceHook>,
 }
 
 impl CPU {
 
     // Create an instance of the CPU.
     pub fn new() -> CPU {
+        CPU::with_variant(Variant::Ricoh2A03)
+    }
+
+    /// Create a CPU of the given model
+    pub fn with_variant(variant: Variant) -> CPU {
         CPU {
             a: 0,
             x: 0,
             y: 0,
             pc: 0,
-            sp: 0x100,
-            status: 0,
+            sp: 0,
+            status: Status::U,
             cycles: 0,
             address: 0,
             branch_address: 0,
+            nmi_pending: false,
+            irq_pending: false,
+            poll_cycle: None,
+            delayed_i: None,
+            hijackable: false,
+            halted: false,
+            xaa_magic: DEFAULT_MAGIC,
+            lxa_magic: DEFAULT_MAGIC,
+            cycle_accurate: false,
+            step: 0,
+            opcode: &OPCODES[0],
+            hardware_interrupt: false,
+            pointer: 0,
+            data: 0,
+            variant,
+            opcodes: if variant == Variant::Cmos65C02 { &OPCODES_65C02 } else { &OPCODES },
+            decimal_enabled: variant != Variant::Ricoh2A03,
+            cycle_count: 0,
+            trace: None,
         }
     }
 
-    ///  Execute one clock cycle
-    pub fn tick(&mut self, bus: &mut Bus) {
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Choose between running all of an instruction on its first cycle and
+    /// spreading its bus accesses over its cycles like the real CPU, dummy
+    /// reads and writes included. Only switch between instructions.
+    /// The 65C02 always runs in the first mode.
+    pub fn set_cycle_accurate(&mut self, enabled: bool) {
+        self.cycle_accurate = enabled && self.variant != Variant::Cmos65C02;
+    }
+
+    pub fn cycle_accurate(&self) -> bool {
+        self.cycle_accurate
+    }
+
+    /// Set the constants the unstable XAA and LAX immediate opcodes OR into A.
+    /// Common values are $00, $EE and $FF depending on the chip and its temperature.
+    pub fn set_magic_constants(&mut self, xaa: u8, lxa: u8) {
+        self.xaa_magic = xaa;
+        self.lxa_magic = lxa;
+    }
+
+    /// True after a JAM opcode locked up the CPU
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Put the registers in their power-on state and run the reset sequence
+    pub fn power_on<B: CpuBus>(&mut self, bus: &mut B) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.sp = 0;
+        self.status = Status::U;
+        self.reset(bus);
+    }
+
+    /// Run the 7 cycle reset sequence.
+    /// The CPU goes through the motions of an interrupt but the three stack
+    /// writes are turned into reads, so SP is decremented and memory is kept.
+    pub fn reset<B: CpuBus>(&mut self, bus: &mut B) {
+        // The next opcode is fetched twice and thrown away
+        bus.read(self.pc);
+        bus.read(self.pc);
+        for _ in 0..3 {
+            bus.read(STACK_PAGE | self.sp);
+            self.sp = self.sp.wrapping_sub(1) & 0xFF;
+        }
+        self.set_flag(Status::I);
+        self.pc = self.read_word(bus, RESET_VECTOR);
+        self.cycles = 7;
+        self.poll_cycle = None;
+        self.nmi_pending = false;
+        self.irq_pending = false;
+        self.delayed_i = None;
+        self.hijackable = false;
+        self.halted = false;
+        self.step = 0;
+    }
+
+    ///  Execute one clock cycle, then let the bus clock its devices
+    pub fn tick<B: CpuBus>(&mut self, bus: &mut B) {
+        self.run_cycle(bus);
+        bus.tick();
+    }
+
+    fn run_cycle<B: CpuBus>(&mut self, bus: &mut B) {
+        if self.halted {
+            return;
+        }
+        if self.trace.is_some() && self.cycles == 0 && self.step == 0
+            && !self.nmi_pending && !self.irq_pending {
+            self.trace_instruction(bus);
+        }
+        self.cycle_count += 1;
+        if self.cycle_accurate {
+            self.tick_cycle(bus);
+            return;
+        }
         if self.cycles > 0 {
+            if self.hijackable && self.cycles >= HIJACK_CYCLES && bus.nmi_edge() {
+                bus.acknowledge_nmi();
+                self.hijackable = false;
+                self.address = NMI_VECTOR;
+                self.pc = self.read_word(bus, NMI_VECTOR);
+            }
             self.cycles -= 1;
+            if Some(self.cycles) == self.poll_cycle {
+                self.poll_interrupts(bus);
+            }
+            return;
+        }
+        self.hijackable = false;
+        if self.nmi_pending {
+            self.nmi(bus);
+            return;
+        }
+        if self.irq_pending {
+            self.irq_pending = false;
+            self.interrupt(bus, IRQ_VECTOR, false);
             return;
         }
         let instruction = self.fetch_instruction(bus);
         self.cycles = instruction.cycles - 1; // Remove this cycle
+        self.poll_cycle = Some(POLL_CYCLES);
+        self.delayed_i = None;
 
-        let mut add_cycles = self.addressing_mode(&instruction.addressing_mode, bus);
-        add_cycles &= self.execute(&instruction, bus);
-        if add_cycles {
+        let page_crossed = self.addressing_mode(&instruction.addressing_mode, bus);
+        self.execute(instruction, bus);
+        if page_crossed && instruction.page_penalty {
             self.cycles += 1;
         }
 
-        instruction.print();
+        // Branches poll before their operand fetch, taken branches without
+        // a page crossing do not poll again
+        if let AddressingMode::Relative = instruction.addressing_mode {
+            self.poll_interrupts(bus);
+        }
+        if Some(self.cycles) == self.poll_cycle {
+            self.poll_interrupts(bus);
+        }
     }
 
     pub fn print_register(&self) {
@@ -92,7 +277,46 @@ impl CPU {
         self.pc = pc;
     }
 
-    pub fn print_page(&self, bus: &Bus, start: usize) {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    /// Stack pointer, offset into the stack page
+    pub fn sp(&self) -> u8 {
+        self.sp as u8
+    }
+
+    /// Load the A, X and Y registers and the stack pointer
+    pub fn set_registers(&mut self, a: u8, x: u8, y: u8, sp: u8) {
+        self.a = a;
+        self.x = x;
+        self.y = y;
+        self.sp = sp as usize;
+    }
+
+    /// Processor status register
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Replace the processor status. The unused bit stays set and B is dropped.
+    pub fn set_status(&mut self, status: Status) {
+        self.status = Status::from_pulled(status.bits());
+    }
+
+    pub fn print_page<B: CpuBus>(&self, bus: &B, start: usize) {
         println!("Memory:");
         for i in 0..16 as usize {
             self.print_mem(bus, start + i*0x10);
@@ -100,52 +324,68 @@ impl CPU {
         }
     }
 
-    fn print_mem(&self, bus: &Bus, start: usize) {
+    fn print_mem<B: CpuBus>(&self, bus: &B, start: usize) {
         print!("${:#04x}:", start);
         for i in 0..16 as usize {
-            print!("  {:#04x}", bus.read(start + i));
+            print!("  {:#04x}", bus.peek(start + i));
         }
     }
 
-    pub fn print_instructions(&self, bus: &Bus) {
+    /// Print the instructions from PC on
+    pub fn print_instructions<B: CpuBus>(&self, bus: &B) {
         println!("Instructions:");
+        let disassembler = Disassembler::new(self.variant);
+        let mut address = self.pc;
         for i in 0..10 {
-            let instruction = Instruction::new(bus.read(self.pc - 5 + i));
-            if i == 5 {
-                print!("** ");
-            }
-            print!("${:#04x}: ", self.pc -5 + i);
-            instruction.print();
+            let line = disassembler.line(bus, address);
+            address += line.bytes.len();
+            println!("{}{}", if i == 0 { "** " } else { "   " }, line);
         }
     }
 
-    pub fn set_ram(&mut self, bus: &mut Bus, values: &Vec<u8>, start: usize) {
+    pub fn set_ram<B: CpuBus>(&mut self, bus: &mut B, values: &Vec<u8>, start: usize) {
         for i in 0..values.len() {
             bus.write(start + i, values[i]);
         }
     }
 
     pub fn print_status(&self) {
-        println!("C: {}, Z: {}, I: {}, D: {}, B: {}, V: {}, N: {}",
-               self.check_flag(StatusFlags::C) as u8,
-               self.check_flag(StatusFlags::Z) as u8,
-               self.check_flag(StatusFlags::I) as u8,
-               self.check_flag(StatusFlags::D) as u8,
-               self.check_flag(StatusFlags::B) as u8,
-               self.check_flag(StatusFlags::V) as u8,
-               self.check_flag(StatusFlags::N) as u8
+        println!("C: {}, Z: {}, I: {}, D: {}, V: {}, N: {}",
+               self.check_flag(Status::C) as u8,
+               self.check_flag(Status::Z) as u8,
+               self.check_flag(Status::I) as u8,
+               self.check_flag(Status::D) as u8,
+               self.check_flag(Status::V) as u8,
+               self.check_flag(Status::N) as u8
             )
     }
 
+    /// Read a little endian 16 bit value
+    fn read_word<B: CpuBus>(&self, bus: &mut B, address: usize) -> usize {
+        bus.read(address) as usize | (bus.read((address + 1) & 0xFFFF) as usize) << 8
+    }
+
+    /// Push a byte to the stack
+    fn push<B: CpuBus>(&mut self, bus: &mut B, value: u8) {
+        bus.write(STACK_PAGE | self.sp, value);
+        self.sp = self.sp.wrapping_sub(1) & 0xFF;
+    }
+
+    /// Pull a byte from the stack
+    fn pull<B: CpuBus>(&mut self, bus: &mut B) -> u8 {
+        self.sp = (self.sp + 1) & 0xFF;
+        bus.read(STACK_PAGE | self.sp)
+    }
+
     /// Fetch memory pointed by program counter
-    fn fetch_instruction(&mut self, bus: &Bus) -> Instruction {
-        let ret = Instruction::new(bus.read(self.pc));
-        self.pc += 1;
+    fn fetch_instruction<B: CpuBus>(&mut self, bus: &mut B) -> &'static OpcodeInfo {
+        let ret = &self.opcodes[bus.read(self.pc) as usize];
+        self.pc = (self.pc + 1) & 0xFFFF;
         ret
     }
 
     /// Execute operation related to addressing mode.
-    fn addressing_mode(&mut self, mode: &AddressingMode, bus: &Bus) -> bool {
+    fn addressing_mode<B: CpuBus>(&mut self, mode: &AddressingMode, bus: &mut B) -> bool {
         match mode {
             AddressingMode::Immediate => {
                 return self.immediate();
@@ -186,179 +426,210 @@ impl CPU {
             AddressingMode::Accumulator => {
                 return false;
             }
+            AddressingMode::ZeroPageIndirect => {
+                return self.zero_page_indirect(bus);
+            }
+            AddressingMode::AbsoluteIndexedIndirect => {
+                return self.absolute_indexed_indirect(bus);
+            }
         }
     }
 
     /// Execute instruction.
-    fn execute(&mut self, instruction: &Instruction, bus: &mut Bus) -> bool {
-        match instruction.itype {
+    fn execute<B: CpuBus>(&mut self, instruction: &OpcodeInfo, bus: &mut B) {
+        match instruction.mnemonic {
             InstructionType::ADC => {
-                return self.adc(bus);
+                self.adc(bus);
             }
             InstructionType::AND => {
-                return self.and(bus);
-            }
-            InstructionType::ASL => {
-                return self.asl(&instruction.addressing_mode, bus);
+                self.and(bus);
             }
-            InstructionType::BCC => {
-                return self.bcc();
-            }
-            InstructionType::BCS => {
-                return self.bcs();
+            InstructionType::BCC | InstructionType::BCS | InstructionType::BEQ
+                | InstructionType::BMI | InstructionType::BNE | InstructionType::BPL
+                | InstructionType::BVC | InstructionType::BVS | InstructionType::BRA => {
+                let taken = self.branch_taken(instruction.mnemonic);
+                self.branch_if(taken);
             }
-            InstructionType::BEQ => {
-                return self.beq();
+            InstructionType::ASL | InstructionType::LSR | InstructionType::ROL
+                | InstructionType::ROR | InstructionType::INC | InstructionType::DEC
+                | InstructionType::SLO | InstructionType::RLA | InstructionType::SRE
+                | InstructionType::RRA | InstructionType::DCP | InstructionType::ISC
+                | InstructionType::TRB | InstructionType::TSB => {
+                self.read_modify_write(instruction, bus);
             }
             InstructionType::BIT => {
-                return self.bit(bus);
-            }
-            InstructionType::BMI => {
-                return self.bmi();
-            }
-            InstructionType::BNE => {
-                return self.bne();
-            }
-            InstructionType::BPL => {
-                return self.bpl();
+                self.bit(&instruction.addressing_mode, bus);
             }
             InstructionType::BRK => {
-                return false;
-            }
-            InstructionType::BVC => {
-                return self.bvc();
-            }
-            InstructionType::BVS => {
-                return self.bvs();
+                self.brk(bus);
             }
             InstructionType::CLC => {
-                return self.clc();
+                self.clc();
             }
             InstructionType::CLD => {
-                return self.cld();
+                self.cld();
             }
             InstructionType::CLI => {
-                return self.cli();
+                self.cli();
             }
             InstructionType::CLV => {
-                return self.clv();
+                self.clv();
             }
             InstructionType::CMP => {
-                return self.cmp(bus);
+                self.cmp(bus);
             }
             InstructionType::CPX => {
-                return self.cpx(bus);
+                self.cpx(bus);
             }
             InstructionType::CPY => {
-                return self.cpy(bus);
-            }
-            InstructionType::DEC => {
-                return self.dec(bus);
+                self.cpy(bus);
             }
             InstructionType::DEX => {
-                return self.dex();
+                self.dex();
             }
             InstructionType::DEY => {
-                return self.dey();
+                self.dey();
             }
             InstructionType::EOR => {
-                return self.eor(bus);
-            }
-            InstructionType::INC => {
-                return self.inc(bus);
+                self.eor(bus);
             }
             InstructionType::INX => {
-                return self.inx();
+                self.inx();
             }
             InstructionType::INY => {
-                return self.iny();
+                self.iny();
             }
             InstructionType::JMP => {
-                return self.jmp();
+                self.jmp();
             }
             InstructionType::JSR => {
-                return self.jsr(bus);
+                self.jsr(bus);
             }
             InstructionType::LDA => {
-                return self.lda(bus);
+                self.lda(bus);
             }
             InstructionType::LDX => {
-                return self.ldx(bus);
+                self.ldx(bus);
             }
             InstructionType::LDY => {
-                return self.ldy(bus);
-            }
-            InstructionType::LSR => {
-                return self.lsr(&instruction.addressing_mode, bus);
-            }
-            InstructionType::NOP => {
-                return false;
+                self.ldy(bus);
             }
+            InstructionType::NOP => {}
             InstructionType::ORA => {
-                return self.ora(bus);
+                self.ora(bus);
             }
             InstructionType::PHA => {
-                return self.pha(bus);
+                self.pha(bus);
             }
             InstructionType::PHP => {
-                return self.php(bus);
+                self.php(bus);
             }
             InstructionType::PLA => {
-                return self.pla(bus);
+                self.pla(bus);
             }
             InstructionType::PLP => {
-                return self.plp(bus);
-            }
-            InstructionType::ROL => {
-                return self.rol(&instruction.addressing_mode, bus);
-            }
-            InstructionType::ROR => {
-                return self.ror(&instruction.addressing_mode, bus);
+                self.plp(bus);
             }
             InstructionType::RTI => {
-                return self.rti(bus);
+                self.rti(bus);
             }
             InstructionType::RTS => {
-                return self.rts(bus);
+                self.rts(bus);
             }
             InstructionType::SBC => {
-                return self.sbc(bus);
+                self.sbc(bus);
             }
             InstructionType::SEC => {
-                return self.sec();
+                self.sec();
             }
             InstructionType::SED => {
-                return self.sed();
+                self.sed();
             }
             InstructionType::SEI => {
-                return self.sei();
+                self.sei();
             }
             InstructionType::STA => {
-                return self.sta(bus);
+                self.sta(bus);
             }
             InstructionType::STX => {
-                return self.stx(bus);
+                self.stx(bus);
             }
             InstructionType::STY => {
-                return self.sty(bus);
+                self.sty(bus);
             }
             InstructionType::TAX => {
-                return self.tax();
+                self.tax();
             }
             InstructionType::TAY => {
-                return self.tay();
+                self.tay();
             }
             InstructionType::TSX => {
-                return self.tsx();
+                self.tsx();
             }
             InstructionType::TXA => {
-                return self.txa();
+                self.txa();
             }
             InstructionType::TXS => {
-                return self.txs();
+                self.txs();
             }
             InstructionType::TYA => {
-                return self.tya();
+                self.tya();
+            }
+            InstructionType::ALR => {
+                self.alr(bus);
+            }
+            InstructionType::ANC => {
+                self.anc(bus);
+            }
+            InstructionType::ARR => {
+                self.arr(bus);
+            }
+            InstructionType::AXS => {
+                self.axs(bus);
+            }
+            InstructionType::JAM => {
+                self.jam();
+            }
+            InstructionType::LAS => {
+                self.las(bus);
+            }
+            InstructionType::LAX => {
+                self.lax(&instruction.addressing_mode, bus);
+            }
+            InstructionType::SAX => {
+                self.sax(bus);
+            }
+            InstructionType::SHA => {
+                self.sha(bus);
+            }
+            InstructionType::SHX => {
+                self.shx(bus);
+            }
+            InstructionType::SHY => {
+                self.shy(bus);
+            }
+            InstructionType::TAS => {
+                self.tas(bus);
+            }
+            InstructionType::XAA => {
+                self.xaa(bus);
+            }
+            InstructionType::PHX => {
+                self.push(bus, self.x);
+            }
+            InstructionType::PHY => {
+                self.push(bus, self.y);
+            }
+            InstructionType::PLX => {
+                self.x = self.pull(bus);
+                self.set_value_flags(self.x);
+            }
+            InstructionType::PLY => {
+                self.y = self.pull(bus);
+                self.set_value_flags(self.y);
+            }
+            InstructionType::STZ => {
+                bus.write(self.address, 0);
             }
         }
     }
@@ -373,29 +644,26 @@ impl CPU {
         (res < 0, (res & 0xFF) as u8)
     }
 
-
-    fn set_flag(&mut self, flag: StatusFlags) {
-        self.status |= flag as u8;
+    fn set_flag(&mut self, flag: Status) {
+        self.status.insert(flag);
     }
 
-    fn check_flag(&self, flag: StatusFlags) -> bool {
-        return self.status & flag as u8 == flag as u8;
+    fn check_flag(&self, flag: Status) -> bool {
+        return self.status.contains(flag);
     }
 
-    fn clear_flag(&mut self, flag: StatusFlags) {
-        if self.check_flag(flag) {
-            self.status ^= flag as u8;
-        }
+    fn clear_flag(&mut self, flag: Status) {
+        self.status.remove(flag);
     }
 
     fn set_value_flags(&mut self, value: u8) {
-        self.clear_flag(StatusFlags::N);
-        self.clear_flag(StatusFlags::Z);
+        self.clear_flag(Status::N);
+        self.clear_flag(Status::Z);
 
         if value == 0 {
-            self.set_flag(StatusFlags::Z);
+            self.set_flag(Status::Z);
         } else if value & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::N);
+            self.set_flag(Status::N);
         }
     }
 
@@ -403,7 +671,120 @@ impl CPU {
         self.set_value_flags(self.a);
     }
 
-    fn get_value(&mut self, mode: &AddressingMode, bus: &Bus) -> u8 {
+    /// A + value + C, setting C, V, N and Z. SBC adds the complement.
+    fn add_to_accumulator(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + self.check_flag(Status::C) as u16;
+        let res = (sum & 0xFF) as u8;
+        self.status.set_carry(sum > 0xFF);
+        self.status.set_overflow((self.a ^ res) & (value ^ res) & 0x80 != 0);
+        self.a = res;
+        self.set_accumulator_flags();
+    }
+
+    /// ADC, in BCD when the variant has decimal mode and D is set
+    fn add_with_carry(&mut self, value: u8) {
+        if self.decimal_enabled && self.check_flag(Status::D) {
+            self.decimal_add(value);
+        } else {
+            self.add_to_accumulator(value);
+        }
+    }
+
+    /// SBC, in BCD when the variant has decimal mode and D is set
+    fn subtract_from_accumulator(&mut self, value: u8) {
+        if self.decimal_enabled && self.check_flag(Status::D) {
+            self.decimal_subtract(value);
+        } else {
+            self.add_to_accumulator(!value);
+        }
+    }
+
+    /// BCD addition. The NMOS 6502 sets Z from the binary sum and N and V
+    /// from the sum before the high digit is adjusted, the 65C02 sets N and Z
+    /// from the result and takes one more cycle.
+    fn decimal_add(&mut self, value: u8) {
+        let a = self.a as i16;
+        let b = value as i16;
+        let binary = (a + b + self.check_flag(Status::C) as i16) as u8;
+
+        let mut low = (a & 0x0F) + (b & 0x0F) + self.check_flag(Status::C) as i16;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) + (b & 0xF0) + low;
+        let unadjusted = sum as u8;
+        self.status.set_overflow((a as u8 ^ unadjusted) & (value ^ unadjusted) & 0x80 != 0);
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.status.set_carry(sum >= 0x100);
+        self.a = sum as u8;
+
+        if self.variant == Variant::Cmos65C02 {
+            self.set_accumulator_flags();
+            self.cycles += 1;
+        } else {
+            self.status.set_zero(binary == 0);
+            self.status.set_negative(unadjusted & 0x80 == 0x80);
+        }
+    }
+
+    /// BCD subtraction. C and V always come from the binary difference, on
+    /// the NMOS 6502 N and Z do too.
+    fn decimal_subtract(&mut self, value: u8) {
+        let a = self.a as i16;
+        let b = value as i16;
+        let borrow = 1 - self.check_flag(Status::C) as i16;
+        let low = (a & 0x0F) - (b & 0x0F) - borrow;
+
+        let mut res;
+        if self.variant == Variant::Cmos65C02 {
+            res = a - b - borrow;
+            if res < 0 {
+                res -= 0x60;
+            }
+            if low < 0 {
+                res -= 0x06;
+            }
+        } else {
+            let low = if low < 0 { ((low - 0x06) & 0x0F) - 0x10 } else { low };
+            res = (a & 0xF0) - (b & 0xF0) + low;
+            if res < 0 {
+                res -= 0x60;
+            }
+        }
+
+        self.add_to_accumulator(!value);
+        self.a = res as u8;
+        if self.variant == Variant::Cmos65C02 {
+            self.set_accumulator_flags();
+            self.cycles += 1;
+        }
+    }
+
+    /// Shift left, bit 7 goes to C and carry_in to bit 0
+    fn shift_left(&mut self, value: u8, carry_in: bool) -> u8 {
+        let res = (value << 1) | carry_in as u8;
+        self.status.set_carry(value & 0x80 == 0x80);
+        self.set_value_flags(res);
+        return res;
+    }
+
+    /// Shift right, bit 0 goes to C and carry_in to bit 7
+    fn shift_right(&mut self, value: u8, carry_in: bool) -> u8 {
+        let res = (value >> 1) | ((carry_in as u8) << 7);
+        self.status.set_carry(value & 0x01 == 0x01);
+        self.set_value_flags(res);
+        return res;
+    }
+
+    /// Compare a register with a value, setting C, N and Z
+    fn compare_value(&mut self, register: u8, value: u8) {
+        self.status.set_carry(register >= value);
+        self.set_value_flags(register.wrapping_sub(value));
+    }
+
+    fn get_value<B: CpuBus>(&mut self, mode: &AddressingMode, bus: &mut B) -> u8 {
         match mode {
             AddressingMode::Accumulator => {
                 return self.a;
@@ -414,7 +795,7 @@ impl CPU {
         }
     }
 
-    fn store_value(&mut self, mode: &AddressingMode, bus: &mut Bus, value: u8) {
+    fn store_value<B: CpuBus>(&mut self, mode: &AddressingMode, bus: &mut B, value: u8) {
         match mode {
             AddressingMode::Accumulator => {
                 self.a = value;
@@ -428,586 +809,1240 @@ impl CPU {
     /* Different addressing modes: */
     fn immediate(&mut self) -> bool {
         self.address = self.pc;
-        self.pc += 1;
+        self.pc = (self.pc + 1) & 0xFFFF;
         return false;
     }
 
-    fn zero_page(&mut self, bus: &Bus) -> bool {
+    fn zero_page<B: CpuBus>(&mut self, bus: &mut B) -> bool {
         self.address = bus.read(self.pc) as usize;
-        self.pc += 1;
+        self.pc = (self.pc + 1) & 0xFFFF;
         return false;
     }
 
-    fn zero_page_x(&mut self, bus: &Bus) -> bool {
-        self.address = ((bus.read(self.pc) + self.x) & 0xFF) as usize;
-        self.pc += 1;
+    fn zero_page_x<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        self.address = bus.read(self.pc).wrapping_add(self.x) as usize;
+        self.pc = (self.pc + 1) & 0xFFFF;
         return false;
     }
 
-    fn zero_page_y(&mut self, bus: &Bus) -> bool {
-        self.address = (bus.read(self.pc) as usize + self.y as usize) & 0xFF;
-        self.pc += 1;
+    fn zero_page_y<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        self.address = bus.read(self.pc).wrapping_add(self.y) as usize;
+        self.pc = (self.pc + 1) & 0xFFFF;
         return false;
     }
 
-    fn absolute(&mut self, bus: &Bus) -> bool {
-        self.address = bus.read(self.pc) as usize | ((bus.read(self.pc + 1) as usize) << 8);
-        self.pc += 2;
+    fn absolute<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        self.address = self.read_word(bus, self.pc);
+        self.pc = (self.pc + 2) & 0xFFFF;
         return false;
     }
 
-    fn absolute_x(&mut self, bus: &Bus) -> bool {
-        self.absolute(bus);
+    /// Add an index to the address, returning true when a page is crossed
+    fn index(&mut self, index: u8) -> bool {
         let prev = self.address;
-        self.address += self.x as usize;
-        if (prev & 0xFF00) != (self.address & 0xFF00) {
-            return true;
-        }
-        return false;
+        self.address = (self.address + index as usize) & 0xFFFF;
+        return (prev & 0xFF00) != (self.address & 0xFF00);
+    }
+
+    /// Pointer stored in page zero. The high byte of a pointer at $FF comes from $00.
+    fn zero_page_pointer<B: CpuBus>(&self, bus: &mut B, pointer: u8) -> usize {
+        bus.read(pointer as usize) as usize | (bus.read(pointer.wrapping_add(1) as usize) as usize) << 8
     }
 
-    fn absolute_y(&mut self, bus: &Bus) -> bool {
+    fn absolute_x<B: CpuBus>(&mut self, bus: &mut B) -> bool {
         self.absolute(bus);
-        let prev = self.address;
-        self.address += self.y as usize;
-        if (prev & 0xFF00) != (self.address & 0xFF00) {
-            return true;
-        }
-        return false;
+        return self.index(self.x);
     }
 
-    fn indirect(&mut self, bus: &Bus) -> bool {
-        let address = bus.read(self.pc) as usize | ((bus.read(self.pc + 1) as usize) << 8);
-        let mut inc: usize = 1;
-        if address & 0xFF == 0xFF {
-            inc = 0;
-        }
-        self.address = bus.read(address) as usize | ((bus.read(address + inc) as usize) << 8);
-        self.pc += 2;
-        return false;
+    fn absolute_y<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        self.absolute(bus);
+        return self.index(self.y);
     }
 
-    fn indirect_x(&mut self, bus: &Bus) -> bool {
-        let base = bus.read(self.pc) as usize;
-        let base_x = base + self.x as usize;
-        self.address = bus.read(base_x) as usize | (bus.read(base_x + 1) as usize) << 8;
+    /// JMP ($xxxx). The NMOS 6502 does not carry into the pointer's high
+    /// byte, so JMP ($10FF) reads its target from $10FF and $1000.
+    fn indirect<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        let address = self.read_word(bus, self.pc);
+        let high = if self.variant == Variant::Cmos65C02 {
+            (address + 1) & 0xFFFF
+        } else {
+            (address & 0xFF00) | ((address + 1) & 0xFF)
+        };
+        self.address = bus.read(address) as usize | ((bus.read(high) as usize) << 8);
+        self.pc = (self.pc + 2) & 0xFFFF;
+        return false;
+    }
 
+    fn indirect_x<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        let base = bus.read(self.pc);
+        self.address = self.zero_page_pointer(bus, base.wrapping_add(self.x));
+        self.pc = (self.pc + 1) & 0xFFFF;
         return false;
     }
 
-    fn indirect_y(&mut self, bus: &Bus) -> bool {
-        let base = bus.read(self.pc) as usize;
-        self.address = bus.read(base) as usize | (bus.read(base + 1) as usize) << 8;
-        let prev = self.address;
-        self.address += self.y as usize;
+    fn indirect_y<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        let base = bus.read(self.pc);
+        self.address = self.zero_page_pointer(bus, base);
+        self.pc = (self.pc + 1) & 0xFFFF;
+        return self.index(self.y);
+    }
 
-        if self.address & 0xFF00 == prev & 0xFF00 {
-            return false;
-        }
+    fn zero_page_indirect<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        let base = bus.read(self.pc);
+        self.address = self.zero_page_pointer(bus, base);
+        self.pc = (self.pc + 1) & 0xFFFF;
+        return false;
+    }
 
-        return true;
+    fn absolute_indexed_indirect<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        self.absolute(bus);
+        let pointer = (self.address + self.x as usize) & 0xFFFF;
+        self.address = self.read_word(bus, pointer);
+        return false;
     }
 
-    fn relative(&mut self, bus: &Bus) -> bool {
+    fn relative<B: CpuBus>(&mut self, bus: &mut B) -> bool {
         self.branch_address = bus.read(self.pc) as usize;
-        println!("Branch address: {:#04x}", self.branch_address);
-        self.pc += 1;
+        self.pc = (self.pc + 1) & 0xFFFF;
         if (self.branch_address & 0x80) == 0x80 {
             self.branch_address |= 0xFF00;
         }
-        println!("Branch address: {:#04x}", self.branch_address);
         return false;
     }
 
-    pub fn irq(&mut self, bus: &mut Bus) {
-        if !self.check_flag(StatusFlags::I) {
-            self.nmi(bus);
-        }
-    }
-
-    pub fn nmi(&mut self, bus: &mut Bus) {
-        bus.write(self.sp, ((self.pc & 0xFF00) >> 8) as u8);
-        bus.write(self.sp - 1, (self.pc & 0xFF) as u8);
-        self.sp -= 2;
-        self.clear_flag(StatusFlags::B);
-        self.set_flag(StatusFlags::I);
-
-        self.address = 0xFFEE;
-        self.pc = bus.read(self.address) as usize |
-                    (((bus.read(self.address + 1)) as usize) << 8);
-        self.cycles = 8;
-    }
-
-    /* Instruction implementations */
-    fn adc(&mut self, bus: &mut Bus) -> bool {
-        let mut val = bus.read(self.address);
-        if self.check_flag(StatusFlags::C) {
-            val += 1;
+    /// Sample the interrupt lines, deciding what runs after this instruction
+    fn poll_interrupts<B: CpuBus>(&mut self, bus: &mut B) {
+        if bus.nmi_edge() {
+            bus.acknowledge_nmi();
+            self.nmi_pending = true;
         }
-        let res = self.overflow_add(self.a, val);
-        if res.0 {
-            self.set_flag(StatusFlags::C);
+        let disabled = self.delayed_i.unwrap_or(self.check_flag(Status::I));
+        self.irq_pending = bus.irq() && !disabled;
+    }
+
+    /// Push PC and P and jump through a vector, taking this cycle and the
+    /// next 6. The pushed copy of P has B set for BRK alone.
+    fn interrupt<B: CpuBus>(&mut self, bus: &mut B, vector: usize, brk: bool) {
+        self.push(bus, ((self.pc & 0xFF00) >> 8) as u8);
+        self.push(bus, (self.pc & 0xFF) as u8);
+        self.push(bus, self.status.pushed(brk));
+        self.set_flag(Status::I);
+        if self.variant == Variant::Cmos65C02 {
+            self.clear_flag(Status::D);
         }
 
-        self.set_value_flags(res.1);
-
-        if (val & 0x80 == 0x80) && (res.1 & 0x80 == 0x80) && (self.a & 0x80 != 0x80)
-            || (val & 0x80 == 0) && (res.1 & 0x80 == 0) && (self.a & 0x80 != 0) {
-            self.set_flag(StatusFlags::V);
-        }
-        self.a = res.1;
-        return true;
+        self.address = vector;
+        self.pc = self.read_word(bus, vector);
+        self.cycles = INTERRUPT_CYCLES - 1;
+        self.hijackable = vector == IRQ_VECTOR;
+        // The handler's first instruction always runs before another interrupt
+        self.poll_cycle = None;
     }
 
-    fn sbc(&mut self, bus: &Bus) -> bool {
-        let mut val = bus.read(self.address);
-
-        if !self.check_flag(StatusFlags::C) {
-            val += 1;
+    /// Run the IRQ sequence unless interrupts are disabled
+    pub fn irq<B: CpuBus>(&mut self, bus: &mut B) {
+        if !self.check_flag(Status::I) {
+            self.interrupt(bus, IRQ_VECTOR, false);
         }
+    }
 
-        let res = self.overflow_subtract(self.a, val);
-        if res.0 {
-            self.set_flag(StatusFlags::C);
-        }
-        self.set_value_flags(res.1);
+    /// Run the NMI sequence
+    pub fn nmi<B: CpuBus>(&mut self, bus: &mut B) {
+        self.nmi_pending = false;
+        self.interrupt(bus, NMI_VECTOR, false);
+    }
 
-        if res.1 & 0x80 == 0x80 {
-            self.clear_flag(StatusFlags::C);
-        }
+    /* Instruction implementations */
+    fn adc<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = bus.read(self.address);
+        self.add_with_carry(val);
+    }
 
-        if (val & 0x80 == 0x80) && (res.1 & 0x80 == 0x80) && (self.a & 0x80 != 0x80)
-            || (val & 0x80 == 0) && (res.1 & 0x80 == 0) && (self.a & 0x80 != 0) {
-            self.set_flag(StatusFlags::V);
-        }
-        self.a = res.1;
-        return true;
+    fn sbc<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = bus.read(self.address);
+        self.subtract_from_accumulator(val);
     }
 
-    fn and(&mut self, bus: &mut Bus) -> bool {
+    fn and<B: CpuBus>(&mut self, bus: &mut B) {
         self.a &= bus.read(self.address);
         self.set_accumulator_flags();
-
-        return true;
     }
 
-    fn asl(&mut self, mode: &AddressingMode, bus: &mut Bus) -> bool {
-        let mut val = self.get_value(mode, bus);
+    /// Read, change and write back the operand. Flags and, for the
+    /// combined unofficial opcodes, A are updated by `modify`.
+    fn read_modify_write<B: CpuBus>(&mut self, instruction: &OpcodeInfo, bus: &mut B) {
+        let val = self.get_value(&instruction.addressing_mode, bus);
+        let res = self.modify(instruction.mnemonic, val);
+        self.store_value(&instruction.addressing_mode, bus, res);
+    }
 
-        if val & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::C);
-            val ^= 0x80;
+    /// ALU work of the read-modify-write instructions, returning the value written back
+    fn modify(&mut self, mnemonic: InstructionType, value: u8) -> u8 {
+        let carry = self.check_flag(Status::C);
+        match mnemonic {
+            InstructionType::ASL => {
+                return self.shift_left(value, false);
+            }
+            InstructionType::LSR => {
+                return self.shift_right(value, false);
+            }
+            InstructionType::ROL => {
+                return self.shift_left(value, carry);
+            }
+            InstructionType::ROR => {
+                return self.shift_right(value, carry);
+            }
+            InstructionType::INC => {
+                let res = value.wrapping_add(1);
+                self.set_value_flags(res);
+                return res;
+            }
+            InstructionType::DEC => {
+                let res = value.wrapping_sub(1);
+                self.set_value_flags(res);
+                return res;
+            }
+            InstructionType::SLO => {
+                let res = self.shift_left(value, false);
+                self.a |= res;
+                self.set_accumulator_flags();
+                return res;
+            }
+            InstructionType::RLA => {
+                let res = self.shift_left(value, carry);
+                self.a &= res;
+                self.set_accumulator_flags();
+                return res;
+            }
+            InstructionType::SRE => {
+                let res = self.shift_right(value, false);
+                self.a ^= res;
+                self.set_accumulator_flags();
+                return res;
+            }
+            InstructionType::RRA => {
+                let res = self.shift_right(value, carry);
+                self.add_with_carry(res);
+                return res;
+            }
+            InstructionType::DCP => {
+                let res = value.wrapping_sub(1);
+                self.compare_value(self.a, res);
+                return res;
+            }
+            InstructionType::ISC => {
+                let res = value.wrapping_add(1);
+                self.subtract_from_accumulator(res);
+                return res;
+            }
+            InstructionType::TRB => {
+                self.status.set_zero(self.a & value == 0);
+                return value & !self.a;
+            }
+            InstructionType::TSB => {
+                self.status.set_zero(self.a & value == 0);
+                return value | self.a;
+            }
+            _ => {
+                panic!("{} is not a read-modify-write instruction", mnemonic);
+            }
         }
-        val *= 2;
-
-        self.set_value_flags(val);
+    }
 
-        self.store_value(mode, bus, val);
+    /// Condition tested by a branch instruction
+    fn branch_taken(&self, mnemonic: InstructionType) -> bool {
+        match mnemonic {
+            InstructionType::BCC => {
+                return !self.check_flag(Status::C);
+            }
+            InstructionType::BCS => {
+                return self.check_flag(Status::C);
+            }
+            InstructionType::BEQ => {
+                return self.check_flag(Status::Z);
+            }
+            InstructionType::BMI => {
+                return self.check_flag(Status::N);
+            }
+            InstructionType::BNE => {
+                return !self.check_flag(Status::Z);
+            }
+            InstructionType::BPL => {
+                return !self.check_flag(Status::N);
+            }
+            InstructionType::BVC => {
+                return !self.check_flag(Status::V);
+            }
+            InstructionType::BVS => {
+                return self.check_flag(Status::V);
+            }
+            InstructionType::BRA => {
+                return true;
+            }
+            _ => {
+                return false;
+            }
+        }
+    }
 
-        return false;
+    fn brk<B: CpuBus>(&mut self, bus: &mut B) {
+        // The byte after BRK is skipped
+        self.pc = (self.pc + 1) & 0xFFFF;
+        self.interrupt(bus, IRQ_VECTOR, true);
     }
 
-    fn branch_if(&mut self, value: bool) -> bool {
+    fn branch_if(&mut self, value: bool) {
         if value {
             self.cycles += 1;
             let prev = self.pc;
-            self.pc += self.branch_address;
+            self.pc = (self.pc + self.branch_address) & 0xFFFF;
             if prev & 0xFF00 != self.pc & 0xFF00 {
                 self.cycles += 1;
+            } else {
+                self.poll_cycle = None;
             }
-            self.pc &= 0xFFFF;
         }
-        return false;
     }
 
-    fn bcs(&mut self) -> bool {
-        return self.branch_if(self.check_flag(StatusFlags::C));
+    fn bit<B: CpuBus>(&mut self, mode: &AddressingMode, bus: &mut B) {
+        let val = bus.read(self.address);
+        self.status.set_zero(self.a & val == 0);
+        // The 65C02's BIT #imm only sets Z
+        if *mode != AddressingMode::Immediate {
+            self.status.set_negative(val & 0x80 == 0x80);
+            self.status.set_overflow(val & 0x40 == 0x40);
+        }
     }
 
-    fn bcc(&mut self) -> bool {
-        return self.branch_if(!self.check_flag(StatusFlags::C));
+    fn clc(&mut self) {
+        self.clear_flag(Status::C);
     }
-    fn beq(&mut self) -> bool {
-        return self.branch_if(self.check_flag(StatusFlags::Z));
-    }
-
-    fn bit(&mut self, bus: &Bus) -> bool {
-        let res = self.a & bus.read(self.address);
-
-        self.set_value_flags(res);
 
-        if res & 0x40 == 0x40 {
-            self.set_flag(StatusFlags::V);
-        }
-
-        return false;
+    fn cli(&mut self) {
+        self.delayed_i = Some(self.check_flag(Status::I));
+        self.clear_flag(Status::I);
     }
 
-    fn bmi(&mut self) -> bool {
-        return self.branch_if(self.check_flag(StatusFlags::N));
+    fn cld(&mut self) {
+        self.clear_flag(Status::D);
     }
 
-    fn bne(&mut self) -> bool {
-        return self.branch_if(!self.check_flag(StatusFlags::Z));
+    fn clv(&mut self) {
+        self.clear_flag(Status::V);
     }
 
-    fn bpl(&mut self) -> bool {
-        return self.branch_if(!self.check_flag(StatusFlags::N));
+    fn compare<B: CpuBus>(&mut self, bus: &mut B, value: u8) {
+        let val = bus.read(self.address);
+        self.compare_value(value, val);
     }
 
-    fn bvc(&mut self) -> bool {
-        return self.branch_if(self.check_flag(StatusFlags::V));
+    fn cmp<B: CpuBus>(&mut self, bus: &mut B) {
+        self.compare(bus, self.a);
     }
 
-    fn bvs(&mut self) -> bool {
-        return self.branch_if(!self.check_flag(StatusFlags::V));
+    fn cpx<B: CpuBus>(&mut self, bus: &mut B) {
+        self.compare(bus, self.x);
     }
 
-    fn clc(&mut self) -> bool {
-        self.clear_flag(StatusFlags::C);
-        return false;
+    fn cpy<B: CpuBus>(&mut self, bus: &mut B) {
+        self.compare(bus, self.y);
     }
 
-    fn cli(&mut self) -> bool {
-        self.clear_flag(StatusFlags::I);
-        return false;
+    fn dex(&mut self) {
+        let res = self.overflow_subtract(self.x, 1);
+        self.x = res.1;
+        self.set_value_flags(res.1);
     }
-
-    fn cld(&mut self) -> bool {
-        self.clear_flag(StatusFlags::D);
-        return false;
+    fn dey(&mut self) {
+        let res = self.overflow_subtract(self.y, 1);
+        self.y = res.1;
+        self.set_value_flags(res.1);
     }
 
-    fn clv(&mut self) -> bool {
-        self.clear_flag(StatusFlags::V);
-        return false;
+    fn eor<B: CpuBus>(&mut self, bus: &mut B) {
+        self.a ^= bus.read(self.address);
+        self.set_accumulator_flags();
     }
 
-    fn compare(&mut self, bus: &Bus, value: u8) {
-        self.clear_flag(StatusFlags::C);
-        self.clear_flag(StatusFlags::Z);
-        self.clear_flag(StatusFlags::N);
+    fn inx(&mut self) {
+        let res = self.overflow_add(self.x, 1);
+        self.x = res.1;
+        self.set_value_flags(self.x);
+    }
 
-        if value >= bus.read(self.address) {
-            self.set_flag(StatusFlags::C);
-        }
-        if value == bus.read(self.address) {
-            self.set_flag(StatusFlags::Z);
-        }
-        if value & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::N);
-        }
+    fn iny(&mut self) {
+        let res = self.overflow_add(self.y, 1);
+        self.y = res.1;
+        self.set_value_flags(self.y);
     }
 
-    fn cmp(&mut self, bus: &Bus) -> bool {
-        self.compare(bus, self.a);
-        return true;
+    fn jmp(&mut self) {
+        self.pc = self.address;
     }
 
-    fn cpx(&mut self, bus: &Bus) -> bool {
-        self.compare(bus, self.x);
-        return true;
+    fn jsr<B: CpuBus>(&mut self, bus: &mut B) {
+        // The return address pushed is the last byte of the JSR
+        let ret = self.pc.wrapping_sub(1) & 0xFFFF;
+        self.push(bus, ((ret & 0xFF00) >> 8) as u8);
+        self.push(bus, (ret & 0xFF) as u8);
+
+        self.pc = self.address;
     }
 
-    fn cpy(&mut self, bus: &Bus) -> bool {
-        self.compare(bus, self.y);
-        return true;
+    fn lda<B: CpuBus>(&mut self, bus: &mut B) {
+        self.a = bus.read(self.address);
+        self.set_accumulator_flags();
     }
 
-    fn dec(&mut self, bus: &mut Bus) -> bool {
-        let res = self.overflow_subtract(bus.read(self.address), 1);
-        bus.write(self.address, res.1);
-        self.set_value_flags(res.1);
+    fn ldx<B: CpuBus>(&mut self, bus: &mut B) {
+        self.x = bus.read(self.address);
+        self.set_value_flags(self.x);
+    }
 
-        return false;
+    fn ldy<B: CpuBus>(&mut self, bus: &mut B) {
+        self.y = bus.read(self.address);
+        self.set_value_flags(self.y);
     }
 
-    fn dex(&mut self) -> bool {
-        let res = self.overflow_subtract(self.x, 1);
-        self.x = res.1;
-        self.set_value_flags(res.1);
+    fn ora<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = bus.read(self.address);
 
-        return false;
+        self.a |= val;
+        self.set_accumulator_flags();
     }
-    fn dey(&mut self) -> bool {
-        let res = self.overflow_subtract(self.y, 1);
-        self.y = res.1;
-        self.set_value_flags(res.1);
 
-        return false;
+    fn pha<B: CpuBus>(&mut self, bus: &mut B) {
+        self.push(bus, self.a);
     }
 
-    fn eor(&mut self, bus: &Bus) -> bool {
-        self.a ^= bus.read(self.address);
-        self.set_accumulator_flags();
-        return true;
+    fn php<B: CpuBus>(&mut self, bus: &mut B) {
+        self.push(bus, self.status.pushed(true));
     }
 
-    fn inc(&mut self, bus: &mut Bus) -> bool {
-        let res = self.overflow_add(bus.read(self.address), 1);
-        if res.1 == 0 {
-            self.set_flag(StatusFlags::Z);
-        } else if res.1 & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::N);
-        }
-        return false;
+    fn pla<B: CpuBus>(&mut self, bus: &mut B) {
+        self.a = self.pull(bus);
+        self.set_accumulator_flags();
     }
 
-    fn inx(&mut self) -> bool {
-        let res = self.overflow_add(self.x, 1);
-        self.x = res.1;
-        self.set_value_flags(self.x);
+    fn plp<B: CpuBus>(&mut self, bus: &mut B) {
+        self.delayed_i = Some(self.check_flag(Status::I));
+        self.status = Status::from_pulled(self.pull(bus));
+    }
 
-        return false;
+    fn rti<B: CpuBus>(&mut self, bus: &mut B) {
+        self.status = Status::from_pulled(self.pull(bus));
+        self.pc = self.pull(bus) as usize;
+        self.pc |= (self.pull(bus) as usize) << 8;
     }
 
-    fn iny(&mut self) -> bool {
-        let res = self.overflow_add(self.y, 1);
-        self.y = res.1;
-        self.set_value_flags(self.y);
-        return false;
+    fn rts<B: CpuBus>(&mut self, bus: &mut B) {
+        self.pc = self.pull(bus) as usize;
+        self.pc |= (self.pull(bus) as usize) << 8;
+        self.pc = (self.pc + 1) & 0xFFFF;
     }
 
-    fn jmp(&mut self) -> bool {
-        self.pc = self.address;
-        return false;
+    fn sec(&mut self) {
+        self.set_flag(Status::C);
     }
 
-    fn jsr(&mut self, bus: &mut Bus) -> bool {
-        self.pc += 1;
-        bus.write(self.sp, (((self.pc) & 0xFF00) >> 8) as u8);
-        bus.write(self.sp - 1, ((self.pc) & 0xFF) as u8);
-        self.sp -= 2;
+    fn sed(&mut self) {
+        self.set_flag(Status::D);
+    }
 
-        self.pc = self.address;
-        return false;
+    fn sei(&mut self) {
+        self.delayed_i = Some(self.check_flag(Status::I));
+        self.set_flag(Status::I);
     }
 
-    fn lda(&mut self, bus: &Bus) -> bool {
-        self.a = bus.read(self.address);
-        self.set_accumulator_flags();
-        return true;
+    fn sta<B: CpuBus>(&mut self, bus: &mut B) {
+        bus.write(self.address, self.a);
     }
 
-    fn ldx(&mut self, bus: &Bus) -> bool {
-        self.x = bus.read(self.address);
-        if self.x == 0 {
-            self.set_flag(StatusFlags::Z)
-        } else if self.x & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::N);
-        }
-        return true;
+    fn stx<B: CpuBus>(&mut self, bus: &mut B) {
+        bus.write(self.address, self.x);
     }
 
-    fn ldy(&mut self, bus: &Bus) -> bool {
-        self.y = bus.read(self.address);
-        self.set_value_flags(self.y);
-        return true;
+    fn sty<B: CpuBus>(&mut self, bus: &mut B) {
+        bus.write(self.address, self.y);
     }
 
-    fn lsr(&mut self, mode: &AddressingMode, bus: &mut Bus) -> bool {
-        let val= self.get_value(mode, bus);
+    fn tax(&mut self) {
+        self.x = self.a;
+        self.set_accumulator_flags();
+    }
 
-        if val == 0 {
-            self.set_flag(StatusFlags::C);
-            return false;
-        }
-        if val & 0x01 == 0x01 {
-            self.set_flag(StatusFlags::C);
-        } else {
-            self.clear_flag(StatusFlags::C);
-        }
+    fn tay(&mut self) {
+        self.y = self.a;
+        self.set_accumulator_flags();
+    }
 
-        let res = (((val as i16) >> 1) & 0xFF) as u8;
-        self.set_value_flags(res);
+    fn tsx(&mut self) {
+        self.x = self.sp as u8;
+        self.set_value_flags(self.x);
+    }
 
-        self.store_value(mode, bus, res & 0x7F);
-        return false;
+    fn txa(&mut self) {
+        self.a = self.x;
+        self.set_accumulator_flags();
     }
 
-    fn ora(&mut self, bus: &Bus) -> bool {
-        let val = bus.read(self.address);
+    fn txs(&mut self) {
+        self.sp = self.x as usize;
+    }
 
-        self.a |= val;
+    fn tya(&mut self) {
+        self.a = self.y;
         self.set_accumulator_flags();
-
-        return true;
     }
 
-    fn pha(&mut self, bus: &mut Bus) -> bool {
-        bus.write(self.sp, self.a);
-        self.sp -= 1;
-        return false;
+    /* Unofficial instructions */
+    fn alr<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = self.a & bus.read(self.address);
+        self.a = self.shift_right(val, false);
     }
 
-    fn php(&mut self, bus: &mut Bus) -> bool {
-        self.set_flag(StatusFlags::B);
-        bus.write(self.sp, self.status);
-        self.sp -= 1;
-        return false;
+    fn anc<B: CpuBus>(&mut self, bus: &mut B) {
+        self.a &= bus.read(self.address);
+        self.set_accumulator_flags();
+        self.status.set_carry(self.a & 0x80 == 0x80);
     }
 
-    fn pla(&mut self, bus: &mut Bus) -> bool {
-        self.a = bus.read(self.sp);
-        self.sp += 1;
+    fn arr<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = self.a & bus.read(self.address);
+        self.a = (val >> 1) | ((self.check_flag(Status::C) as u8) << 7);
         self.set_accumulator_flags();
-        return false;
+        // C and V come from bits 6 and 5 of the result, as if added in the ALU
+        self.status.set_carry(self.a & 0x40 == 0x40);
+        self.status.set_overflow(((self.a >> 6) ^ (self.a >> 5)) & 1 == 1);
     }
 
-    fn plp(&mut self, bus: &Bus) -> bool {
-        self.status = bus.read(self.sp);
-        self.sp += 1;
-        return false;
+    fn axs<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = bus.read(self.address);
+        let and = self.a & self.x;
+        self.status.set_carry(and >= val);
+        self.x = and.wrapping_sub(val);
+        self.set_value_flags(self.x);
     }
 
-    fn rol(&mut self, mode: &AddressingMode, bus: &mut Bus) -> bool {
+    fn jam(&mut self) {
+        self.halted = true;
+        self.cycles = 0;
+    }
 
-        let val = self.get_value(mode, bus);
-        let mut res = (((val as u16) << 1) & 0xFF) as u8;
+    fn las<B: CpuBus>(&mut self, bus: &mut B) {
+        let val = bus.read(self.address) & self.sp as u8;
+        self.a = val;
+        self.x = val;
+        self.sp = val as usize;
+        self.set_value_flags(val);
+    }
 
-        if self.check_flag(StatusFlags::C) {
-            res |= 1;
+    fn lax<B: CpuBus>(&mut self, mode: &AddressingMode, bus: &mut B) {
+        let mut val = bus.read(self.address);
+        if let AddressingMode::Immediate = mode {
+            val &= self.a | self.lxa_magic;
         }
+        self.a = val;
+        self.x = val;
+        self.set_value_flags(val);
+    }
 
-        if res == 0 {
-            self.set_flag(StatusFlags::Z)
-        } else if res & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::N);
-        }
+    fn sax<B: CpuBus>(&mut self, bus: &mut B) {
+        bus.write(self.address, self.a & self.x);
+    }
 
-        if val & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::C);
+    /// Store for SHA, SHX, SHY and TAS. The value is ANDed with the high byte
+    /// of the base address plus one and, when indexing crossed a page,
+    /// replaces the high byte of the address written to.
+    fn unstable_store<B: CpuBus>(&mut self, bus: &mut B, value: u8, index: u8) {
+        let address = self.address & 0xFFFF;
+        let base = address.wrapping_sub(index as usize) & 0xFFFF;
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        if (base ^ address) & 0xFF00 != 0 {
+            bus.write((address & 0xFF) | (value as usize) << 8, value);
         } else {
-            self.clear_flag(StatusFlags::C);
+            bus.write(address, value);
         }
+    }
 
-        self.store_value(mode, bus, res);
-        return false;
+    fn sha<B: CpuBus>(&mut self, bus: &mut B) {
+        self.unstable_store(bus, self.a & self.x, self.y);
     }
 
-    fn ror(&mut self, mode: &AddressingMode, bus: &mut Bus) -> bool {
+    fn shx<B: CpuBus>(&mut self, bus: &mut B) {
+        self.unstable_store(bus, self.x, self.y);
+    }
 
-        let val = self.get_value(mode, bus);
-        let mut res = (((val as i16) >> 1) & 0xFF) as u8;
+    fn shy<B: CpuBus>(&mut self, bus: &mut B) {
+        self.unstable_store(bus, self.y, self.x);
+    }
 
-        if self.check_flag(StatusFlags::C) {
-            res |= 0x80;
-        }
+    fn tas<B: CpuBus>(&mut self, bus: &mut B) {
+        self.sp = (self.a & self.x) as usize;
+        self.unstable_store(bus, self.a & self.x, self.y);
+    }
 
-        if res == 0 {
-            self.set_flag(StatusFlags::Z)
-        } else if res & 0x80 == 0x80 {
-            self.set_flag(StatusFlags::N);
-        }
+    fn xaa<B: CpuBus>(&mut self, bus: &mut B) {
+        self.a = (self.a | self.xaa_magic) & self.x & bus.read(self.address);
+        self.set_accumulator_flags();
+    }
+}
 
-        if val & 0x1 == 0x1 {
-            self.set_flag(StatusFlags::C);
-        } else {
-            self.clear_flag(StatusFlags::C);
+
+#[cfg(test)]
+mod tests {
+    use super::{CPU, OpcodeInfo, InstructionType, Variant};
+    use super::Status;
+    use crate::bus::{Bus, IrqSource};
+    use crate::cartridge::Cartridge;
+    use crate::memory::FlatMemory;
+
+    /// Bus with a 16K NROM cartridge with NMI at $9000, reset at $8000 and IRQ at $A000
+    fn bus_with_vectors() -> Bus {
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0];
+        rom.resize(16 + 0x4000, 0xEA);
+        rom[16 + 0x3FFA..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0xA0]);
+        let mut bus = Bus::new();
+        bus.cartridge = Cartridge::from_bytes(&rom).unwrap();
+        bus
+    }
+
+    #[test]
+    pub fn test_power_on_and_reset() {
+        let mut cpu = CPU::new();
+        let mut bus = bus_with_vectors();
+        cpu.a = 0x12;
+        cpu.power_on(&mut bus);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.sp, 0xFD);
+        assert_eq!(cpu.a, 0);
+        assert!(cpu.check_flag(Status::I));
+        assert_eq!(cpu.cycles, 7);
+
+        // A soft reset keeps the registers and moves SP down without writing
+        cpu.a = 0x12;
+        cpu.sp = 0x80;
+        cpu.pc = 0x1234;
+        cpu.status = Status::U;
+        bus.ram[0x17E] = 0x55;
+        cpu.reset(&mut bus);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.sp, 0x7D);
+        assert_eq!(cpu.a, 0x12);
+        assert!(cpu.check_flag(Status::I));
+        assert_eq!(bus.ram[0x17E], 0x55);
+    }
+
+    /// CPU running from $0200 in RAM with an empty stack
+    fn cpu_at_0200(bus: &mut Bus, program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.sp = 0xFD;
+        cpu.pc = 0x0200;
+        bus.ram[0x200..0x200 + program.len()].copy_from_slice(program);
+        cpu
+    }
+
+    fn finish_instruction(cpu: &mut CPU, bus: &mut Bus) {
+        while cpu.cycles > 0 {
+            cpu.tick(bus);
         }
+    }
 
-        self.store_value(mode, bus, res);
-        return false;
+    #[test]
+    pub fn test_brk() {
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x00]);
+        cpu.set_flag(Status::C);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0xA000);
+        assert_eq!(cpu.cycles, 6);
+        assert_eq!(cpu.sp, 0xFA);
+        assert_eq!(bus.ram[0x1FD], 0x02);
+        assert_eq!(bus.ram[0x1FC], 0x02);
+        assert_eq!(bus.ram[0x1FB], (Status::C | Status::B | Status::U).bits());
+        assert!(cpu.check_flag(Status::I));
     }
 
-    fn rti(&mut self, bus: &Bus) -> bool {
-        self.status = bus.read(self.sp);
-        self.pc = (bus.read(self.sp + 1) as usize) << 8 | bus.read(self.sp + 2) as usize;
-        self.sp += 3;
-        return false;
+    #[test]
+    pub fn test_irq_and_nmi() {
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[]);
+        cpu.set_flag(Status::I);
+        cpu.irq(&mut bus);
+        assert_eq!(cpu.pc, 0x0200);
+
+        cpu.clear_flag(Status::I);
+        cpu.irq(&mut bus);
+        assert_eq!(cpu.pc, 0xA000);
+        assert_eq!(bus.ram[0x1FB] & Status::B.bits(), 0);
+        assert_eq!(cpu.cycles, 6);
+
+        // NMI ignores the I flag
+        cpu.nmi(&mut bus);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(bus.ram[0x1F9], 0x00);
+        assert_eq!(bus.ram[0x1F8] & Status::B.bits(), 0);
+        assert!(cpu.check_flag(Status::I));
     }
 
-    fn rts(&mut self, bus: &Bus) -> bool {
-        self.pc = (bus.read(self.sp) as usize) << 8 | bus.read(self.sp + 1) as usize;
-        self.sp += 2;
-        return false;
+    #[test]
+    pub fn test_nmi_hijack() {
+        // An NMI during the first cycles of BRK takes over the vector
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x00]);
+        for _ in 0..4 {
+            cpu.tick(&mut bus);
+        }
+        bus.set_nmi(true);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(bus.ram[0x1FB] & Status::B.bits(), Status::B.bits());
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        // The NMI was consumed by the hijack
+        assert_eq!(cpu.sp, 0xFA);
+
+        // Once the vector is read the NMI waits for the handler's first instruction
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x00]);
+        for _ in 0..5 {
+            cpu.tick(&mut bus);
+        }
+        bus.set_nmi(true);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0xA000);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0xA001);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(cpu.sp, 0xF7);
     }
 
-    fn sec(&mut self) -> bool {
-        self.set_flag(StatusFlags::C);
-        return false;
+    #[test]
+    pub fn test_reset_during_interrupt() {
+        // A reset in the middle of BRK leaves no NMI hijack behind
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x00]);
+        cpu.tick(&mut bus);
+        cpu.tick(&mut bus);
+        cpu.reset(&mut bus);
+        bus.set_nmi(true);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x8000);
+        // The NMI is taken after the first instruction of the reset handler
+        finish_instruction(&mut cpu, &mut bus);
+        assert_eq!(run_until(&mut cpu, &mut bus, 0x9000), 2);
+
+        // Nor the I flag SEI had before, which would let an IRQ through
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x78]);
+        cpu.tick(&mut bus);
+        cpu.reset(&mut bus);
+        assert_eq!(cpu.delayed_i, None);
+    }
+
+    /// Run whole instructions until PC reaches an address, returning the number executed
+    fn run_until(cpu: &mut CPU, bus: &mut Bus, pc: usize) -> usize {
+        let mut count = 0;
+        while cpu.pc != pc {
+            cpu.tick(bus);
+            finish_instruction(cpu, bus);
+            count += 1;
+            assert!(count < 100, "PC never reached {:#06x}", pc);
+        }
+        count
     }
 
-    fn sed(&mut self) -> bool {
-        self.set_flag(StatusFlags::D);
-        return false;
+    #[test]
+    pub fn test_irq_line() {
+        // NOPs with interrupts enabled
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0xEA; 8]);
+        bus.set_irq(IrqSource::Dmc, true);
+        bus.set_irq(IrqSource::FrameCounter, true);
+        cpu.tick(&mut bus);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0xA000);
+
+        // The line stays low while any device holds it
+        bus.set_irq(IrqSource::Dmc, false);
+        assert!(bus.irq());
+        bus.set_irq(IrqSource::FrameCounter, false);
+        assert!(!bus.irq());
     }
 
-    fn sei(&mut self) -> bool {
-        self.set_flag(StatusFlags::I);
-        return false;
+    #[test]
+    pub fn test_nmi_edge() {
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0xEA; 8]);
+        bus.set_nmi(true);
+        run_until(&mut cpu, &mut bus, 0x9000);
+
+        // Holding the line does not trigger again
+        cpu.pc = 0x0200;
+        cpu.tick(&mut bus);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x0202);
+
+        // This NOP already polled, so the next one runs before the NMI
+        bus.set_nmi(false);
+        bus.set_nmi(true);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x0203);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x9000);
     }
 
-    fn sta(&mut self, bus: &mut Bus) -> bool {
-        bus.write(self.address, self.a);
-        return false;
+    #[test]
+    pub fn test_i_flag_delay() {
+        // CLI, NOP: the IRQ waits until after the NOP
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x58, 0xEA, 0xEA]);
+        cpu.set_flag(Status::I);
+        bus.set_irq(IrqSource::External, true);
+        run_until(&mut cpu, &mut bus, 0x0202);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0xA000);
+
+        // SEI: the IRQ polled before I was set still runs
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x78, 0xEA]);
+        bus.set_irq(IrqSource::External, true);
+        cpu.tick(&mut bus);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0xA000);
     }
 
-    fn stx(&mut self, bus: &mut Bus) -> bool {
-        bus.write(self.address, self.x);
-        return false;
+    #[test]
+    pub fn test_branch_polling() {
+        // A taken branch without page crossing ignores an IRQ raised on its
+        // last cycles, LDA zero page with the same length does not
+        for (program, delayed) in [([0xD0, 0x00, 0xEA], true), ([0xA5, 0x00, 0xEA], false)] {
+            let mut bus = bus_with_vectors();
+            let mut cpu = cpu_at_0200(&mut bus, &program);
+            cpu.set_flag(Status::C);
+            cpu.tick(&mut bus);
+            bus.set_irq(IrqSource::External, true);
+            finish_instruction(&mut cpu, &mut bus);
+            cpu.tick(&mut bus);
+            assert_eq!(cpu.pc == 0xA000, !delayed);
+        }
     }
 
-    fn sty(&mut self, bus: &mut Bus) -> bool {
-        bus.write(self.address, self.y);
-        return false;
+    #[test]
+    pub fn test_php_plp() {
+        let mut bus = Bus::new();
+        // PHP, PLP
+        let mut cpu = cpu_at_0200(&mut bus, &[0x08, 0x28]);
+        cpu.set_status(Status::N | Status::C);
+        assert_eq!(cpu.status().bits(), 0xA1);
+        cpu.tick(&mut bus);
+        assert_eq!(bus.ram[0x1FD], 0xB1);
+
+        // PLP drops B and keeps the unused bit set
+        bus.ram[0x1FD] = 0xDF;
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.status().bits(), 0xEF);
+        assert!(cpu.status().overflow());
     }
 
-    fn tax(&mut self) -> bool {
-        self.x = self.a;
-        self.set_accumulator_flags();
+    #[test]
+    pub fn test_ldx_txs_flags() {
+        let mut bus = Bus::new();
+        // LDX #$80, LDX #$01, TXS
+        let mut cpu = cpu_at_0200(&mut bus, &[0xA2, 0x80, 0xA2, 0x01, 0x9A]);
+        cpu.tick(&mut bus);
+        assert!(cpu.status().negative());
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert!(!cpu.status().negative());
+        assert!(!cpu.status().zero());
 
-        return false;
+        // TXS leaves the flags alone
+        cpu.set_status(Status::Z);
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.sp(), 0x01);
+        assert!(cpu.status().zero());
     }
 
-    fn tay(&mut self) -> bool {
-        self.y = self.a;
-        self.set_accumulator_flags();
+    #[test]
+    pub fn test_trace() {
+        use std::{cell::RefCell, rc::Rc};
 
-        return false;
+        let mut bus = Bus::new();
+        // LDA ($80),Y, NOP $A9, JMP ($02FF)
+        let mut cpu = cpu_at_0200(&mut bus, &[0xB1, 0x80, 0x04, 0xA9, 0x6C, 0xFF, 0x02]);
+        cpu.y = 0x05;
+        bus.ram[0x80] = 0x00;
+        bus.ram[0x81] = 0x03;
+        bus.ram[0x305] = 0x89;
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let log = lines.clone();
+        cpu.set_trace(Some(Box::new(move |line: &str| log.borrow_mut().push(line.to_string()))));
+        for _ in 0..3 {
+            cpu.tick(&mut bus);
+            finish_instruction(&mut cpu, &mut bus);
+        }
+        assert_eq!(*lines.borrow(), [
+            "0200  B1 80     LDA ($80),Y = 0300 @ 0305 = 89  A:00 X:00 Y:05 P:20 SP:FD PPU:  0,  0 CYC:0",
+            "0202  04 A9    *NOP $A9 = 00                    A:89 X:00 Y:05 P:A0 SP:FD PPU:  0, 15 CYC:5",
+            "0204  6C FF 02  JMP ($02FF) = B100              A:89 X:00 Y:05 P:A0 SP:FD PPU:  0, 24 CYC:8",
+        ]);
+        assert_eq!(cpu.cycle_count(), 13);
     }
 
-    fn tsx(&mut self) -> bool {
-        self.x = self.sp as u8;
-        self.set_value_flags(self.x);
+    #[test]
+    pub fn test_stack() {
+        let mut bus = Bus::new();
+        // JSR $0210, RTS
+        let mut cpu = cpu_at_0200(&mut bus, &[0x20, 0x10, 0x02]);
+        bus.ram[0x210] = 0x60;
 
-        return false;
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x0210);
+        assert_eq!(cpu.sp, 0xFB);
+        assert_eq!(bus.ram[0x1FD], 0x02);
+        assert_eq!(bus.ram[0x1FC], 0x02);
+
+        finish_instruction(&mut cpu, &mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.pc, 0x0203);
+        assert_eq!(cpu.sp, 0xFD);
     }
 
-    fn txa(&mut self) -> bool {
-        self.a = self.x;
-        self.set_accumulator_flags();
+    /// Run one instruction, returning the cycles it took
+    fn step_cycles(cpu: &mut CPU, bus: &mut Bus) -> usize {
+        let mut count = 1;
+        cpu.tick(bus);
+        while cpu.cycles > 0 {
+            cpu.tick(bus);
+            count += 1;
+        }
+        count
+    }
 
-        return false;
+    #[test]
+    pub fn test_cycle_accurate_matches_instant() {
+        let program = [
+            0xA2, 0x05,             // LDX #$05
+            0xA0, 0xF0,             // LDY #$F0
+            0xB9, 0x20, 0x03,       // LDA $0320,Y, crossing a page
+            0x9D, 0x00, 0x03,       // STA $0300,X
+            0xFE, 0x00, 0x03,       // INC $0300,X
+            0x06, 0x10,             // ASL $10
+            0x36, 0x0E,             // ROL $0E,X
+            0xB1, 0x20,             // LDA ($20),Y
+            0x20, 0x30, 0x02,       // JSR $0230
+            0x38,                   // SEC
+            0xB0, 0x01,             // BCS +1
+            0xEA,                   // NOP, skipped
+            0x90, 0x10,             // BCC, not taken
+            0x6C, 0x40, 0x02,       // JMP ($0240)
+        ];
+        let mut states = Vec::new();
+        for cycle_accurate in [false, true] {
+            let mut bus = Bus::new();
+            let mut cpu = cpu_at_0200(&mut bus, &program);
+            cpu.set_cycle_accurate(cycle_accurate);
+            bus.ram[0x230..0x233].copy_from_slice(&[0x48, 0x68, 0x60]);
+            bus.ram[0x240..0x242].copy_from_slice(&[0x50, 0x02]);
+            bus.ram[0x410] = 0x81;
+            bus.ram[0x10] = 0x40;
+            bus.ram[0x13] = 0x01;
+            bus.ram[0x20..0x22].copy_from_slice(&[0x80, 0x03]);
+            bus.ram[0x470] = 0x7F;
+
+            let mut trace = Vec::new();
+            while cpu.pc != 0x0250 {
+                let cycles = step_cycles(&mut cpu, &mut bus);
+                trace.push((cpu.pc, cpu.a, cpu.x, cpu.y, cpu.sp, cpu.status, cycles));
+                assert!(trace.len() < 50);
+            }
+            states.push((trace, bus.ram.to_vec()));
+        }
+        assert_eq!(states[0], states[1]);
+        let cycles: Vec<usize> = states[0].0.iter().map(|state| state.6).collect();
+        assert_eq!(cycles, [2, 2, 5, 5, 7, 5, 6, 6, 6, 3, 4, 6, 2, 3, 2, 5]);
     }
 
-    fn txs(&mut self) -> bool {
-        self.sp = self.x as usize;
-        self.set_value_flags(self.x);
+    #[test]
+    pub fn test_cycle_accurate_timing() {
+        let mut bus = Bus::new();
+        // STA $0300, LDA ($1E,X)
+        let mut cpu = cpu_at_0200(&mut bus, &[0x8D, 0x00, 0x03, 0xA1, 0x1E]);
+        cpu.set_cycle_accurate(true);
+        cpu.a = 0x42;
+        cpu.x = 0x02;
+        bus.ram[0x20..0x22].copy_from_slice(&[0x34, 0x01]);
+        bus.ram[0x134] = 0x99;
+
+        // The write happens on the last cycle
+        for _ in 0..3 {
+            cpu.tick(&mut bus);
+            assert_eq!(bus.ram[0x300], 0);
+        }
+        cpu.tick(&mut bus);
+        assert_eq!(bus.ram[0x300], 0x42);
 
-        return false;
+        assert_eq!(step_cycles(&mut cpu, &mut bus), 6);
+        assert_eq!(cpu.a, 0x99);
+        assert_eq!(cpu.pc, 0x0205);
     }
 
-    fn tya(&mut self) -> bool {
-        self.a = self.y;
-        self.set_accumulator_flags();
+    #[test]
+    pub fn test_pc_wraps() {
+        // Program at the top of memory, start address and PC after one instruction
+        let cases: [(&[u8], usize, usize); 5] = [
+            (&[0xEA], 0xFFFF, 0x0000),                   // NOP
+            (&[0xA9, 0x42], 0xFFFF, 0x0001),             // LDA #$42
+            (&[0xAD, 0x03, 0x02], 0xFFFF, 0x0002),       // LDA $0203
+            (&[0xD0, 0x02], 0xFFFF, 0x0003),             // BNE past the operand
+            (&[0x20, 0x00, 0x03], 0xFFFD, 0x0300),       // JSR $0300, returning to $0000
+        ];
+        for cycle_accurate in [false, true] {
+            for (program, start, pc) in cases {
+                let mut memory = FlatMemory::new();
+                memory.load(start, program);
+                memory.memory[0x0203] = 0x99;
+                memory.memory[0x0300] = 0x60;
+                let mut cpu = CPU::new();
+                cpu.set_cycle_accurate(cycle_accurate);
+                cpu.sp = 0xFD;
+                cpu.set_pc(start);
+                cpu.tick(&mut memory);
+                while cpu.cycles > 0 {
+                    cpu.tick(&mut memory);
+                }
+                assert_eq!(cpu.pc(), pc, "{:02X?} in cycle accurate mode {}", program, cycle_accurate);
+            }
+
+            // RTS from the JSR above
+            let mut cpu = CPU::new();
+            let mut memory = FlatMemory::new();
+            memory.load(0xFFFD, &[0x20, 0x00, 0x03]);
+            memory.memory[0x0300] = 0x60;
+            cpu.set_cycle_accurate(cycle_accurate);
+            cpu.sp = 0xFD;
+            cpu.set_pc(0xFFFD);
+            for _ in 0..12 {
+                cpu.tick(&mut memory);
+            }
+            assert_eq!(memory.memory[0x1FC..0x1FE], [0xFF, 0xFF]);
+            assert_eq!(cpu.pc(), 0x0000);
+        }
+    }
 
-        return false;
+    #[test]
+    pub fn test_cycle_accurate_pc_wraps() {
+        // LDA #$42 at $FFFF with its operand at $0000, then LDA $0203 from $0001
+        let mut memory = FlatMemory::new();
+        memory.load(0xFFFF, &[0xA9, 0x42, 0xAD, 0x03, 0x02]);
+        memory.memory[0x0203] = 0x99;
+        let mut cpu = CPU::new();
+        cpu.set_cycle_accurate(true);
+        cpu.set_pc(0xFFFF);
+        for _ in 0..2 {
+            cpu.tick(&mut memory);
+        }
+        assert_eq!((cpu.pc, cpu.a), (0x0001, 0x42));
+        for _ in 0..4 {
+            cpu.tick(&mut memory);
+        }
+        assert_eq!((cpu.pc, cpu.a), (0x0004, 0x99));
+
+        // NOP at $FFFF, the opcode fetch alone wraps PC
+        memory.load(0xFFFF, &[0xEA]);
+        cpu.set_pc(0xFFFF);
+        for _ in 0..2 {
+            cpu.tick(&mut memory);
+        }
+        assert_eq!(cpu.pc(), 0x0000);
     }
-}
 
+    #[test]
+    pub fn test_dummy_accesses() {
+        // The N163 sound RAM port advances its address on every access
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0x30, 0x10];
+        rom.resize(16 + 0x8000 + 0x2000, 0);
+        let mut bus = Bus::new();
+        bus.cartridge = Cartridge::from_bytes(&rom).unwrap();
+        let port = |bus: &mut Bus, values: &[u8]| {
+            bus.write(0xF800, 0x80);
+            for &value in values {
+                bus.write(0x4800, value);
+            }
+            bus.write(0xF800, 0x80);
+        };
+
+        for (cycle_accurate, expected) in [(false, [0x10, 0x11, 0x30]), (true, [0x10, 0x10, 0x11])] {
+            // INC $4800: cycle accurate mode writes the old value back first
+            port(&mut bus, &[0x10, 0x20, 0x30]);
+            let mut cpu = cpu_at_0200(&mut bus, &[0xEE, 0x00, 0x48]);
+            cpu.set_cycle_accurate(cycle_accurate);
+            step(&mut cpu, &mut bus);
+            bus.write(0xF800, 0x80);
+            let values: Vec<u8> = (0..3).map(|_| bus.read(0x4800)).collect();
+            assert_eq!(values, expected);
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::CPU;
-    use super::StatusFlags;
-    use crate::bus::Bus;
+        // LDA $48F0,X crossing a page reads $4810 before $4910
+        for (cycle_accurate, expected) in [(false, 0x01), (true, 0x02)] {
+            port(&mut bus, &[0x01, 0x02]);
+            let mut cpu = cpu_at_0200(&mut bus, &[0xBD, 0xF0, 0x48]);
+            cpu.set_cycle_accurate(cycle_accurate);
+            cpu.x = 0x20;
+            assert_eq!(step_cycles(&mut cpu, &mut bus), 5);
+            assert_eq!(cpu.a, expected);
+        }
+    }
+
+    #[test]
+    pub fn test_cycle_accurate_interrupts() {
+        // Same IRQ and NMI behaviour as the instant mode
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x58, 0xEA, 0xEA]);
+        cpu.set_cycle_accurate(true);
+        cpu.set_flag(Status::I);
+        bus.set_irq(IrqSource::External, true);
+        run_until(&mut cpu, &mut bus, 0x0202);
+        assert_eq!(step_cycles(&mut cpu, &mut bus), 7);
+        assert_eq!(cpu.pc, 0xA000);
+        assert_eq!(bus.ram[0x1FB] & Status::B.bits(), 0);
+        bus.set_irq(IrqSource::External, false);
+
+        // An NMI during BRK takes over its vector
+        let mut bus = bus_with_vectors();
+        let mut cpu = cpu_at_0200(&mut bus, &[0x00]);
+        cpu.set_cycle_accurate(true);
+        for _ in 0..3 {
+            cpu.tick(&mut bus);
+        }
+        bus.set_nmi(true);
+        finish_instruction(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(bus.ram[0x1FB] & Status::B.bits(), Status::B.bits());
+        // The NMI was consumed by the hijack
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x9001);
+    }
+
+    /// Run ADC or SBC immediate with D set on a fresh CPU of the variant
+    fn decimal(variant: Variant, opcode: u8, a: u8, value: u8, carry: bool) -> (u8, Status, usize) {
+        let mut bus = Bus::new();
+        let mut cpu = CPU::with_variant(variant);
+        cpu.pc = 0x0200;
+        bus.ram[0x200..0x202].copy_from_slice(&[opcode, value]);
+        cpu.set_status(Status::D);
+        cpu.status.set_carry(carry);
+        cpu.a = a;
+        let cycles = step_cycles(&mut cpu, &mut bus);
+        (cpu.a, cpu.status, cycles)
+    }
+
+    #[test]
+    pub fn test_decimal_mode() {
+        // The 2A03 adds in binary whatever D says
+        assert_eq!(decimal(Variant::Ricoh2A03, 0x69, 0x09, 0x01, false).0, 0x0A);
+
+        let (a, status, cycles) = decimal(Variant::Nmos6502, 0x69, 0x09, 0x01, false);
+        assert_eq!((a, status.carry(), cycles), (0x10, false, 2));
+
+        // 99 + 1: the NMOS flags follow the intermediate results
+        let (a, status, _) = decimal(Variant::Nmos6502, 0x69, 0x99, 0x01, false);
+        assert_eq!(a, 0x00);
+        assert!(status.carry() && !status.zero() && status.negative());
+        let (a, status, cycles) = decimal(Variant::Cmos65C02, 0x69, 0x99, 0x01, false);
+        assert_eq!((a, cycles), (0x00, 3));
+        assert!(status.carry() && status.zero() && !status.negative());
+
+        // 00 - 01 = 99 with a borrow
+        let (a, status, _) = decimal(Variant::Nmos6502, 0xE9, 0x00, 0x01, true);
+        assert_eq!(a, 0x99);
+        assert!(!status.carry() && status.negative());
+        let (a, status, _) = decimal(Variant::Cmos65C02, 0xE9, 0x00, 0x01, true);
+        assert_eq!(a, 0x99);
+        assert!(!status.carry());
+
+        let (a, status, _) = decimal(Variant::Nmos6502, 0xE9, 0x46, 0x12, true);
+        assert_eq!(a, 0x34);
+        assert!(status.carry());
+        let (a, _, _) = decimal(Variant::Cmos65C02, 0xE9, 0x40, 0x13, true);
+        assert_eq!(a, 0x27);
+    }
+
+    #[test]
+    pub fn test_65c02_instructions() {
+        let mut bus = Bus::new();
+        let program = [
+            0x64, 0x10,             // STZ $10
+            0xA9, 0x0F,             // LDA #$0F
+            0x04, 0x11,             // TSB $11
+            0x14, 0x12,             // TRB $12
+            0xDA,                   // PHX
+            0x7A,                   // PLY
+            0xB2, 0x13,             // LDA ($13)
+            0x80, 0x01,             // BRA +1
+            0xEA,                   // NOP, skipped
+            0x1A,                   // INC A
+            0x7C, 0x00, 0x03,       // JMP ($0300,X)
+        ];
+        let mut cpu = CPU::with_variant(Variant::Cmos65C02);
+        cpu.sp = 0xFD;
+        cpu.pc = 0x0200;
+        bus.ram[0x200..0x200 + program.len()].copy_from_slice(&program);
+        bus.ram[0x10..0x15].copy_from_slice(&[0xFF, 0xF0, 0x3C, 0x00, 0x04]);
+        bus.ram[0x400] = 0x55;
+        bus.ram[0x302..0x304].copy_from_slice(&[0x00, 0x05]);
+        cpu.x = 0x02;
+
+        for _ in 0..7 {
+            step(&mut cpu, &mut bus);
+        }
+        assert_eq!(&bus.ram[0x10..0x13], &[0x00, 0xFF, 0x30]);
+        assert!(!cpu.check_flag(Status::Z));
+        assert_eq!((cpu.y, cpu.a), (0x02, 0x55));
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x020F);
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.a, 0x56);
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0x0500);
+
+        // JMP ($02FF) reads its high byte from $0300, BRK clears D
+        bus.ram[0x2FF] = 0x34;
+        bus.ram[0x300] = 0x12;
+        bus.ram[0x200] = 0x34;
+        for (variant, target) in [(Variant::Nmos6502, 0x3434), (Variant::Cmos65C02, 0x1234)] {
+            let mut cpu = CPU::with_variant(variant);
+            cpu.pc = 0x0500;
+            bus.ram[0x500..0x503].copy_from_slice(&[0x6C, 0xFF, 0x02]);
+            step(&mut cpu, &mut bus);
+            assert_eq!(cpu.pc, target);
+        }
+        let mut bus = bus_with_vectors();
+        let mut cpu = CPU::with_variant(Variant::Cmos65C02);
+        cpu.pc = 0x0200;
+        cpu.set_status(Status::D);
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.pc, 0xA000);
+        assert!(!cpu.check_flag(Status::D));
+    }
 
     #[test]
     pub fn test_flags() {
         let mut cpu = CPU::new();
-        cpu.set_flag(StatusFlags::Z);
-        assert!(cpu.check_flag(StatusFlags::Z));
+        cpu.set_flag(Status::Z);
+        assert!(cpu.check_flag(Status::Z));
     }
 
     #[test]
@@ -1043,7 +2078,7 @@ mod tests {
 
         cpu.pc = 0;
         bus.ram[1] = 9;
-        cpu.set_flag(StatusFlags::C);
+        cpu.set_flag(Status::C);
         cpu.tick(&mut bus);
         cpu.tick(&mut bus);
         assert_eq!(cpu.a, 0);
@@ -1052,30 +2087,184 @@ mod tests {
         cpu.tick(&mut bus);
         cpu.tick(&mut bus);
         assert_eq!(cpu.a, cpu.overflow_subtract(0, 9).1);
-        assert!(!cpu.check_flag(StatusFlags::C));
+        assert!(!cpu.check_flag(Status::C));
 
         cpu.a = 0;
         cpu.pc = 0;
         bus.ram[0] = 0x69;
         bus.ram[1] = 0;
 
-        cpu.status = 0;
+        cpu.status = Status::U;
         cpu.tick(&mut bus);
         cpu.tick(&mut bus);
 
         assert_eq!(cpu.a, 0);
-        assert!(cpu.check_flag(StatusFlags::Z));
-        assert!(!cpu.check_flag(StatusFlags::N));
-        assert!(!cpu.check_flag(StatusFlags::V));
+        assert!(cpu.check_flag(Status::Z));
+        assert!(!cpu.check_flag(Status::N));
+        assert!(!cpu.check_flag(Status::V));
 
         cpu.pc = 0;
         bus.ram[1] = 0x80;
         cpu.tick(&mut bus);
         cpu.tick(&mut bus);
 
-        assert!(cpu.check_flag(StatusFlags::N));
-        assert!(cpu.check_flag(StatusFlags::V));
+        assert!(cpu.check_flag(Status::N));
+        assert!(!cpu.check_flag(Status::V));
+
+        // Two positives giving a negative overflow
+        cpu.pc = 0;
+        cpu.a = 0x7F;
+        bus.ram[1] = 0x01;
+        cpu.tick(&mut bus);
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.a, 0x80);
+        assert!(cpu.check_flag(Status::V));
+        assert!(!cpu.check_flag(Status::C));
+    }
+
+    /// Run one instruction to completion
+    fn step(cpu: &mut CPU, bus: &mut Bus) {
+        cpu.tick(bus);
+        finish_instruction(cpu, bus);
+    }
+
+    #[test]
+    pub fn test_unofficial_decoding() {
+        for (opcode, bytes, cycles) in [(0x03, 2, 8), (0xA7, 2, 3), (0xB7, 2, 4), (0x1B, 3, 7),
+                                        (0xDF, 3, 7), (0x0C, 3, 4), (0x1A, 1, 2), (0x80, 2, 2),
+                                        (0x9E, 3, 5), (0x93, 2, 6), (0xEB, 2, 2)] {
+            let instruction = OpcodeInfo::decode(opcode);
+            assert_eq!((instruction.bytes, instruction.cycles), (bytes, cycles), "{:#04x}", opcode);
+        }
+        assert!(matches!(OpcodeInfo::decode(0xC7).mnemonic, InstructionType::DCP));
+        assert!(matches!(OpcodeInfo::decode(0x83).mnemonic, InstructionType::SAX));
+        assert!(matches!(OpcodeInfo::decode(0x72).mnemonic, InstructionType::JAM));
+    }
+
+    #[test]
+    pub fn test_unofficial_read_modify_write() {
+        let mut bus = Bus::new();
+        // LAX $10, SAX $11, DCP $12, ISC $13, SLO $14, RRA $15
+        let mut cpu = cpu_at_0200(&mut bus, &[0xA7, 0x10, 0x87, 0x11, 0xC7, 0x12,
+                                              0xE7, 0x13, 0x07, 0x14, 0x67, 0x15]);
+        bus.ram[0x10] = 0xF0;
+        bus.ram[0x12] = 0xF1;
+        bus.ram[0x13] = 0x0F;
+        bus.ram[0x14] = 0x81;
+        bus.ram[0x15] = 0x03;
+        cpu.y = 0x3C;
+
+        step(&mut cpu, &mut bus);
+        assert_eq!((cpu.a, cpu.x), (0xF0, 0xF0));
+        assert!(cpu.check_flag(Status::N));
+
+        cpu.x = 0x3C;
+        step(&mut cpu, &mut bus);
+        assert_eq!(bus.ram[0x11], 0x30);
+
+        step(&mut cpu, &mut bus);
+        assert_eq!(bus.ram[0x12], 0xF0);
+        assert!(cpu.check_flag(Status::Z) && cpu.check_flag(Status::C));
+
+        // $F0 - $10 with carry set
+        step(&mut cpu, &mut bus);
+        assert_eq!(bus.ram[0x13], 0x10);
+        assert_eq!(cpu.a, 0xE0);
+        assert!(cpu.check_flag(Status::C));
+
+        step(&mut cpu, &mut bus);
+        assert_eq!(bus.ram[0x14], 0x02);
+        assert_eq!(cpu.a, 0xE2);
+        assert!(cpu.check_flag(Status::C));
+
+        // ROR takes the carry from SLO, then ADC adds the bit shifted out
+        step(&mut cpu, &mut bus);
+        assert_eq!(bus.ram[0x15], 0x81);
+        assert_eq!(cpu.a, 0x64);
+        assert!(cpu.check_flag(Status::C));
+        assert_eq!(cpu.pc, 0x020C);
+    }
+
+    #[test]
+    pub fn test_unofficial_immediate() {
+        let mut bus = Bus::new();
+        // ANC #$80, ALR #$03, ARR #$C0, AXS #$01, XAA #$FF, LAX #$0F
+        let mut cpu = cpu_at_0200(&mut bus, &[0x0B, 0x80, 0x4B, 0x03, 0x6B, 0xC0,
+                                              0xCB, 0x01, 0x8B, 0xFF, 0xAB, 0x0F]);
+        cpu.set_magic_constants(0xFF, 0x00);
+        cpu.a = 0xC3;
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.a, 0x80);
+        assert!(cpu.check_flag(Status::C) && cpu.check_flag(Status::N));
+
+        cpu.a = 0x03;
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.check_flag(Status::C));
+
+        cpu.a = 0xFF;
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.a, 0xE0);
+        assert!(cpu.check_flag(Status::C));
+        assert!(!cpu.check_flag(Status::V));
+
+        cpu.a = 0xFF;
+        cpu.x = 0x0F;
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.x, 0x0E);
+        assert!(cpu.check_flag(Status::C));
+
+        step(&mut cpu, &mut bus);
+        assert_eq!(cpu.a, 0x0E);
+
+        // With a zero magic constant LAX #imm ANDs with A
+        cpu.a = 0x3C;
+        step(&mut cpu, &mut bus);
+        assert_eq!((cpu.a, cpu.x), (0x0C, 0x0C));
+    }
+
+    #[test]
+    pub fn test_unstable_stores() {
+        let mut bus = Bus::new();
+        // SHX $02F0,Y crossing into page 3, SHY $0400,X without crossing
+        let mut cpu = cpu_at_0200(&mut bus, &[0x9E, 0xF0, 0x02, 0x9C, 0x00, 0x04]);
+        cpu.x = 0xFF;
+        cpu.y = 0x20;
+        step(&mut cpu, &mut bus);
+        // X & ($02 + 1) = $03 becomes the high byte of the address
+        assert_eq!(bus.ram[0x310], 0x03);
+
+        cpu.x = 0x10;
+        cpu.y = 0xFF;
+        step(&mut cpu, &mut bus);
+        assert_eq!(bus.ram[0x410 & 0x7FF], 0x05);
+
+        // LAS $0500,Y
+        let mut cpu = cpu_at_0200(&mut bus, &[0xBB, 0x00, 0x05]);
+        cpu.y = 0;
+        bus.ram[0x500] = 0xAF;
+        step(&mut cpu, &mut bus);
+        assert_eq!((cpu.a, cpu.x, cpu.sp), (0xAD, 0xAD, 0xAD));
+    }
+
+    #[test]
+    pub fn test_jam() {
+        let mut bus = bus_with_vectors();
+        // NOP $12, JAM
+        let mut cpu = cpu_at_0200(&mut bus, &[0x04, 0x12, 0x02, 0xEA]);
+        step(&mut cpu, &mut bus);
+        step(&mut cpu, &mut bus);
+        assert!(cpu.halted());
+        // Neither ticks nor interrupts get it going again
+        bus.set_nmi(true);
+        for _ in 0..10 {
+            cpu.tick(&mut bus);
+        }
+        assert_eq!(cpu.pc, 0x0203);
 
+        cpu.reset(&mut bus);
+        assert!(!cpu.halted());
+        assert_eq!(cpu.pc, 0x8000);
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,489 @@
+use super::{CPU, InstructionType, AddressingMode, Status, OPCODES};
+use super::{STACK_PAGE, NMI_VECTOR, IRQ_VECTOR, INTERRUPT_CYCLES};
+use crate::memory::CpuBus;
+
+/// What happens after a cycle of the current instruction
+#[derive(PartialEq, Eq)]
+enum Next {
+    /// More cycles follow, interrupts are polled at the end of this one
+    Continue,
+    /// More cycles follow without polling
+    ContinueNoPoll,
+    /// The instruction is complete
+    Done,
+}
+
+/// How an instruction uses its operand address
+#[derive(PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+fn access(mnemonic: InstructionType) -> Access {
+    match mnemonic {
+        InstructionType::STA | InstructionType::STX | InstructionType::STY
+            | InstructionType::SAX | InstructionType::SHA | InstructionType::SHX
+            | InstructionType::SHY | InstructionType::TAS => Access::Write,
+        InstructionType::ASL | InstructionType::LSR | InstructionType::ROL
+            | InstructionType::ROR | InstructionType::INC | InstructionType::DEC
+            | InstructionType::SLO | InstructionType::RLA | InstructionType::SRE
+            | InstructionType::RRA | InstructionType::DCP | InstructionType::ISC => Access::Modify,
+        _ => Access::Read,
+    }
+}
+
+impl CPU {
+    /// Run one cycle, doing the single bus access the 6502 does on it
+    pub(super) fn tick_cycle<B: CpuBus>(&mut self, bus: &mut B) {
+        if self.step == 0 {
+            // Cycles left by reset
+            if self.cycles > 0 {
+                self.cycles -= 1;
+                return;
+            }
+            self.start(bus);
+            return;
+        }
+
+        self.step += 1;
+        match self.run_step(bus) {
+            Next::Done => {
+                self.step = 0;
+                self.cycles = 0;
+            }
+            next => {
+                self.cycles = self.cycles.saturating_sub(1).max(1);
+                if next == Next::Continue {
+                    self.poll_interrupts(bus);
+                }
+            }
+        }
+    }
+
+    /// First cycle: fetch an opcode, or throw it away to run a pending interrupt
+    fn start<B: CpuBus>(&mut self, bus: &mut B) {
+        self.step = 1;
+        self.delayed_i = None;
+        if self.nmi_pending || self.irq_pending {
+            bus.read(self.pc);
+            self.hardware_interrupt = true;
+            self.irq_pending = false;
+            self.opcode = &OPCODES[0];
+            self.cycles = INTERRUPT_CYCLES - 1;
+            return;
+        }
+
+        self.hardware_interrupt = false;
+        self.opcode = self.fetch_instruction(bus);
+        self.cycles = self.opcode.cycles - 1;
+        // BRK sets I before the handler polls, nothing is picked up on the way
+        if self.opcode.mnemonic != InstructionType::BRK {
+            self.poll_interrupts(bus);
+        }
+    }
+
+    fn run_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        let opcode = self.opcode;
+        match opcode.mnemonic {
+            InstructionType::BRK => {
+                return self.interrupt_step(bus);
+            }
+            InstructionType::JSR => {
+                return self.jsr_step(bus);
+            }
+            InstructionType::RTS => {
+                return self.rts_step(bus);
+            }
+            InstructionType::RTI => {
+                return self.rti_step(bus);
+            }
+            InstructionType::PHA | InstructionType::PHP => {
+                if self.step == 2 {
+                    bus.read(self.pc);
+                    return Next::Continue;
+                }
+                self.execute(opcode, bus);
+                return Next::Done;
+            }
+            InstructionType::PLA | InstructionType::PLP => {
+                match self.step {
+                    2 => {
+                        bus.read(self.pc);
+                    }
+                    3 => {
+                        bus.read(STACK_PAGE | self.sp);
+                    }
+                    _ => {
+                        self.execute(opcode, bus);
+                        return Next::Done;
+                    }
+                }
+                return Next::Continue;
+            }
+            InstructionType::JMP => {
+                return self.jmp_step(bus);
+            }
+            _ => {}
+        }
+
+        match opcode.addressing_mode {
+            AddressingMode::Implied => {
+                bus.read(self.pc);
+                self.execute(opcode, bus);
+                Next::Done
+            }
+            AddressingMode::Accumulator => {
+                bus.read(self.pc);
+                self.a = self.modify(opcode.mnemonic, self.a);
+                Next::Done
+            }
+            AddressingMode::Immediate => {
+                self.address = self.pc;
+                self.pc = (self.pc + 1) & 0xFFFF;
+                self.operate(bus);
+                Next::Done
+            }
+            AddressingMode::Relative => {
+                self.branch_step(bus)
+            }
+            AddressingMode::ZeroPage => {
+                if self.step == 2 {
+                    self.address = self.read_pc(bus) as usize;
+                    return Next::Continue;
+                }
+                self.operand_step(bus, self.step - 2)
+            }
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let index = if opcode.addressing_mode == AddressingMode::ZeroPageX { self.x } else { self.y };
+                match self.step {
+                    2 => {
+                        self.address = self.read_pc(bus) as usize;
+                    }
+                    3 => {
+                        bus.read(self.address);
+                        self.address = (self.address + index as usize) & 0xFF;
+                    }
+                    _ => {
+                        return self.operand_step(bus, self.step - 3);
+                    }
+                }
+                Next::Continue
+            }
+            AddressingMode::Absolute => {
+                match self.step {
+                    2 => {
+                        self.pointer = self.read_pc(bus) as usize;
+                    }
+                    3 => {
+                        self.address = self.pointer | (self.read_pc(bus) as usize) << 8;
+                    }
+                    _ => {
+                        return self.operand_step(bus, self.step - 3);
+                    }
+                }
+                Next::Continue
+            }
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let index = if opcode.addressing_mode == AddressingMode::AbsoluteX { self.x } else { self.y };
+                match self.step {
+                    2 => {
+                        self.pointer = self.read_pc(bus) as usize;
+                    }
+                    3 => {
+                        self.pointer |= (self.read_pc(bus) as usize) << 8;
+                        self.address = (self.pointer + index as usize) & 0xFFFF;
+                    }
+                    4 => {
+                        return self.indexed_step(bus);
+                    }
+                    _ => {
+                        return self.operand_step(bus, self.step - 4);
+                    }
+                }
+                Next::Continue
+            }
+            AddressingMode::IndirectX => {
+                match self.step {
+                    2 => {
+                        self.pointer = self.read_pc(bus) as usize;
+                    }
+                    3 => {
+                        bus.read(self.pointer);
+                        self.pointer = (self.pointer + self.x as usize) & 0xFF;
+                    }
+                    4 => {
+                        self.data = bus.read(self.pointer);
+                    }
+                    5 => {
+                        let high = bus.read((self.pointer + 1) & 0xFF) as usize;
+                        self.address = self.data as usize | high << 8;
+                    }
+                    _ => {
+                        return self.operand_step(bus, self.step - 5);
+                    }
+                }
+                Next::Continue
+            }
+            AddressingMode::IndirectY => {
+                match self.step {
+                    2 => {
+                        self.pointer = self.read_pc(bus) as usize;
+                    }
+                    3 => {
+                        self.data = bus.read(self.pointer);
+                    }
+                    4 => {
+                        let high = bus.read((self.pointer + 1) & 0xFF) as usize;
+                        self.pointer = self.data as usize | high << 8;
+                        self.address = (self.pointer + self.y as usize) & 0xFFFF;
+                    }
+                    5 => {
+                        return self.indexed_step(bus);
+                    }
+                    _ => {
+                        return self.operand_step(bus, self.step - 5);
+                    }
+                }
+                Next::Continue
+            }
+            AddressingMode::Indirect | AddressingMode::ZeroPageIndirect
+                | AddressingMode::AbsoluteIndexedIndirect => {
+                // JMP is handled above and the 65C02 never runs cycle accurate
+                Next::Done
+            }
+        }
+    }
+
+    /// Read the byte at PC and move past it
+    fn read_pc<B: CpuBus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.pc);
+        self.pc = (self.pc + 1) & 0xFFFF;
+        value
+    }
+
+    /// Execute an instruction once its operand address is known. Read
+    /// instructions read it with the execution, unofficial NOPs read and
+    /// discard it.
+    fn operate<B: CpuBus>(&mut self, bus: &mut B) {
+        if self.opcode.mnemonic == InstructionType::NOP {
+            bus.read(self.address);
+        } else {
+            self.execute(self.opcode, bus);
+        }
+    }
+
+    /// Access to the indexed address before the carry reaches the high byte.
+    /// Reads that did not cross a page are done, everything else treats it
+    /// as a dummy read and accesses the right address on the next cycle.
+    fn indexed_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        let unfixed = (self.pointer & 0xFF00) | (self.address & 0xFF);
+        let read = access(self.opcode.mnemonic) == Access::Read;
+        if read && unfixed == self.address {
+            self.operate(bus);
+            return Next::Done;
+        }
+        bus.read(unfixed);
+        if read {
+            self.cycles += 1;
+        }
+        Next::Continue
+    }
+
+    /// Cycles spent at the operand address, counted from 1
+    fn operand_step<B: CpuBus>(&mut self, bus: &mut B, step: u8) -> Next {
+        match access(self.opcode.mnemonic) {
+            Access::Read | Access::Write => {
+                self.operate(bus);
+                Next::Done
+            }
+            Access::Modify => {
+                match step {
+                    1 => {
+                        self.data = bus.read(self.address);
+                    }
+                    2 => {
+                        // The unmodified value is written back first
+                        bus.write(self.address, self.data);
+                    }
+                    _ => {
+                        let res = self.modify(self.opcode.mnemonic, self.data);
+                        bus.write(self.address, res);
+                        return Next::Done;
+                    }
+                }
+                Next::Continue
+            }
+        }
+    }
+
+    fn branch_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        match self.step {
+            2 => {
+                self.data = self.read_pc(bus);
+                if !self.branch_taken(self.opcode.mnemonic) {
+                    return Next::Done;
+                }
+                self.cycles += 1;
+                // A taken branch polls on its operand fetch and, if it
+                // crosses a page, on the high byte fixup
+                Next::ContinueNoPoll
+            }
+            3 => {
+                bus.read(self.pc);
+                let offset = self.data as i8 as isize;
+                let target = (self.pc as isize + offset) as usize & 0xFFFF;
+                if target & 0xFF00 == self.pc & 0xFF00 {
+                    self.pc = target;
+                    return Next::Done;
+                }
+                self.pc = (self.pc & 0xFF00) | (target & 0xFF);
+                self.pointer = target;
+                self.cycles += 1;
+                Next::Continue
+            }
+            _ => {
+                bus.read(self.pc);
+                self.pc = self.pointer;
+                Next::Done
+            }
+        }
+    }
+
+    fn jmp_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        match self.step {
+            2 => {
+                self.pointer = self.read_pc(bus) as usize;
+            }
+            3 => {
+                let high = self.read_pc(bus) as usize;
+                self.pointer |= high << 8;
+                if self.opcode.addressing_mode == AddressingMode::Absolute {
+                    self.pc = self.pointer;
+                    return Next::Done;
+                }
+            }
+            4 => {
+                self.data = bus.read(self.pointer);
+            }
+            _ => {
+                // The high byte comes from the same page as the low byte
+                let high = bus.read((self.pointer & 0xFF00) | ((self.pointer + 1) & 0xFF)) as usize;
+                self.pc = self.data as usize | high << 8;
+                return Next::Done;
+            }
+        }
+        Next::Continue
+    }
+
+    fn jsr_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        match self.step {
+            2 => {
+                self.data = self.read_pc(bus);
+            }
+            3 => {
+                bus.read(STACK_PAGE | self.sp);
+            }
+            4 => {
+                self.push(bus, ((self.pc & 0xFF00) >> 8) as u8);
+            }
+            5 => {
+                self.push(bus, (self.pc & 0xFF) as u8);
+            }
+            _ => {
+                let high = bus.read(self.pc) as usize;
+                self.pc = self.data as usize | high << 8;
+                return Next::Done;
+            }
+        }
+        Next::Continue
+    }
+
+    fn rts_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        match self.step {
+            2 => {
+                bus.read(self.pc);
+            }
+            3 => {
+                bus.read(STACK_PAGE | self.sp);
+            }
+            4 => {
+                self.data = self.pull(bus);
+            }
+            5 => {
+                let high = self.pull(bus) as usize;
+                self.pc = self.data as usize | high << 8;
+            }
+            _ => {
+                bus.read(self.pc);
+                self.pc = (self.pc + 1) & 0xFFFF;
+                return Next::Done;
+            }
+        }
+        Next::Continue
+    }
+
+    fn rti_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        match self.step {
+            2 => {
+                bus.read(self.pc);
+            }
+            3 => {
+                bus.read(STACK_PAGE | self.sp);
+            }
+            4 => {
+                self.status = Status::from_pulled(self.pull(bus));
+            }
+            5 => {
+                self.data = self.pull(bus);
+            }
+            _ => {
+                let high = self.pull(bus) as usize;
+                self.pc = self.data as usize | high << 8;
+                return Next::Done;
+            }
+        }
+        Next::Continue
+    }
+
+    /// BRK, IRQ and NMI. An NMI seen before P is pushed takes over the vector.
+    fn interrupt_step<B: CpuBus>(&mut self, bus: &mut B) -> Next {
+        let brk = !self.hardware_interrupt;
+        match self.step {
+            2 => {
+                bus.read(self.pc);
+                if brk {
+                    self.pc = (self.pc + 1) & 0xFFFF;
+                }
+            }
+            3 => {
+                self.push(bus, ((self.pc & 0xFF00) >> 8) as u8);
+            }
+            4 => {
+                self.push(bus, (self.pc & 0xFF) as u8);
+            }
+            5 => {
+                if self.nmi_pending || bus.nmi_edge() {
+                    bus.acknowledge_nmi();
+                    self.nmi_pending = false;
+                    self.address = NMI_VECTOR;
+                } else {
+                    self.address = IRQ_VECTOR;
+                }
+                self.push(bus, self.status.pushed(brk));
+            }
+            6 => {
+                self.data = bus.read(self.address);
+                self.set_flag(Status::I);
+            }
+            _ => {
+                let high = bus.read(self.address + 1) as usize;
+                self.pc = self.data as usize | high << 8;
+                return Next::Done;
+            }
+        }
+        // The handler's first instruction always runs before another interrupt
+        Next::ContinueNoPoll
+    }
+}
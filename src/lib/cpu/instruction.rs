@@ -1,5 +1,6 @@
+use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum InstructionType {
     /// Arithmetic
     ADC,
@@ -43,9 +44,34 @@ pub enum InstructionType {
     STA, STX, STY,
     /// Transfer
     TAX, TAY, TSX, TXA, TXS, TYA,
+    /// Unofficial read-modify-write combined with an ALU operation
+    DCP, ISC, RLA, RRA, SLO, SRE,
+    /// Unofficial load and store of A and X together
+    LAX, SAX,
+    /// Unofficial immediate operations
+    ALR, ANC, ARR, AXS, XAA,
+    /// Unofficial operations depending on the address high byte or SP, unstable on real chips
+    LAS, SHA, SHX, SHY, TAS,
+    /// Halts the CPU until reset
+    JAM,
+    /// 65C02 branch always
+    BRA,
+    /// 65C02 push and pull of X and Y
+    PHX, PHY, PLX, PLY,
+    /// 65C02 store zero
+    STZ,
+    /// 65C02 test and reset or set memory bits
+    TRB, TSB,
 }
 
-#[derive(Debug)]
+/// The mnemonic, as written in assembly
+impl fmt::Display for InstructionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AddressingMode {
     Accumulator,
     Immediate,
@@ -60,1298 +86,407 @@ pub enum AddressingMode {
     IndirectY,
     Relative,
     Implied,
+    /// 65C02 ($zp)
+    ZeroPageIndirect,
+    /// 65C02 JMP ($addr,X)
+    AbsoluteIndexedIndirect,
 }
 
-/// 6502 instruction
-pub struct Instruction {
-    pub value: u8,
-    pub itype: InstructionType,
-    pub addressing_mode: AddressingMode,
-    pub cycles: u8,
-    pub bytes: u8,
-}
-
-fn adc(value: u8) -> Instruction {
-    let itype = InstructionType::ADC;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x69 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0x65 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x75 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x6D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x7D => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0x79 => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0x61 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0x71 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
-        }
-    }
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn and(value: u8) -> Instruction {
-    let itype = InstructionType::AND;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x29 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0x25 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x35 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x2D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x3D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0x39 => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0x21 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0x31 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
-        }
-    }
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn asl(value: u8) -> Instruction {
-    let itype = InstructionType::ASL;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x0A => {
-            bytes = 1;
-            cycles = 2;
-            addressing_mode = AddressingMode::Accumulator;
-        }
-        0x06 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x16 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x0E => {
-            bytes = 3;
-            cycles = 6;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x1E => {
-            bytes = 3;
-            cycles = 7;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn bit(value: u8) -> Instruction {
-    let itype = InstructionType::BIT;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x24 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x2C => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        _ => {
-        }
-    }
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn cmp(value: u8) -> Instruction {
-    let itype = InstructionType::CMP;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xC9 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0xC5 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xD5 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0xCD => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-
-        }
-        0xDD => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0xD9 => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0xC1 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0xD1 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
-        }
-    }
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn cpy(value: u8) -> Instruction {
-    let itype = InstructionType::CPY;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xC0 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0xC4 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xCC => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn dec(value: u8) -> Instruction {
-    let itype = InstructionType::DEC;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xC6 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xD6 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::ZeroPageX;
-
-        }
-        0xCE => {
-            bytes = 3;
-            cycles = 6;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0xDE => {
-            bytes = 3;
-            cycles = 7;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn eor(value: u8) -> Instruction {
-    let itype = InstructionType::EOR;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x49 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0x45 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x55 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x4D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x5D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0x59 => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0x41 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX
-        }
-        0x51 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::IndirectY
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn inc(value: u8) -> Instruction {
-    let itype = InstructionType::INC;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xE6 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xF6 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0xEE => {
-            bytes = 3;
-            cycles = 6;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0xFE => {
-            bytes = 3;
-            cycles = 7;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn jmp(value: u8) -> Instruction {
-    let itype = InstructionType::JMP;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x4C => {
-            bytes = 3;
-            cycles = 3;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x6C => {
-            bytes = 3;
-            cycles = 5;
-            addressing_mode = AddressingMode::Indirect;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn lda(value: u8) -> Instruction {
-    let itype = InstructionType::LDA;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xA9 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0xA5 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xB5 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0xAD => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0xBD => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0xB9 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0xA1 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0xB1 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-
-fn ldx(value: u8) -> Instruction {
-    let itype = InstructionType::LDX;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xA2 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0xA6 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xB6 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageY;
+impl AddressingMode {
+    /// Length of an instruction using this mode, opcode included
+    pub const fn bytes(self) -> u8 {
+        match self {
+            AddressingMode::Accumulator | AddressingMode::Implied => 1,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX
+                | AddressingMode::AbsoluteY | AddressingMode::Indirect
+                | AddressingMode::AbsoluteIndexedIndirect => 3,
+            _ => 2,
         }
-        0xAE => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0xBE => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
     }
 }
 
-fn ldy(value: u8) -> Instruction {
-    let itype = InstructionType::LDY;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xA0 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0xA4 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xB4 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0xAC => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0xBC => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn lsr(value: u8) -> Instruction {
-    let itype = InstructionType::LSR;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x4A => {
-            bytes = 1;
-            cycles = 2;
-            addressing_mode = AddressingMode::Accumulator;
-        }
-        0x46 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x56 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x4E => {
-            bytes = 3;
-            cycles = 6;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x5E => {
-            bytes = 3;
-            cycles = 7;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn ora(value: u8) -> Instruction {
-    let itype = InstructionType::ORA;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x09 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0x05 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x15 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x0D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x1D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0x19 => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0x01 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0x11 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn rol(value: u8) -> Instruction {
-    let itype = InstructionType::ROL;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x2A => {
-            bytes = 1;
-            cycles = 2;
-            addressing_mode = AddressingMode::Accumulator;
-        }
-        0x26 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x36 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x2E => {
-            bytes = 3;
-            cycles = 6;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x3E => {
-            bytes = 3;
-            cycles = 7;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
+/// Decoding information for one 6502 opcode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub mnemonic: InstructionType,
+    pub addressing_mode: AddressingMode,
+    /// Length, opcode included
+    pub bytes: u8,
+    /// Cycles without page crossing or taken branch
+    pub cycles: u8,
+    /// Takes one more cycle when indexing crosses a page.
+    /// Branches add their own cycles and do not use this.
+    pub page_penalty: bool,
 }
 
-
-fn ror(value: u8) -> Instruction {
-    let itype = InstructionType::ROR;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x2A => {
-            bytes = 1;
-            cycles = 2;
-            addressing_mode = AddressingMode::Accumulator;
-        }
-        0x26 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x36 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x2E => {
-            bytes = 3;
-            cycles = 6;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x3E => {
-            bytes = 3;
-            cycles = 7;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        _ => {
-        }
+impl OpcodeInfo {
+    /// Table entry for an opcode
+    pub fn decode(opcode: u8) -> &'static OpcodeInfo {
+        &OPCODES[opcode as usize]
     }
 
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
-}
-
-fn sbc(value: u8) -> Instruction {
-    let itype = InstructionType::SBC;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0xE9 => {
-            bytes = 2;
-            cycles = 2;
-            addressing_mode = AddressingMode::Immediate;
-        }
-        0xE5 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0xF5 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0xED => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0xFD => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0xF9 => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0xE1 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0xF1 => {
-            bytes = 2;
-            cycles = 5;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
+    /// True for the 151 opcodes documented for the NMOS 6502
+    pub fn documented(opcode: u8) -> bool {
+        use InstructionType::*;
+        match OPCODES[opcode as usize].mnemonic {
+            DCP | ISC | RLA | RRA | SLO | SRE | LAX | SAX | ALR | ANC | ARR | AXS
+                | XAA | LAS | SHA | SHX | SHY | TAS | JAM => false,
+            NOP => opcode == 0xEA,
+            SBC => opcode != 0xEB,
+            _ => true,
         }
     }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
 }
 
-fn sta(value: u8) -> Instruction {
-    let itype = InstructionType::STA;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x85 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x95 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x8D => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        0x9D => {
-            bytes = 3;
-            cycles = 5;
-            addressing_mode = AddressingMode::AbsoluteX;
-        }
-        0x99 => {
-            bytes = 3;
-            cycles = 5;
-            addressing_mode = AddressingMode::AbsoluteY;
-        }
-        0x81 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectX;
-        }
-        0x91 => {
-            bytes = 2;
-            cycles = 6;
-            addressing_mode = AddressingMode::IndirectY;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
+const fn op(mnemonic: InstructionType, addressing_mode: AddressingMode, cycles: u8,
+            page_penalty: bool) -> OpcodeInfo {
+    OpcodeInfo {
+        mnemonic,
+        addressing_mode,
+        bytes: addressing_mode.bytes(),
+        cycles,
+        page_penalty,
     }
 }
 
-fn stx(value: u8) -> Instruction {
-    let itype = InstructionType::STX;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x86 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x96 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageY;
-        }
-        0x8E => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
-        }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
-    }
+/// Every opcode, official or not, indexed by its value
+pub static OPCODES: [OpcodeInfo; 256] = NMOS_OPCODES;
+
+/// 65C02 opcodes. Undefined opcodes are NOPs of various lengths.
+pub static OPCODES_65C02: [OpcodeInfo; 256] = cmos_opcodes();
+
+const NMOS_OPCODES: [OpcodeInfo; 256] = {
+    use InstructionType::*;
+    use AddressingMode::*;
+    [
+        // $00
+        op(BRK, Implied, 7, false), op(ORA, IndirectX, 6, false), op(JAM, Implied, 2, false), op(SLO, IndirectX, 8, false),
+        op(NOP, ZeroPage, 3, false), op(ORA, ZeroPage, 3, false), op(ASL, ZeroPage, 5, false), op(SLO, ZeroPage, 5, false),
+        op(PHP, Implied, 3, false), op(ORA, Immediate, 2, false), op(ASL, Accumulator, 2, false), op(ANC, Immediate, 2, false),
+        op(NOP, Absolute, 4, false), op(ORA, Absolute, 4, false), op(ASL, Absolute, 6, false), op(SLO, Absolute, 6, false),
+        // $10
+        op(BPL, Relative, 2, false), op(ORA, IndirectY, 5, true), op(JAM, Implied, 2, false), op(SLO, IndirectY, 8, false),
+        op(NOP, ZeroPageX, 4, false), op(ORA, ZeroPageX, 4, false), op(ASL, ZeroPageX, 6, false), op(SLO, ZeroPageX, 6, false),
+        op(CLC, Implied, 2, false), op(ORA, AbsoluteY, 4, true), op(NOP, Implied, 2, false), op(SLO, AbsoluteY, 7, false),
+        op(NOP, AbsoluteX, 4, true), op(ORA, AbsoluteX, 4, true), op(ASL, AbsoluteX, 7, false), op(SLO, AbsoluteX, 7, false),
+        // $20
+        op(JSR, Absolute, 6, false), op(AND, IndirectX, 6, false), op(JAM, Implied, 2, false), op(RLA, IndirectX, 8, false),
+        op(BIT, ZeroPage, 3, false), op(AND, ZeroPage, 3, false), op(ROL, ZeroPage, 5, false), op(RLA, ZeroPage, 5, false),
+        op(PLP, Implied, 4, false), op(AND, Immediate, 2, false), op(ROL, Accumulator, 2, false), op(ANC, Immediate, 2, false),
+        op(BIT, Absolute, 4, false), op(AND, Absolute, 4, false), op(ROL, Absolute, 6, false), op(RLA, Absolute, 6, false),
+        // $30
+        op(BMI, Relative, 2, false), op(AND, IndirectY, 5, true), op(JAM, Implied, 2, false), op(RLA, IndirectY, 8, false),
+        op(NOP, ZeroPageX, 4, false), op(AND, ZeroPageX, 4, false), op(ROL, ZeroPageX, 6, false), op(RLA, ZeroPageX, 6, false),
+        op(SEC, Implied, 2, false), op(AND, AbsoluteY, 4, true), op(NOP, Implied, 2, false), op(RLA, AbsoluteY, 7, false),
+        op(NOP, AbsoluteX, 4, true), op(AND, AbsoluteX, 4, true), op(ROL, AbsoluteX, 7, false), op(RLA, AbsoluteX, 7, false),
+        // $40
+        op(RTI, Implied, 6, false), op(EOR, IndirectX, 6, false), op(JAM, Implied, 2, false), op(SRE, IndirectX, 8, false),
+        op(NOP, ZeroPage, 3, false), op(EOR, ZeroPage, 3, false), op(LSR, ZeroPage, 5, false), op(SRE, ZeroPage, 5, false),
+        op(PHA, Implied, 3, false), op(EOR, Immediate, 2, false), op(LSR, Accumulator, 2, false), op(ALR, Immediate, 2, false),
+        op(JMP, Absolute, 3, false), op(EOR, Absolute, 4, false), op(LSR, Absolute, 6, false), op(SRE, Absolute, 6, false),
+        // $50
+        op(BVC, Relative, 2, false), op(EOR, IndirectY, 5, true), op(JAM, Implied, 2, false), op(SRE, IndirectY, 8, false),
+        op(NOP, ZeroPageX, 4, false), op(EOR, ZeroPageX, 4, false), op(LSR, ZeroPageX, 6, false), op(SRE, ZeroPageX, 6, false),
+        op(CLI, Implied, 2, false), op(EOR, AbsoluteY, 4, true), op(NOP, Implied, 2, false), op(SRE, AbsoluteY, 7, false),
+        op(NOP, AbsoluteX, 4, true), op(EOR, AbsoluteX, 4, true), op(LSR, AbsoluteX, 7, false), op(SRE, AbsoluteX, 7, false),
+        // $60
+        op(RTS, Implied, 6, false), op(ADC, IndirectX, 6, false), op(JAM, Implied, 2, false), op(RRA, IndirectX, 8, false),
+        op(NOP, ZeroPage, 3, false), op(ADC, ZeroPage, 3, false), op(ROR, ZeroPage, 5, false), op(RRA, ZeroPage, 5, false),
+        op(PLA, Implied, 4, false), op(ADC, Immediate, 2, false), op(ROR, Accumulator, 2, false), op(ARR, Immediate, 2, false),
+        op(JMP, Indirect, 5, false), op(ADC, Absolute, 4, false), op(ROR, Absolute, 6, false), op(RRA, Absolute, 6, false),
+        // $70
+        op(BVS, Relative, 2, false), op(ADC, IndirectY, 5, true), op(JAM, Implied, 2, false), op(RRA, IndirectY, 8, false),
+        op(NOP, ZeroPageX, 4, false), op(ADC, ZeroPageX, 4, false), op(ROR, ZeroPageX, 6, false), op(RRA, ZeroPageX, 6, false),
+        op(SEI, Implied, 2, false), op(ADC, AbsoluteY, 4, true), op(NOP, Implied, 2, false), op(RRA, AbsoluteY, 7, false),
+        op(NOP, AbsoluteX, 4, true), op(ADC, AbsoluteX, 4, true), op(ROR, AbsoluteX, 7, false), op(RRA, AbsoluteX, 7, false),
+        // $80
+        op(NOP, Immediate, 2, false), op(STA, IndirectX, 6, false), op(NOP, Immediate, 2, false), op(SAX, IndirectX, 6, false),
+        op(STY, ZeroPage, 3, false), op(STA, ZeroPage, 3, false), op(STX, ZeroPage, 3, false), op(SAX, ZeroPage, 3, false),
+        op(DEY, Implied, 2, false), op(NOP, Immediate, 2, false), op(TXA, Implied, 2, false), op(XAA, Immediate, 2, false),
+        op(STY, Absolute, 4, false), op(STA, Absolute, 4, false), op(STX, Absolute, 4, false), op(SAX, Absolute, 4, false),
+        // $90
+        op(BCC, Relative, 2, false), op(STA, IndirectY, 6, false), op(JAM, Implied, 2, false), op(SHA, IndirectY, 6, false),
+        op(STY, ZeroPageX, 4, false), op(STA, ZeroPageX, 4, false), op(STX, ZeroPageY, 4, false), op(SAX, ZeroPageY, 4, false),
+        op(TYA, Implied, 2, false), op(STA, AbsoluteY, 5, false), op(TXS, Implied, 2, false), op(TAS, AbsoluteY, 5, false),
+        op(SHY, AbsoluteX, 5, false), op(STA, AbsoluteX, 5, false), op(SHX, AbsoluteY, 5, false), op(SHA, AbsoluteY, 5, false),
+        // $A0
+        op(LDY, Immediate, 2, false), op(LDA, IndirectX, 6, false), op(LDX, Immediate, 2, false), op(LAX, IndirectX, 6, false),
+        op(LDY, ZeroPage, 3, false), op(LDA, ZeroPage, 3, false), op(LDX, ZeroPage, 3, false), op(LAX, ZeroPage, 3, false),
+        op(TAY, Implied, 2, false), op(LDA, Immediate, 2, false), op(TAX, Implied, 2, false), op(LAX, Immediate, 2, false),
+        op(LDY, Absolute, 4, false), op(LDA, Absolute, 4, false), op(LDX, Absolute, 4, false), op(LAX, Absolute, 4, false),
+        // $B0
+        op(BCS, Relative, 2, false), op(LDA, IndirectY, 5, true), op(JAM, Implied, 2, false), op(LAX, IndirectY, 5, true),
+        op(LDY, ZeroPageX, 4, false), op(LDA, ZeroPageX, 4, false), op(LDX, ZeroPageY, 4, false), op(LAX, ZeroPageY, 4, false),
+        op(CLV, Implied, 2, false), op(LDA, AbsoluteY, 4, true), op(TSX, Implied, 2, false), op(LAS, AbsoluteY, 4, true),
+        op(LDY, AbsoluteX, 4, true), op(LDA, AbsoluteX, 4, true), op(LDX, AbsoluteY, 4, true), op(LAX, AbsoluteY, 4, true),
+        // $C0
+        op(CPY, Immediate, 2, false), op(CMP, IndirectX, 6, false), op(NOP, Immediate, 2, false), op(DCP, IndirectX, 8, false),
+        op(CPY, ZeroPage, 3, false), op(CMP, ZeroPage, 3, false), op(DEC, ZeroPage, 5, false), op(DCP, ZeroPage, 5, false),
+        op(INY, Implied, 2, false), op(CMP, Immediate, 2, false), op(DEX, Implied, 2, false), op(AXS, Immediate, 2, false),
+        op(CPY, Absolute, 4, false), op(CMP, Absolute, 4, false), op(DEC, Absolute, 6, false), op(DCP, Absolute, 6, false),
+        // $D0
+        op(BNE, Relative, 2, false), op(CMP, IndirectY, 5, true), op(JAM, Implied, 2, false), op(DCP, IndirectY, 8, false),
+        op(NOP, ZeroPageX, 4, false), op(CMP, ZeroPageX, 4, false), op(DEC, ZeroPageX, 6, false), op(DCP, ZeroPageX, 6, false),
+        op(CLD, Implied, 2, false), op(CMP, AbsoluteY, 4, true), op(NOP, Implied, 2, false), op(DCP, AbsoluteY, 7, false),
+        op(NOP, AbsoluteX, 4, true), op(CMP, AbsoluteX, 4, true), op(DEC, AbsoluteX, 7, false), op(DCP, AbsoluteX, 7, false),
+        // $E0
+        op(CPX, Immediate, 2, false), op(SBC, IndirectX, 6, false), op(NOP, Immediate, 2, false), op(ISC, IndirectX, 8, false),
+        op(CPX, ZeroPage, 3, false), op(SBC, ZeroPage, 3, false), op(INC, ZeroPage, 5, false), op(ISC, ZeroPage, 5, false),
+        op(INX, Implied, 2, false), op(SBC, Immediate, 2, false), op(NOP, Implied, 2, false), op(SBC, Immediate, 2, false),
+        op(CPX, Absolute, 4, false), op(SBC, Absolute, 4, false), op(INC, Absolute, 6, false), op(ISC, Absolute, 6, false),
+        // $F0
+        op(BEQ, Relative, 2, false), op(SBC, IndirectY, 5, true), op(JAM, Implied, 2, false), op(ISC, IndirectY, 8, false),
+        op(NOP, ZeroPageX, 4, false), op(SBC, ZeroPageX, 4, false), op(INC, ZeroPageX, 6, false), op(ISC, ZeroPageX, 6, false),
+        op(SED, Implied, 2, false), op(SBC, AbsoluteY, 4, true), op(NOP, Implied, 2, false), op(ISC, AbsoluteY, 7, false),
+        op(NOP, AbsoluteX, 4, true), op(SBC, AbsoluteX, 4, true), op(INC, AbsoluteX, 7, false), op(ISC, AbsoluteX, 7, false),
+    ]
+};
+
+/// Opcodes the 65C02 adds or changes. Columns 3, 7, B and F are one cycle NOPs.
+const CMOS_CHANGES: [(usize, OpcodeInfo); 46] = {
+    use InstructionType::*;
+    use AddressingMode::*;
+    [
+        (0x02, op(NOP, Immediate, 2, false)), (0x22, op(NOP, Immediate, 2, false)),
+        (0x42, op(NOP, Immediate, 2, false)), (0x62, op(NOP, Immediate, 2, false)),
+        (0x82, op(NOP, Immediate, 2, false)), (0xC2, op(NOP, Immediate, 2, false)),
+        (0xE2, op(NOP, Immediate, 2, false)),
+        (0x12, op(ORA, ZeroPageIndirect, 5, false)), (0x32, op(AND, ZeroPageIndirect, 5, false)),
+        (0x52, op(EOR, ZeroPageIndirect, 5, false)), (0x72, op(ADC, ZeroPageIndirect, 5, false)),
+        (0x92, op(STA, ZeroPageIndirect, 5, false)), (0xB2, op(LDA, ZeroPageIndirect, 5, false)),
+        (0xD2, op(CMP, ZeroPageIndirect, 5, false)), (0xF2, op(SBC, ZeroPageIndirect, 5, false)),
+        (0x04, op(TSB, ZeroPage, 5, false)), (0x0C, op(TSB, Absolute, 6, false)),
+        (0x14, op(TRB, ZeroPage, 5, false)), (0x1C, op(TRB, Absolute, 6, false)),
+        (0x1A, op(INC, Accumulator, 2, false)), (0x3A, op(DEC, Accumulator, 2, false)),
+        (0x34, op(BIT, ZeroPageX, 4, false)), (0x3C, op(BIT, AbsoluteX, 4, true)),
+        (0x89, op(BIT, Immediate, 2, false)),
+        (0x44, op(NOP, ZeroPage, 3, false)), (0x54, op(NOP, ZeroPageX, 4, false)),
+        (0xD4, op(NOP, ZeroPageX, 4, false)), (0xF4, op(NOP, ZeroPageX, 4, false)),
+        (0x5C, op(NOP, Absolute, 8, false)), (0xDC, op(NOP, Absolute, 4, false)),
+        (0xFC, op(NOP, Absolute, 4, false)),
+        (0x5A, op(PHY, Implied, 3, false)), (0x7A, op(PLY, Implied, 4, false)),
+        (0xDA, op(PHX, Implied, 3, false)), (0xFA, op(PLX, Implied, 4, false)),
+        (0x64, op(STZ, ZeroPage, 3, false)), (0x74, op(STZ, ZeroPageX, 4, false)),
+        (0x9C, op(STZ, Absolute, 4, false)), (0x9E, op(STZ, AbsoluteX, 5, false)),
+        (0x6C, op(JMP, Indirect, 6, false)), (0x7C, op(JMP, AbsoluteIndexedIndirect, 6, false)),
+        (0x80, op(BRA, Relative, 2, false)),
+        // Shifts and rotates skip the extra cycle when no page is crossed
+        (0x1E, op(ASL, AbsoluteX, 6, true)), (0x3E, op(ROL, AbsoluteX, 6, true)),
+        (0x5E, op(LSR, AbsoluteX, 6, true)), (0x7E, op(ROR, AbsoluteX, 6, true)),
+    ]
+};
+
+const fn cmos_opcodes() -> [OpcodeInfo; 256] {
+    let mut table = NMOS_OPCODES;
+    let mut i = 0;
+    while i < 256 {
+        if i & 0x03 == 0x03 {
+            table[i] = op(InstructionType::NOP, AddressingMode::Implied, 1, false);
+        }
+        i += 1;
+    }
+    let mut i = 0;
+    while i < CMOS_CHANGES.len() {
+        table[CMOS_CHANGES[i].0] = CMOS_CHANGES[i].1;
+        i += 1;
+    }
+    table
 }
 
-fn sty(value: u8) -> Instruction {
-    let itype = InstructionType::STY;
-    let mut bytes = 0;
-    let mut cycles = 0;
-    let mut addressing_mode = AddressingMode::Implied;
-
-    match value {
-        0x84 => {
-            bytes = 2;
-            cycles = 3;
-            addressing_mode = AddressingMode::ZeroPage;
-        }
-        0x94 => {
-            bytes = 2;
-            cycles = 4;
-            addressing_mode = AddressingMode::ZeroPageX;
-        }
-        0x8C => {
-            bytes = 3;
-            cycles = 4;
-            addressing_mode = AddressingMode::Absolute;
+#[cfg(test)]
+mod tests {
+    use super::{AddressingMode, InstructionType, OpcodeInfo, OPCODES, OPCODES_65C02};
+
+    /// Opcode matrix as printed in 6502 references: mnemonic, mode and
+    /// cycles, with a * when crossing a page costs a cycle
+    const REFERENCE: [&str; 256] = [
+        "BRK imp 7", "ORA izx 6", "JAM imp 2", "SLO izx 8", "NOP zp 3", "ORA zp 3", "ASL zp 5", "SLO zp 5",
+        "PHP imp 3", "ORA imm 2", "ASL acc 2", "ANC imm 2", "NOP abs 4", "ORA abs 4", "ASL abs 6", "SLO abs 6",
+        "BPL rel 2", "ORA izy 5*", "JAM imp 2", "SLO izy 8", "NOP zpx 4", "ORA zpx 4", "ASL zpx 6", "SLO zpx 6",
+        "CLC imp 2", "ORA aby 4*", "NOP imp 2", "SLO aby 7", "NOP abx 4*", "ORA abx 4*", "ASL abx 7", "SLO abx 7",
+        "JSR abs 6", "AND izx 6", "JAM imp 2", "RLA izx 8", "BIT zp 3", "AND zp 3", "ROL zp 5", "RLA zp 5",
+        "PLP imp 4", "AND imm 2", "ROL acc 2", "ANC imm 2", "BIT abs 4", "AND abs 4", "ROL abs 6", "RLA abs 6",
+        "BMI rel 2", "AND izy 5*", "JAM imp 2", "RLA izy 8", "NOP zpx 4", "AND zpx 4", "ROL zpx 6", "RLA zpx 6",
+        "SEC imp 2", "AND aby 4*", "NOP imp 2", "RLA aby 7", "NOP abx 4*", "AND abx 4*", "ROL abx 7", "RLA abx 7",
+        "RTI imp 6", "EOR izx 6", "JAM imp 2", "SRE izx 8", "NOP zp 3", "EOR zp 3", "LSR zp 5", "SRE zp 5",
+        "PHA imp 3", "EOR imm 2", "LSR acc 2", "ALR imm 2", "JMP abs 3", "EOR abs 4", "LSR abs 6", "SRE abs 6",
+        "BVC rel 2", "EOR izy 5*", "JAM imp 2", "SRE izy 8", "NOP zpx 4", "EOR zpx 4", "LSR zpx 6", "SRE zpx 6",
+        "CLI imp 2", "EOR aby 4*", "NOP imp 2", "SRE aby 7", "NOP abx 4*", "EOR abx 4*", "LSR abx 7", "SRE abx 7",
+        "RTS imp 6", "ADC izx 6", "JAM imp 2", "RRA izx 8", "NOP zp 3", "ADC zp 3", "ROR zp 5", "RRA zp 5",
+        "PLA imp 4", "ADC imm 2", "ROR acc 2", "ARR imm 2", "JMP ind 5", "ADC abs 4", "ROR abs 6", "RRA abs 6",
+        "BVS rel 2", "ADC izy 5*", "JAM imp 2", "RRA izy 8", "NOP zpx 4", "ADC zpx 4", "ROR zpx 6", "RRA zpx 6",
+        "SEI imp 2", "ADC aby 4*", "NOP imp 2", "RRA aby 7", "NOP abx 4*", "ADC abx 4*", "ROR abx 7", "RRA abx 7",
+        "NOP imm 2", "STA izx 6", "NOP imm 2", "SAX izx 6", "STY zp 3", "STA zp 3", "STX zp 3", "SAX zp 3",
+        "DEY imp 2", "NOP imm 2", "TXA imp 2", "XAA imm 2", "STY abs 4", "STA abs 4", "STX abs 4", "SAX abs 4",
+        "BCC rel 2", "STA izy 6", "JAM imp 2", "SHA izy 6", "STY zpx 4", "STA zpx 4", "STX zpy 4", "SAX zpy 4",
+        "TYA imp 2", "STA aby 5", "TXS imp 2", "TAS aby 5", "SHY abx 5", "STA abx 5", "SHX aby 5", "SHA aby 5",
+        "LDY imm 2", "LDA izx 6", "LDX imm 2", "LAX izx 6", "LDY zp 3", "LDA zp 3", "LDX zp 3", "LAX zp 3",
+        "TAY imp 2", "LDA imm 2", "TAX imp 2", "LAX imm 2", "LDY abs 4", "LDA abs 4", "LDX abs 4", "LAX abs 4",
+        "BCS rel 2", "LDA izy 5*", "JAM imp 2", "LAX izy 5*", "LDY zpx 4", "LDA zpx 4", "LDX zpy 4", "LAX zpy 4",
+        "CLV imp 2", "LDA aby 4*", "TSX imp 2", "LAS aby 4*", "LDY abx 4*", "LDA abx 4*", "LDX aby 4*", "LAX aby 4*",
+        "CPY imm 2", "CMP izx 6", "NOP imm 2", "DCP izx 8", "CPY zp 3", "CMP zp 3", "DEC zp 5", "DCP zp 5",
+        "INY imp 2", "CMP imm 2", "DEX imp 2", "AXS imm 2", "CPY abs 4", "CMP abs 4", "DEC abs 6", "DCP abs 6",
+        "BNE rel 2", "CMP izy 5*", "JAM imp 2", "DCP izy 8", "NOP zpx 4", "CMP zpx 4", "DEC zpx 6", "DCP zpx 6",
+        "CLD imp 2", "CMP aby 4*", "NOP imp 2", "DCP aby 7", "NOP abx 4*", "CMP abx 4*", "DEC abx 7", "DCP abx 7",
+        "CPX imm 2", "SBC izx 6", "NOP imm 2", "ISC izx 8", "CPX zp 3", "SBC zp 3", "INC zp 5", "ISC zp 5",
+        "INX imp 2", "SBC imm 2", "NOP imp 2", "SBC imm 2", "CPX abs 4", "SBC abs 4", "INC abs 6", "ISC abs 6",
+        "BEQ rel 2", "SBC izy 5*", "JAM imp 2", "ISC izy 8", "NOP zpx 4", "SBC zpx 4", "INC zpx 6", "ISC zpx 6",
+        "SED imp 2", "SBC aby 4*", "NOP imp 2", "ISC aby 7", "NOP abx 4*", "SBC abx 4*", "INC abx 7", "ISC abx 7",
+    ];
+
+    /// Instruction lengths, one row per high nibble, from the usual 16x16
+    /// 6502 opcode matrices rather than from the addressing modes
+    const LENGTHS: [u8; 256] = [
+        1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        3, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    ];
+
+    /// Base cycle counts
+    const CYCLES: [u8; 256] = [
+        7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    ];
+
+    /// 1 where crossing a page while indexing costs an extra cycle
+    const PAGE_PENALTIES: [u8; 256] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1, 1, 1,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0,
+    ];
+
+    fn mode_name(mode: AddressingMode) -> (&'static str, u8) {
+        match mode {
+            AddressingMode::Implied => ("imp", 1),
+            AddressingMode::Accumulator => ("acc", 1),
+            AddressingMode::Immediate => ("imm", 2),
+            AddressingMode::ZeroPage => ("zp", 2),
+            AddressingMode::ZeroPageX => ("zpx", 2),
+            AddressingMode::ZeroPageY => ("zpy", 2),
+            AddressingMode::Relative => ("rel", 2),
+            AddressingMode::IndirectX => ("izx", 2),
+            AddressingMode::IndirectY => ("izy", 2),
+            AddressingMode::Absolute => ("abs", 3),
+            AddressingMode::AbsoluteX => ("abx", 3),
+            AddressingMode::AbsoluteY => ("aby", 3),
+            AddressingMode::Indirect => ("ind", 3),
+            AddressingMode::ZeroPageIndirect => ("izp", 2),
+            AddressingMode::AbsoluteIndexedIndirect => ("iax", 3),
+        }
+    }
+
+    #[test]
+    pub fn test_reference_table() {
+        for (opcode, (info, reference)) in OPCODES.iter().zip(REFERENCE.iter()).enumerate() {
+            let (mode, bytes) = mode_name(info.addressing_mode);
+            let penalty = if info.page_penalty { "*" } else { "" };
+            let entry = format!("{} {} {}{}", info.mnemonic, mode, info.cycles, penalty);
+            assert_eq!(&entry, reference, "opcode {:#04x}", opcode);
+            assert_eq!(info.bytes, bytes, "opcode {:#04x}", opcode);
+        }
+    }
+
+    #[test]
+    pub fn test_reference_matrices() {
+        for (opcode, info) in OPCODES.iter().enumerate() {
+            assert_eq!(info.bytes, LENGTHS[opcode], "length of opcode {:#04x}", opcode);
+            assert_eq!(info.cycles, CYCLES[opcode], "cycles of opcode {:#04x}", opcode);
+            assert_eq!(info.page_penalty as u8, PAGE_PENALTIES[opcode], "page penalty of opcode {:#04x}", opcode);
+        }
+
+        // Branches take 2 cycles, the taken and page crossing cycles are added when branching
+        for opcode in (0x10..=0xF0).step_by(0x20) {
+            let info = OpcodeInfo::decode(opcode);
+            assert_eq!(info.addressing_mode, AddressingMode::Relative);
+            assert_eq!((info.bytes, info.cycles, info.page_penalty), (2, 2, false));
+        }
+    }
+
+    #[test]
+    pub fn test_decode() {
+        let adc = OpcodeInfo::decode(0x7D);
+        assert_eq!(adc.mnemonic, InstructionType::ADC);
+        assert_eq!(adc.addressing_mode, AddressingMode::AbsoluteX);
+        assert_eq!((adc.bytes, adc.cycles, adc.page_penalty), (3, 4, true));
+        assert_eq!(OpcodeInfo::decode(0x90).mnemonic, InstructionType::BCC);
+        assert_eq!(OpcodeInfo::decode(0xB0).mnemonic, InstructionType::BCS);
+        assert_eq!(OpcodeInfo::decode(0xAA).mnemonic, InstructionType::TAX);
+        assert_eq!(format!("{}", OpcodeInfo::decode(0xEB).mnemonic), "SBC");
+    }
+
+    #[test]
+    pub fn test_documented() {
+        assert_eq!((0..=0xFF).filter(|&opcode| OpcodeInfo::documented(opcode)).count(), 151);
+        assert!(OpcodeInfo::documented(0xEA));
+        assert!(!OpcodeInfo::documented(0x1A));
+        assert!(OpcodeInfo::documented(0xE9));
+        assert!(!OpcodeInfo::documented(0xEB));
+        assert!(!OpcodeInfo::documented(0xA7));
+    }
+
+    #[test]
+    pub fn test_65c02_table() {
+        let entry = |opcode: usize| {
+            let info = &OPCODES_65C02[opcode];
+            let (mode, _) = mode_name(info.addressing_mode);
+            format!("{} {} {}{}", info.mnemonic, mode, info.cycles, if info.page_penalty { "*" } else { "" })
+        };
+        assert_eq!(entry(0x12), "ORA izp 5");
+        assert_eq!(entry(0x6C), "JMP ind 6");
+        assert_eq!(entry(0x7C), "JMP iax 6");
+        assert_eq!(entry(0x9E), "STZ abx 5");
+        assert_eq!(entry(0x1E), "ASL abx 6*");
+        assert_eq!(entry(0xFE), "INC abx 7");
+        assert_eq!(entry(0x02), "NOP imm 2");
+        assert_eq!(entry(0x5C), "NOP abs 8");
+        // Every opcode ending in 11 is a single cycle NOP
+        for opcode in (0..256).filter(|opcode| opcode & 0x03 == 0x03) {
+            assert_eq!(entry(opcode), "NOP imp 1");
+        }
+        // Nothing from the NMOS unofficial set survives
+        for info in OPCODES_65C02.iter() {
+            assert!(!matches!(info.mnemonic, InstructionType::LAX | InstructionType::JAM
+                | InstructionType::SLO | InstructionType::SHA | InstructionType::XAA));
         }
-        _ => {
-        }
-    }
-
-    Instruction {
-        value: value,
-        itype: itype,
-        addressing_mode: addressing_mode,
-        cycles: cycles,
-        bytes: bytes,
     }
 }
-
-impl Instruction {
-    pub fn new(value: u8) -> Instruction {
-        let itype = InstructionType::NOP;
-        let addressing_mode = AddressingMode::Immediate;
-        let cycles: u8 = 1;
-        let bytes: u8 = 1;
-
-        match value {
-            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                return adc(value);
-            }
-            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
-                return and(value);
-            }
-            0x0A | 0x06 | 0x16 | 0x0E | 0x1E => {
-                return asl(value);
-
-            }
-            0xB0 => {
-                return Instruction {
-                    value: value,
-                    itype: InstructionType::BCC, bytes: 2, cycles: 2,
-                    addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0x90 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BCS,
-                    bytes: 2, cycles: 2, addressing_mode: AddressingMode::Relative
-                }
-            }
-            0xF0 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BEQ,
-                    bytes: 2, cycles: 2, addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0x24 | 0x2C => {
-                return bit(value);
-            }
-            0x30 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BMI, bytes: 2,
-                    cycles: 2, addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0xD0 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BNE, bytes: 2,
-                    cycles: 2, addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0x10 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BPL, bytes: 2,
-                    cycles: 2, addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0x00 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BRK, bytes: 1,
-                    cycles: 7, addressing_mode: AddressingMode::Implied,
-                }
-            }
-            0x50 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BVC, bytes: 2,
-                    cycles: 2, addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0x70 => {
-                return Instruction {
-                    value: value, itype: InstructionType::BVS, bytes: 2,
-                    cycles: 2, addressing_mode: AddressingMode::Relative,
-                }
-            }
-            0x18 => {
-                return Instruction {
-                    value: value, itype: InstructionType::CLC, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                }
-            }
-            0xD8 => {
-                return Instruction {
-                    value: value, itype: InstructionType::CLD, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x58 => {
-                return Instruction {
-                    value: value, itype: InstructionType::CLI, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xB8 => {
-                return Instruction {
-                    value: value, itype: InstructionType::CLV, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                return cmp(value);
-            }
-            0xC0 | 0xC4 | 0xCC => {
-                return cpy(value);
-            }
-            0xC6 | 0xD6 | 0xCE | 0xDE => {
-                return dec(value);
-            }
-            0xCA => {
-                return Instruction {
-                    value: value, itype: InstructionType::DEX, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x88 => {
-                return Instruction {
-                    value: value, itype: InstructionType::DEY, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
-                return eor(value);
-            }
-            0xE6 | 0xF6 | 0xEE | 0xFE => {
-                return inc(value);
-            }
-            0xE8 => {
-                return Instruction {
-                    value: value, itype: InstructionType::INX, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xC8 => {
-                return Instruction {
-                    value: value, itype: InstructionType::INY, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x4C | 0x6C => {
-                return jmp(value);
-            }
-            0x20 => {
-                return Instruction {
-                    value: value, itype: InstructionType::JSR, bytes: 3,
-                    cycles: 6, addressing_mode: AddressingMode::Absolute,
-                };
-            }
-            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                return lda(value);
-            }
-            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                return ldx(value);
-            }
-            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
-                return ldy(value);
-            }
-            0x4A | 0x46 | 0x56 | 0x4E | 0x5E => {
-                return lsr(value);
-            }
-            0xEA => {
-                return Instruction {
-                    value: value, itype: InstructionType::NOP, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x09 | 0x15 | 0x05 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
-                return ora(value);
-            }
-            0x48 => {
-                return Instruction {
-                    value: value, itype: InstructionType::PHA, bytes: 1,
-                    cycles: 3, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x08 => {
-                return Instruction {
-                    value: value, itype: InstructionType::PHP, bytes:1,
-                    cycles: 3, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x68 => {
-                return Instruction {
-                    value: value, itype: InstructionType::PLA, bytes: 1,
-                    cycles: 4, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x28 => {
-                return Instruction {
-                    value: value, itype: InstructionType::PLP, bytes: 1,
-                    cycles: 4, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x2A | 0x26 | 0x36 | 0x2E | 0x3E => {
-                return rol(value);
-            }
-            0x6A | 0x66 | 0x76 | 0x6E | 0x7E => {
-                return ror(value);
-            }
-            0x40 => {
-                return Instruction {
-                    value: value, itype: InstructionType::RTI, bytes: 1,
-                    cycles: 6, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x60 => {
-                return Instruction {
-                    value: value, itype: InstructionType::RTS, bytes: 1,
-                    cycles: 6, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                return sbc(value);
-            }
-            0x38 => {
-                return Instruction {
-                    value: value, itype: InstructionType::SEC, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xF8 => {
-                return Instruction {
-                    value: value, itype: InstructionType::SED, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x78 => {
-                return Instruction {
-                    value: value, itype: InstructionType::SEI, bytes: 1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                return sta(value);
-            }
-            0x86 | 0x96 | 0x8E => {
-                return stx(value);
-            }
-            0x84 | 0x94 | 0x8C => {
-                return sty(value);
-            }
-            0xAA => {
-                Instruction {
-                    value: value, itype: InstructionType::TAX, bytes:1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xA8 => {
-                Instruction {
-                    value: value, itype: InstructionType::TAY, bytes:1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0xBA => {
-                Instruction {
-                    value: value, itype: InstructionType::TSX, bytes:1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x8A => {
-                Instruction {
-                    value: value, itype: InstructionType::TXA, bytes:1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            0x9A => {
-                Instruction {
-                    value: value, itype: InstructionType::TXS, bytes:1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };           }
-            0x98 => {
-                Instruction {
-                    value: value, itype: InstructionType::TYA, bytes:1,
-                    cycles: 2, addressing_mode: AddressingMode::Implied,
-                };
-            }
-            _ => {
-                println!("Illeagal instruction? {}", value);
-            }
-        }
-
-        Instruction {
-            value: value,
-            itype: itype,
-            addressing_mode: addressing_mode,
-            cycles: cycles,
-            bytes: bytes,
-        }
-    }
-    pub fn print(&self) {
-        println!("Instruction: {:?}, Addressing {:?}", self.itype, self.addressing_mode);
-    }
-
-}
\ No newline at end of file
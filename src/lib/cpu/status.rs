@@ -0,0 +1,184 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+/// 6502 processor status register (P).
+///
+/// Flags are combined with `|` and tested with `contains`, like a bitflags type.
+/// B and the unused bit 5 have no storage in the CPU: the unused bit always
+/// reads as 1 and B only appears in copies of P pushed to the stack.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Status(u8);
+
+impl Status {
+    /// Carry
+    pub const C: Status = Status(1 << 0);
+    /// Zero
+    pub const Z: Status = Status(1 << 1);
+    /// Interrupt disable
+    pub const I: Status = Status(1 << 2);
+    /// Decimal mode
+    pub const D: Status = Status(1 << 3);
+    /// Break, set in the byte pushed by PHP and BRK
+    pub const B: Status = Status(1 << 4);
+    /// Unused, always set
+    pub const U: Status = Status(1 << 5);
+    /// Overflow
+    pub const V: Status = Status(1 << 6);
+    /// Negative
+    pub const N: Status = Status(1 << 7);
+
+    /// Status with no flags set
+    pub const fn empty() -> Status {
+        Status(0)
+    }
+
+    /// Status with exactly the given bits set
+    pub const fn from_bits(bits: u8) -> Status {
+        Status(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// True when every flag of `other` is set
+    pub const fn contains(self, other: Status) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Status) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Status) {
+        self.0 &= !other.0;
+    }
+
+    /// Insert or remove flags
+    pub fn set(&mut self, other: Status, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+
+    /// Register value after PLP or RTI, which ignore B and the unused bit
+    pub const fn from_pulled(value: u8) -> Status {
+        Status((value & !Status::B.0) | Status::U.0)
+    }
+
+    /// Byte pushed to the stack. B is set for PHP and BRK, clear for IRQ and NMI.
+    pub const fn pushed(self, brk: bool) -> u8 {
+        let value = self.0 | Status::U.0;
+        if brk {
+            value | Status::B.0
+        } else {
+            value & !Status::B.0
+        }
+    }
+
+    pub fn carry(self) -> bool {
+        self.contains(Status::C)
+    }
+
+    pub fn set_carry(&mut self, value: bool) {
+        self.set(Status::C, value);
+    }
+
+    pub fn zero(self) -> bool {
+        self.contains(Status::Z)
+    }
+
+    pub fn set_zero(&mut self, value: bool) {
+        self.set(Status::Z, value);
+    }
+
+    pub fn interrupt_disable(self) -> bool {
+        self.contains(Status::I)
+    }
+
+    pub fn set_interrupt_disable(&mut self, value: bool) {
+        self.set(Status::I, value);
+    }
+
+    pub fn decimal(self) -> bool {
+        self.contains(Status::D)
+    }
+
+    pub fn set_decimal(&mut self, value: bool) {
+        self.set(Status::D, value);
+    }
+
+    pub fn overflow(self) -> bool {
+        self.contains(Status::V)
+    }
+
+    pub fn set_overflow(&mut self, value: bool) {
+        self.set(Status::V, value);
+    }
+
+    pub fn negative(self) -> bool {
+        self.contains(Status::N)
+    }
+
+    pub fn set_negative(&mut self, value: bool) {
+        self.set(Status::N, value);
+    }
+}
+
+impl BitOr for Status {
+    type Output = Status;
+
+    fn bitor(self, other: Status) -> Status {
+        Status(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Status {
+    fn bitor_assign(&mut self, other: Status) {
+        self.0 |= other.0;
+    }
+}
+
+/// Flags as letters, lowercase when clear: "NV-bDIZC"
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, letter) in "NV-BDIZC".chars().enumerate() {
+            let set = self.0 & (0x80 >> i) != 0;
+            let letter = if set { letter } else { letter.to_ascii_lowercase() };
+            write!(f, "{}", letter)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Status;
+
+    #[test]
+    pub fn test_layout() {
+        assert_eq!((Status::N | Status::V | Status::U).bits(), 0xE0);
+        assert_eq!((Status::C | Status::Z | Status::I | Status::D).bits(), 0x0F);
+
+        let mut status = Status::empty();
+        status.set_overflow(true);
+        status.set_carry(true);
+        assert_eq!(status.bits(), 0x41);
+        assert!(status.overflow());
+        assert!(!status.negative());
+        status.set_carry(false);
+        assert_eq!(status, Status::V);
+    }
+
+    #[test]
+    pub fn test_stack_copies() {
+        let status = Status::C | Status::I;
+        assert_eq!(status.pushed(true), 0x35);
+        assert_eq!(status.pushed(false), 0x25);
+        assert_eq!(Status::from_pulled(0xFF).bits(), 0xEF);
+        assert_eq!(Status::from_pulled(0x00), Status::U);
+        assert_eq!(format!("{}", Status::from_pulled(0xC3)), "NV-bdiZC");
+    }
+}
@@ -0,0 +1,122 @@
+use super::{CPU, InstructionType, AddressingMode, Variant};
+use crate::disassembler::{Disassembler, Line};
+use crate::memory::CpuBus;
+
+/// PPU dots per CPU cycle on NTSC
+const DOTS_PER_CYCLE: u64 = 3;
+/// Dots per scanline
+const DOTS_PER_LINE: u64 = 341;
+/// Scanlines per frame, pre-render line included
+const LINES_PER_FRAME: u64 = 262;
+
+/// Receives a trace line before each instruction
+pub type TraceHook = Box<dyn FnMut(&str)>;
+
+impl CPU {
+    /// Call `hook` with a trace line before each instruction, None to stop
+    pub fn set_trace(&mut self, hook: Option<TraceHook>) {
+        self.trace = hook;
+    }
+
+    /// Cycles run since the CPU was created
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    /// Pass the instruction about to run to the trace hook
+    pub(super) fn trace_instruction<B: CpuBus>(&mut self, bus: &B) {
+        let line = self.trace_line(bus);
+        if let Some(hook) = &mut self.trace {
+            hook(&line);
+        }
+    }
+
+    /// Describe the instruction at PC and the registers in the format of the
+    /// Nintendulator logs, nestest.log among them:
+    ///
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+    ///
+    /// The PPU does not keep its position yet, it is derived from the cycle count.
+    pub fn trace_line<B: CpuBus>(&self, bus: &B) -> String {
+        let line = Disassembler::new(self.variant).line(bus, self.pc);
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        // Nintendulator marks undocumented opcodes and names ISC after its other alias
+        let marker = if line.documented { ' ' } else { '*' };
+        let mnemonic = match line.mnemonic {
+            InstructionType::ISC => String::from("ISB"),
+            mnemonic => mnemonic.to_string(),
+        };
+        let disassembly = if line.operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}{}", mnemonic, line.operand, self.trace_values(bus, &line))
+        };
+
+        let dots = self.cycle_count * DOTS_PER_CYCLE;
+        format!("{:04X}  {:<9}{}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+                self.pc, bytes.join(" "), marker, disassembly,
+                self.a, self.x, self.y, self.status.bits(), self.sp,
+                dots / DOTS_PER_LINE % LINES_PER_FRAME, dots % DOTS_PER_LINE, self.cycle_count)
+    }
+
+    /// Addresses the operand resolves to and the value found there
+    fn trace_values<B: CpuBus>(&self, bus: &B, line: &Line) -> String {
+        let operand = line.target.unwrap_or(0);
+        let zero_page_word = |pointer: usize| {
+            bus.peek(pointer & 0xFF) as usize | (bus.peek((pointer + 1) & 0xFF) as usize) << 8
+        };
+        let value = |address: usize| bus.peek(address & 0xFFFF);
+
+        match line.addressing_mode {
+            AddressingMode::Implied | AddressingMode::Accumulator
+                | AddressingMode::Immediate | AddressingMode::Relative => String::new(),
+            AddressingMode::ZeroPage => format!(" = {:02X}", value(operand)),
+            AddressingMode::ZeroPageX => {
+                let address = (operand + self.x as usize) & 0xFF;
+                format!(" @ {:02X} = {:02X}", address, value(address))
+            }
+            AddressingMode::ZeroPageY => {
+                let address = (operand + self.y as usize) & 0xFF;
+                format!(" @ {:02X} = {:02X}", address, value(address))
+            }
+            AddressingMode::Absolute => match line.mnemonic {
+                InstructionType::JMP | InstructionType::JSR => String::new(),
+                _ => format!(" = {:02X}", value(operand)),
+            },
+            AddressingMode::AbsoluteX => {
+                let address = (operand + self.x as usize) & 0xFFFF;
+                format!(" @ {:04X} = {:02X}", address, value(address))
+            }
+            AddressingMode::AbsoluteY => {
+                let address = (operand + self.y as usize) & 0xFFFF;
+                format!(" @ {:04X} = {:02X}", address, value(address))
+            }
+            AddressingMode::Indirect => {
+                let high = if self.variant == Variant::Cmos65C02 {
+                    (operand + 1) & 0xFFFF
+                } else {
+                    (operand & 0xFF00) | ((operand + 1) & 0xFF)
+                };
+                format!(" = {:04X}", value(operand) as usize | (value(high) as usize) << 8)
+            }
+            AddressingMode::IndirectX => {
+                let pointer = (operand + self.x as usize) & 0xFF;
+                let address = zero_page_word(pointer);
+                format!(" @ {:02X} = {:04X} = {:02X}", pointer, address, value(address))
+            }
+            AddressingMode::IndirectY => {
+                let base = zero_page_word(operand);
+                let address = (base + self.y as usize) & 0xFFFF;
+                format!(" = {:04X} @ {:04X} = {:02X}", base, address, value(address))
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let address = zero_page_word(operand);
+                format!(" = {:04X} = {:02X}", address, value(address))
+            }
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let pointer = (operand + self.x as usize) & 0xFFFF;
+                format!(" = {:04X}", value(pointer) as usize | (value(pointer + 1) as usize) << 8)
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::{AddressingMode, InstructionType, OpcodeInfo, Variant, OPCODES, OPCODES_65C02};
+use crate::memory::CpuBus;
+
+/// One disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    /// Opcode and operand bytes
+    pub bytes: Vec<u8>,
+    pub mnemonic: InstructionType,
+    pub addressing_mode: AddressingMode,
+    /// Operand as written in assembly, `$12,X` or `($34),Y`, empty when implied
+    pub operand: String,
+    /// Address the operand names: the zero page or absolute address, the
+    /// pointer of indirect modes, the destination of branches
+    pub target: Option<usize>,
+    /// False for the undocumented NMOS opcodes
+    pub documented: bool,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "{:04X}  {:<8}  {}", self.address, bytes.join(" "), self.mnemonic)?;
+        if !self.operand.is_empty() {
+            write!(f, " {}", self.operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Turns memory back into assembly, using the opcode table of a CPU model
+pub struct Disassembler {
+    variant: Variant,
+    opcodes: &'static [OpcodeInfo; 256],
+    /// Names printed instead of the addresses they label
+    symbols: HashMap<usize, String>,
+}
+
+impl Disassembler {
+    pub fn new(variant: Variant) -> Disassembler {
+        Disassembler {
+            variant,
+            opcodes: if variant == Variant::Cmos65C02 { &OPCODES_65C02 } else { &OPCODES },
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Print these names instead of the addresses they label
+    pub fn with_symbols(mut self, symbols: HashMap<usize, String>) -> Disassembler {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Disassemble the instruction at an address
+    pub fn line<B: CpuBus>(&self, bus: &B, address: usize) -> Line {
+        let address = address & 0xFFFF;
+        let opcode = bus.peek(address);
+        let instruction = &self.opcodes[opcode as usize];
+        let bytes: Vec<u8> = (0..instruction.bytes as usize)
+            .map(|i| bus.peek((address + i) & 0xFFFF))
+            .collect();
+        let byte = bytes.get(1).copied().unwrap_or(0) as usize;
+        let word = byte | (bytes.get(2).copied().unwrap_or(0) as usize) << 8;
+
+        let (operand, target) = match instruction.addressing_mode {
+            AddressingMode::Implied => (String::new(), None),
+            AddressingMode::Accumulator => (String::from("A"), None),
+            AddressingMode::Immediate => (format!("#${:02X}", byte), None),
+            AddressingMode::ZeroPage => (self.name(byte, 2), Some(byte)),
+            AddressingMode::ZeroPageX => (format!("{},X", self.name(byte, 2)), Some(byte)),
+            AddressingMode::ZeroPageY => (format!("{},Y", self.name(byte, 2)), Some(byte)),
+            AddressingMode::Absolute => (self.name(word, 4), Some(word)),
+            AddressingMode::AbsoluteX => (format!("{},X", self.name(word, 4)), Some(word)),
+            AddressingMode::AbsoluteY => (format!("{},Y", self.name(word, 4)), Some(word)),
+            AddressingMode::Indirect => (format!("({})", self.name(word, 4)), Some(word)),
+            AddressingMode::IndirectX => (format!("({},X)", self.name(byte, 2)), Some(byte)),
+            AddressingMode::IndirectY => (format!("({}),Y", self.name(byte, 2)), Some(byte)),
+            AddressingMode::ZeroPageIndirect => (format!("({})", self.name(byte, 2)), Some(byte)),
+            AddressingMode::AbsoluteIndexedIndirect => (format!("({},X)", self.name(word, 4)), Some(word)),
+            AddressingMode::Relative => {
+                let destination = (address + 2).wrapping_add(byte as i8 as usize) & 0xFFFF;
+                (self.name(destination, 4), Some(destination))
+            }
+        };
+
+        Line {
+            address,
+            bytes,
+            mnemonic: instruction.mnemonic,
+            addressing_mode: instruction.addressing_mode,
+            operand,
+            target,
+            documented: self.variant == Variant::Cmos65C02 || OpcodeInfo::documented(opcode),
+        }
+    }
+
+    /// Disassemble the instructions starting from `start` up to `end`, excluded
+    pub fn range<B: CpuBus>(&self, bus: &B, start: usize, end: usize) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut address = start;
+        while address < end {
+            let line = self.line(bus, address);
+            address += line.bytes.len();
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Symbol of an address, or the address in hex with the given number of digits
+    fn name(&self, address: usize, digits: usize) -> String {
+        match self.symbols.get(&address) {
+            Some(symbol) => symbol.clone(),
+            None => format!("${:0width$X}", address, width = digits),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlatMemory;
+
+    fn memory(start: usize, program: &[u8]) -> FlatMemory {
+        let mut memory = FlatMemory::new();
+        memory.load(start, program);
+        memory
+    }
+
+    #[test]
+    pub fn test_operands() {
+        let program = [
+            0xA9, 0x01,             // LDA #$01
+            0x0A,                   // ASL A
+            0x95, 0x12,             // STA $12,X
+            0xB6, 0x12,             // LDX $12,Y
+            0xB1, 0x34,             // LDA ($34),Y
+            0x81, 0x34,             // STA ($34,X)
+            0x7D, 0x00, 0x20,       // ADC $2000,X
+            0x6C, 0xFC, 0xFF,       // JMP ($FFFC)
+            0xD0, 0xEE,             // BNE $C001
+            0x07, 0x10,             // SLO $10
+            0xE8,                   // INX
+        ];
+        let lines = Disassembler::new(Variant::Ricoh2A03).range(&memory(0xC000, &program), 0xC000, 0xC000 + program.len());
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(text, [
+            "C000  A9 01     LDA #$01",
+            "C002  0A        ASL A",
+            "C003  95 12     STA $12,X",
+            "C005  B6 12     LDX $12,Y",
+            "C007  B1 34     LDA ($34),Y",
+            "C009  81 34     STA ($34,X)",
+            "C00B  7D 00 20  ADC $2000,X",
+            "C00E  6C FC FF  JMP ($FFFC)",
+            "C011  D0 EE     BNE $C001",
+            "C013  07 10     SLO $10",
+            "C015  E8        INX",
+        ]);
+        assert_eq!(lines[8].target, Some(0xC001));
+        assert_eq!(lines[6].target, Some(0x2000));
+        assert!(lines[8].documented);
+        assert!(!lines[9].documented);
+        assert_eq!(lines[10].operand, "");
+    }
+
+    #[test]
+    pub fn test_symbols() {
+        // loop: LDA $2002, BPL loop, STA ($10),Y, JSR $8000
+        let program = [0xAD, 0x02, 0x20, 0x10, 0xFB, 0x91, 0x10, 0x20, 0x00, 0x80];
+        let symbols = HashMap::from([
+            (0xC000, String::from("loop")),
+            (0x2002, String::from("PPUSTATUS")),
+            (0x10, String::from("pointer")),
+        ]);
+        let disassembler = Disassembler::new(Variant::Ricoh2A03).with_symbols(symbols);
+        let operands: Vec<String> = disassembler.range(&memory(0xC000, &program), 0xC000, 0xC00A)
+            .into_iter().map(|line| line.operand).collect();
+        assert_eq!(operands, ["PPUSTATUS", "loop", "(pointer),Y", "$8000"]);
+    }
+
+    #[test]
+    pub fn test_65c02() {
+        // BRA, STA ($12), JMP ($1234,X), a one byte NOP
+        let program = [0x80, 0x02, 0x92, 0x12, 0x7C, 0x34, 0x12, 0x03];
+        let lines = Disassembler::new(Variant::Cmos65C02).range(&memory(0x0200, &program), 0x0200, 0x0208);
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(text, [
+            "0200  80 02     BRA $0204",
+            "0202  92 12     STA ($12)",
+            "0204  7C 34 12  JMP ($1234,X)",
+            "0207  03        NOP",
+        ]);
+        assert!(lines.iter().all(|line| line.documented));
+    }
+
+    #[test]
+    pub fn test_range_wraps() {
+        // JMP $C000 at the top of memory, the operand wraps to the bottom
+        let mut memory = memory(0xFFFF, &[0x4C]);
+        memory.load(0x0000, &[0x00, 0xC0]);
+        let lines = Disassembler::new(Variant::Nmos6502).range(&memory, 0xFFFF, 0x10000);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].to_string(), "FFFF  4C 00 C0  JMP $C000");
+    }
+}
@@ -0,0 +1,221 @@
+/// What the CPU sees of the system it is plugged into
+pub trait CpuBus {
+    /// Read a byte, with whatever side effects the device has
+    fn read(&mut self, address: usize) -> u8;
+
+    fn write(&mut self, address: usize, value: u8);
+
+    /// Read without side effects, for debuggers and traces
+    fn peek(&self, address: usize) -> u8;
+
+    /// Called at the end of every CPU cycle, to clock the other devices
+    fn tick(&mut self) {}
+
+    /// State of the IRQ line, true when asserted
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// True when the NMI line became asserted and the NMI was not serviced yet
+    fn nmi_edge(&self) -> bool {
+        false
+    }
+
+    /// Clear the detected NMI edge once the CPU starts servicing it
+    fn acknowledge_nmi(&mut self) {}
+}
+
+/// One CPU bus access, as recorded by the access log
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: usize,
+    pub value: u8,
+    pub write: bool,
+}
+
+/// 64K of plain RAM, for running the CPU outside of the NES
+pub struct FlatMemory {
+    pub memory: Vec<u8>,
+    /// Level of the IRQ line, true while asserted
+    irq_line: bool,
+    /// Level of the NMI line, true while asserted
+    nmi_line: bool,
+    /// Set when the NMI line becomes asserted
+    nmi_edge: bool,
+    /// Accesses made since logging was enabled
+    access_log: Option<Vec<BusAccess>>,
+}
+
+impl FlatMemory {
+    pub fn new() -> FlatMemory {
+        FlatMemory {
+            memory: vec![0; 0x10000],
+            irq_line: false,
+            nmi_line: false,
+            nmi_edge: false,
+            access_log: None,
+        }
+    }
+
+    /// Copy bytes to memory from an address on
+    pub fn load(&mut self, start: usize, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.memory[(start + i) & 0xFFFF] = byte;
+        }
+    }
+
+    pub fn set_irq(&mut self, active: bool) {
+        self.irq_line = active;
+    }
+
+    /// Drive the NMI line. Only the transition to asserted triggers an NMI.
+    pub fn set_nmi(&mut self, active: bool) {
+        if active && !self.nmi_line {
+            self.nmi_edge = true;
+        }
+        self.nmi_line = active;
+    }
+
+    /// Start or stop recording every read and write
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.access_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Accesses recorded since the last call, oldest first
+    pub fn take_access_log(&mut self) -> Vec<BusAccess> {
+        match &mut self.access_log {
+            Some(log) => std::mem::take(log),
+            None => Vec::new(),
+        }
+    }
+
+    fn log_access(&mut self, address: usize, value: u8, write: bool) {
+        if let Some(log) = &mut self.access_log {
+            log.push(BusAccess { address, value, write });
+        }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> FlatMemory {
+        FlatMemory::new()
+    }
+}
+
+impl CpuBus for FlatMemory {
+    fn read(&mut self, address: usize) -> u8 {
+        let value = self.memory[address & 0xFFFF];
+        self.log_access(address, value, false);
+        value
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        self.log_access(address, value, true);
+        self.memory[address & 0xFFFF] = value;
+    }
+
+    fn peek(&self, address: usize) -> u8 {
+        self.memory[address & 0xFFFF]
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_line
+    }
+
+    fn nmi_edge(&self) -> bool {
+        self.nmi_edge
+    }
+
+    fn acknowledge_nmi(&mut self) {
+        self.nmi_edge = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CPU;
+
+    /// Flat memory counting the cycles it is clocked for
+    struct CountingBus {
+        memory: FlatMemory,
+        ticks: usize,
+    }
+
+    impl CpuBus for CountingBus {
+        fn read(&mut self, address: usize) -> u8 {
+            self.memory.read(address)
+        }
+
+        fn write(&mut self, address: usize, value: u8) {
+            self.memory.write(address, value);
+        }
+
+        fn peek(&self, address: usize) -> u8 {
+            self.memory.peek(address)
+        }
+
+        fn tick(&mut self) {
+            self.ticks += 1;
+        }
+    }
+
+    #[test]
+    pub fn test_flat_memory() {
+        // LDA #$42, STA $8000, INC $FFFF, JAM
+        let mut memory = FlatMemory::new();
+        memory.load(0x1000, &[0xA9, 0x42, 0x8D, 0x00, 0x80, 0xEE, 0xFF, 0xFF, 0x02]);
+        memory.set_access_log(true);
+        let mut cpu = CPU::new();
+        cpu.set_pc(0x1000);
+        while !cpu.halted() {
+            cpu.tick(&mut memory);
+        }
+        assert_eq!(memory.memory[0x8000], 0x42);
+        assert_eq!(memory.memory[0xFFFF], 0x01);
+        let log = memory.take_access_log();
+        assert!(log.contains(&BusAccess { address: 0x8000, value: 0x42, write: true }));
+        assert_eq!(log.iter().filter(|access| access.write).count(), 2);
+        assert!(memory.take_access_log().is_empty());
+    }
+
+    #[test]
+    pub fn test_tick_hook() {
+        // NOP, LDA $12FF,X
+        let mut bus = CountingBus { memory: FlatMemory::new(), ticks: 0 };
+        bus.memory.load(0x0200, &[0xEA, 0xBD, 0xFF, 0x12]);
+        for cycle_accurate in [false, true] {
+            let mut cpu = CPU::new();
+            cpu.set_cycle_accurate(cycle_accurate);
+            cpu.set_pc(0x0200);
+            bus.ticks = 0;
+            for _ in 0..2 {
+                cpu.tick(&mut bus);
+                while cpu.cycles > 0 {
+                    cpu.tick(&mut bus);
+                }
+            }
+            assert_eq!(bus.ticks, 2 + 4);
+        }
+    }
+
+    #[test]
+    pub fn test_interrupt_lines() {
+        let mut memory = FlatMemory::new();
+        memory.load(0xFFFA, &[0x00, 0x90, 0x00, 0x00, 0x00, 0xA0]);
+        memory.load(0x0200, &[0xEA; 4]);
+        let mut cpu = CPU::new();
+        cpu.set_pc(0x0200);
+        memory.set_irq(true);
+        cpu.tick(&mut memory);
+        cpu.tick(&mut memory);
+        cpu.tick(&mut memory);
+        assert_eq!(cpu.pc(), 0xA000);
+
+        memory.set_nmi(true);
+        while cpu.pc() != 0x9000 {
+            cpu.tick(&mut memory);
+        }
+        assert!(!memory.nmi_edge());
+    }
+}
@@ -31,22 +31,22 @@ impl PPU {
     }
 
     pub fn controller(&self, bus: &Bus) -> u8 {
-        bus.read(CONTROLLER)
+        bus.peek(CONTROLLER)
     }
     pub fn mask(&self, bus: &Bus) -> u8 {
-        bus.read(MASK)
+        bus.peek(MASK)
     }
     pub fn scroll(&self, bus: &Bus) -> u8 {
-        bus.read(SCROLL)
+        bus.peek(SCROLL)
     }
     pub fn status(&self, bus: &Bus) -> u8 {
-        bus.read(STATUS)
+        bus.peek(STATUS)
     }
     pub fn oam_address(&self, bus: &Bus) -> u8 {
-        bus.read(OAM_ADDRESS)
+        bus.peek(OAM_ADDRESS)
     }
     pub fn oam_data(&self, bus: &Bus) -> u8 {
-        bus.read(OAM_DATA)
+        bus.peek(OAM_DATA)
     }
 
     pub fn tick(&mut self, bus: &mut Bus) {
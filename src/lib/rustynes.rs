@@ -1,13 +1,25 @@
 mod cpu;
 mod ppu;
 mod bus;
+mod memory;
+mod disassembler;
+mod assembler;
 mod cartridge;
 
-pub use cpu::CPU;
+pub use cpu::{CPU, Status, Variant, TraceHook};
 pub use ppu::PPU;
-pub use cpu::Instruction;
-pub use bus::Bus;
-pub use cartridge::Cartridge;
+pub use cpu::{OpcodeInfo, OPCODES};
+pub use bus::{Bus, IrqSource};
+pub use memory::{CpuBus, BusAccess, FlatMemory};
+pub use disassembler::{Disassembler, Line};
+pub use assembler::{Assembler, AssemblyError, AssemblyErrorKind, Program, Segment};
+pub use cartridge::{Cartridge, CartridgeError, CartridgeInfo, ConsoleType, HeaderFormat, Mapper, Mirroring, Timing};
+pub use cartridge::DEFAULT_AUTOSAVE_CYCLES;
+
+/// Entry point of nestest's automation mode, which runs without a PPU
+pub const NESTEST_START: usize = 0xC000;
+/// nestest is done well before this, stop anyway if it goes astray
+const NESTEST_MAX_CYCLES: u64 = 100_000;
 
 pub struct NES {
     pub bus:Bus,
@@ -27,10 +39,43 @@ impl NES {
         }
     }
 
+    /// Turn the console on and start the program at the reset vector
+    pub fn power_on(&mut self) {
+        self.bus.power_on();
+        self.cpu.power_on(&mut self.bus);
+        self.clock_count = 0;
+    }
+
+    /// Press the reset button
+    pub fn reset(&mut self) {
+        self.bus.reset();
+        self.cpu.reset(&mut self.bus);
+    }
+
+    /// Run the loaded nestest ROM in automation mode, passing a trace line
+    /// for each instruction to `hook`. Stops when the tests return out of
+    /// the ROM; the results are left at $02 and $03, zero when all passed.
+    pub fn run_nestest(&mut self, hook: TraceHook) {
+        self.power_on();
+        self.cpu.set_pc(NESTEST_START);
+        self.cpu.set_trace(Some(hook));
+        while !self.cpu.halted() && self.cpu.cycle_count() < NESTEST_MAX_CYCLES
+            && (NESTEST_START..=0xFFFF).contains(&self.cpu.pc()) {
+            self.tick();
+        }
+        self.cpu.set_trace(None);
+    }
+
     pub fn tick(&mut self) {
         self.cpu.tick(&mut self.bus);
         self.clock_count += 1;
         self.clock_count &= 0xFFFF;
     }
 
+    /// Current audio level. There is no APU yet, so this is the cartridge's
+    /// expansion audio alone.
+    pub fn audio_sample(&self) -> f32 {
+        self.bus.cartridge.audio_output()
+    }
+
 }
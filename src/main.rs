@@ -1,23 +1,60 @@
-use rustyneslib::NES;
+use rustyneslib::{NES, Assembler, Variant};
 use std::io::{stdin};
+use std::process::exit;
+
+/// `--nestest <rom>`: run nestest in automation mode and print its trace log
+fn nestest(path: &str) {
+    let mut nes = NES::new();
+    if let Err(error) = nes.bus.cartridge.load(path.to_string()) {
+        eprintln!("Cannot load {}: {:?}", path, error);
+        exit(1);
+    }
+    nes.run_nestest(Box::new(|line| println!("{}", line)));
+    let (official, unofficial) = (nes.bus.read(0x02), nes.bus.read(0x03));
+    eprintln!("nestest results: ${:02X} ${:02X}", official, unofficial);
+    if official != 0 || unofficial != 0 {
+        exit(1);
+    }
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 3 && args[1] == "--nestest" {
+        nestest(&args[2]);
+        return;
+    }
+
     let mut s=String::new();
 
     println!("Hello, world!");
     let mut nes = NES::new();
-    let program = vec!(0xA2, 0x0A, 0x8E, 0x00, 0x00, 0xA2, 0x03,
-                       0x8E, 0x01, 0x00, 0xAC, 0x00, 0x00, 0xA9, 0x00,
-                       0x18, 0x6D, 0x01, 0x00, 0x88, 0xD0, 0xFA, 0x8D,
-                       0x02, 0x00, 0xEA, 0xEA, 0xEA);
+    nes.power_on();
+    // Multiply 10 by 3
+    let program = Assembler::new(Variant::Ricoh2A03).assemble("
+        .org $0600
+        start:  ldx #10
+                stx $00
+                ldx #3
+                stx $01
+                ldy $00
+                lda #0
+                clc
+        @loop:  adc $01
+                dey
+                bne @loop
+                sta $02
+                nop
+                nop
+                nop
+    ").expect("invalid program");
 
-    nes.cpu.set_ram(&mut nes.bus, &program, 0x5000);
-    nes.cpu.set_pc(0x5000);
+    program.load(&mut nes.bus);
+    nes.cpu.set_pc(0x0600);
 
     loop {
         nes.cpu.tick(&mut nes.bus);
         nes.cpu.print_page(&nes.bus, 0);
-        nes.cpu.print_page(&nes.bus, 0x5000);
+        nes.cpu.print_page(&nes.bus, 0x0600);
         nes.cpu.print_register();
         nes.cpu.print_status();
         while nes.cpu.cycles > 0 {
@@ -0,0 +1,161 @@
+#[cfg(test)]
+mod tests {
+    use rustyneslib::{CPU, Bus, Variant};
+
+    /// Run a program from $0200 for a number of instructions in both the
+    /// instant and the cycle accurate mode, checking they agree. Returns the
+    /// bus and the cycles each instruction took.
+    fn run(variant: Variant, program: &[u8], memory: &[(usize, u8)], count: usize) -> (Bus, Vec<usize>) {
+        let mut results: Vec<(Bus, Vec<usize>)> = Vec::new();
+        for cycle_accurate in [false, true] {
+            let mut bus = Bus::new();
+            for &(address, value) in memory {
+                bus.write(address, value);
+            }
+            for (i, &value) in program.iter().enumerate() {
+                bus.write(0x0200 + i, value);
+            }
+            let mut cpu = CPU::with_variant(variant);
+            cpu.set_cycle_accurate(cycle_accurate);
+            cpu.set_pc(0x0200);
+
+            let mut cycles = Vec::new();
+            for _ in 0..count {
+                let mut taken = 1;
+                cpu.tick(&mut bus);
+                while cpu.cycles > 0 {
+                    cpu.tick(&mut bus);
+                    taken += 1;
+                }
+                cycles.push(taken);
+            }
+            if let Some((first, first_cycles)) = results.first() {
+                assert_eq!(first.ram[..], bus.ram[..], "modes disagree on memory");
+                assert_eq!(first_cycles, &cycles, "modes disagree on timing");
+            }
+            results.push((bus, cycles));
+        }
+        results.pop().unwrap()
+    }
+
+    #[test]
+    pub fn test_zero_page_indexed_wraps() {
+        let program = [
+            0xA2, 0xFF,             // LDX #$FF
+            0xB5, 0x80,             // LDA $80,X reads $7F
+            0x8D, 0x00, 0x07,       // STA $0700
+            0x95, 0xFF,             // STA $FF,X writes $FE
+            0xA0, 0x02,             // LDY #$02
+            0xB6, 0xFF,             // LDX $FF,Y reads $01
+            0x8E, 0x01, 0x07,       // STX $0701
+            0x96, 0xFF,             // STX $FF,Y writes $01
+        ];
+        let (bus, cycles) = run(Variant::Ricoh2A03, &program, &[(0x7F, 0x11), (0x01, 0x22), (0x17F, 0xEE)], 8);
+        assert_eq!(bus.ram[0x700], 0x11);
+        assert_eq!(bus.ram[0xFE], 0x11);
+        assert_eq!(bus.ram[0x701], 0x22);
+        assert_eq!(bus.ram[0x01], 0x22);
+        assert_eq!(bus.ram[0x101], 0x00);
+        assert_eq!(cycles, [2, 4, 4, 4, 2, 4, 4, 4]);
+    }
+
+    #[test]
+    pub fn test_indexed_indirect_wraps() {
+        let program = [
+            0xA2, 0x01,             // LDX #$01
+            0xA1, 0xFE,             // LDA ($FE,X): pointer at $FF, high byte from $00
+            0x8D, 0x00, 0x07,       // STA $0700
+            0xA1, 0xFF,             // LDA ($FF,X): pointer at $00
+            0x8D, 0x01, 0x07,       // STA $0701
+        ];
+        let memory = [(0xFF, 0x34), (0x00, 0x03), (0x01, 0x04), (0x100, 0x05),
+                      (0x334, 0xAA), (0x534, 0xBB), (0x403, 0xCC)];
+        let (bus, cycles) = run(Variant::Ricoh2A03, &program, &memory, 5);
+        assert_eq!(bus.ram[0x700], 0xAA);
+        assert_eq!(bus.ram[0x701], 0xCC);
+        assert_eq!(cycles, [2, 6, 4, 6, 4]);
+    }
+
+    #[test]
+    pub fn test_indirect_indexed() {
+        let program = [
+            0xA0, 0x00,             // LDY #$00
+            0xB1, 0xFF,             // LDA ($FF),Y: pointer high byte from $00
+            0x8D, 0x00, 0x07,       // STA $0700
+            0xA0, 0x20,             // LDY #$20
+            0xB1, 0x10,             // LDA ($10),Y: $03F0 + $20 crosses a page
+            0x8D, 0x01, 0x07,       // STA $0701
+            0x91, 0x12,             // STA ($12),Y: $FFF0 + $20 wraps to $0010
+        ];
+        let memory = [(0xFF, 0x50), (0x00, 0x03), (0x100, 0x04), (0x350, 0xAA), (0x450, 0xEE),
+                      (0x10, 0xF0), (0x11, 0x03), (0x410, 0xBB), (0x12, 0xF0), (0x13, 0xFF)];
+        let (bus, cycles) = run(Variant::Ricoh2A03, &program, &memory, 7);
+        assert_eq!(bus.ram[0x700], 0xAA);
+        assert_eq!(bus.ram[0x701], 0xBB);
+        assert_eq!(bus.ram[0x010], 0xBB);
+        assert_eq!(cycles, [2, 5, 4, 2, 6, 4, 6]);
+    }
+
+    #[test]
+    pub fn test_absolute_indexed() {
+        let program = [
+            0xA2, 0x10,             // LDX #$10
+            0xBD, 0xE0, 0x03,       // LDA $03E0,X, same page
+            0xBD, 0xF8, 0x03,       // LDA $03F8,X, crosses to $0408
+            0x9D, 0xE0, 0x03,       // STA $03E0,X always takes 5 cycles
+            0xA0, 0x02,             // LDY #$02
+            0xB9, 0xFF, 0xFF,       // LDA $FFFF,Y wraps to $0001
+            0x99, 0xFF, 0xFF,       // STA $FFFF,Y
+            0xFE, 0xF8, 0x03,       // INC $03F8,X
+        ];
+        let memory = [(0x3F0, 0x11), (0x408, 0x22), (0x01, 0x33)];
+        let (bus, cycles) = run(Variant::Ricoh2A03, &program, &memory, 8);
+        assert_eq!(bus.ram[0x3F0], 0x22);
+        assert_eq!(bus.ram[0x01], 0x33);
+        assert_eq!(bus.ram[0x408], 0x23);
+        assert_eq!(cycles, [2, 4, 5, 5, 2, 5, 5, 7]);
+    }
+
+    #[test]
+    pub fn test_jmp_indirect_page_wrap() {
+        // JMP ($10FF): the target's low byte is at $10FF, a mirror of $00FF.
+        // The NMOS CPU takes the high byte from $1000 (mirror of $0000), the
+        // 65C02 from $1100 (mirror of $0100).
+        let program = [0x6C, 0xFF, 0x10];
+        let memory = [(0xFF, 0x00), (0x00, 0x03), (0x100, 0x04),
+                      (0x300, 0xA9), (0x301, 0xAA), (0x302, 0x8D), (0x303, 0x00), (0x304, 0x07),
+                      (0x400, 0xA9), (0x401, 0xBB), (0x402, 0x8D), (0x403, 0x00), (0x404, 0x07)];
+        let (bus, cycles) = run(Variant::Nmos6502, &program, &memory, 3);
+        assert_eq!(bus.ram[0x700], 0xAA);
+        assert_eq!(cycles, [5, 2, 4]);
+
+        let (bus, cycles) = run(Variant::Cmos65C02, &program, &memory, 3);
+        assert_eq!(bus.ram[0x700], 0xBB);
+        assert_eq!(cycles, [6, 2, 4]);
+    }
+
+    #[test]
+    pub fn test_branch_boundaries() {
+        let mut program = vec![0xEA; 0x110];
+        program[0x000..0x007].copy_from_slice(&[
+            0xA9, 0x00,             // $0200: LDA #$00
+            0xD0, 0x7F,             // $0202: BNE, not taken
+            0x4C, 0x80, 0x02,       // $0204: JMP $0280
+        ]);
+        program[0x080..0x084].copy_from_slice(&[
+            0xA9, 0x01,             // $0280: LDA #$01
+            0xD0, 0x7C,             // $0282: BNE $0300, forward across a page
+        ]);
+        program[0x0FE..0x105].copy_from_slice(&[
+            0xD0, 0x0E,             // $02FE: BNE $030E, no crossing as PC is already $0300
+            0xD0, 0xFC,             // $0300: BNE $02FE, backward across a page
+            0x8D, 0x00, 0x07,       // $0302: STA $0700
+        ]);
+        program[0x10E..0x110].copy_from_slice(&[
+            0xD0, 0xF2,             // $030E: BNE $0302, backward in the same page
+        ]);
+        let (bus, cycles) = run(Variant::Ricoh2A03, &program, &[], 9);
+        assert_eq!(cycles, [2, 2, 3, 2, 4, 4, 3, 3, 4]);
+        assert_eq!(bus.ram[0x700], 0x01);
+    }
+}
@@ -0,0 +1,76 @@
+//! Trace of nestest in automation mode against the reference nestest.log.
+//!
+//! Neither file is part of the repository. Put `nestest.nes` and `nestest.log`
+//! in `tests/roms`, or point `NESTEST_DIR` at the directory holding them, then
+//! run the ignored tests with `cargo test --test test_nestest -- --ignored`.
+//!
+//! The opening lines of nestest.log are kept in `tests/data` and checked
+//! against a ROM holding the same instructions, so the trace format is
+//! covered without the files.
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use rustyneslib::{Cartridge, NES};
+
+    fn nestest_dir() -> PathBuf {
+        match std::env::var_os("NESTEST_DIR") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms"),
+        }
+    }
+
+    /// Run nestest automation mode and collect the trace
+    fn trace(nes: &mut NES) -> Vec<String> {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let log = lines.clone();
+        nes.run_nestest(Box::new(move |line: &str| log.borrow_mut().push(line.to_string())));
+        lines.take()
+    }
+
+    #[test]
+    pub fn test_nestest_start() {
+        // NROM image with the instructions nestest runs first, then a JAM
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        let mut load = |address: usize, bytes: &[u8]| {
+            let start = 16 + address - 0xC000;
+            rom[start..start + bytes.len()].copy_from_slice(bytes);
+        };
+        load(0xC000, &[0x4C, 0xF5, 0xC5]);
+        load(0xC5F5, &[0xA2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2D, 0xC7]);
+        load(0xC72D, &[0xEA, 0x38, 0xB0, 0x04]);
+        load(0xC735, &[0xEA, 0x18, 0xB0, 0x03, 0x4C, 0x42, 0xC7]);
+        load(0xC742, &[0x02]);
+
+        let mut nes = NES::new();
+        nes.bus.cartridge = Cartridge::from_bytes(&rom).unwrap();
+        let lines = trace(&mut nes);
+        let reference = include_str!("data/nestest_start.log");
+        for (number, expected) in reference.lines().enumerate() {
+            assert_eq!(lines[number], expected, "trace differs at line {}", number + 1);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs nestest.nes and nestest.log in tests/roms or NESTEST_DIR"]
+    pub fn test_nestest_log() {
+        let dir = nestest_dir();
+        let rom = dir.join("nestest.nes");
+        let reference = std::fs::read_to_string(dir.join("nestest.log"))
+            .unwrap_or_else(|err| panic!("{}: {}", dir.join("nestest.log").display(), err));
+
+        let mut nes = NES::new();
+        nes.bus.cartridge.load(rom.to_string_lossy().into_owned())
+            .unwrap_or_else(|err| panic!("{}: {}", rom.display(), err));
+        let lines = trace(&mut nes);
+        for (number, expected) in reference.lines().enumerate() {
+            let actual = lines.get(number).map(|line| line.as_str()).unwrap_or("<end of trace>");
+            assert_eq!(actual, expected.trim_end(), "trace differs at line {}", number + 1);
+        }
+        assert_eq!(nes.bus.read(0x02), 0x00, "official opcode tests failed");
+        assert_eq!(nes.bus.read(0x03), 0x00, "unofficial opcode tests failed");
+    }
+}
@@ -0,0 +1,421 @@
+//! Conformance against the SingleStepTests (Tom Harte's ProcessorTests) 6502
+//! vectors. Each `xx.json` file holds the tests of one opcode: the CPU and RAM
+//! state before and after a single instruction, and every bus access it made.
+//!
+//! The vectors are not part of the repository. Put the `nes6502` and `6502`
+//! directories of the ProcessorTests repository under `tests/ProcessorTests`,
+//! or point `PROCESSOR_TESTS` at the directory holding them, then run the
+//! ignored tests with `cargo test --test test_single_step -- --ignored`.
+//!
+//! A few vectors per addressing mode are written out below, so the bus access
+//! comparison is covered without the files.
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use rustyneslib::{CPU, CpuBus, FlatMemory, BusAccess, Status, Variant, OPCODES};
+
+    /// Opcodes whose result depends on analog effects the vectors capture
+    /// for one particular chip. Their counts are reported but not enforced.
+    const UNSTABLE: [u8; 7] = [0x8B, 0xAB, 0x93, 0x9F, 0x9C, 0x9E, 0x9B];
+
+    #[derive(Debug)]
+    enum Json {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v).unwrap_or(&Json::Null),
+                _ => &Json::Null,
+            }
+        }
+
+        fn items(&self) -> &[Json] {
+            match self {
+                Json::Array(items) => items,
+                _ => &[],
+            }
+        }
+
+        fn number(&self) -> usize {
+            match self {
+                Json::Number(n) => *n as usize,
+                _ => panic!("expected a number, got {:?}", self),
+            }
+        }
+
+        fn string(&self) -> &str {
+            match self {
+                Json::String(s) => s,
+                _ => panic!("expected a string, got {:?}", self),
+            }
+        }
+    }
+
+    /// Just enough JSON for the test vectors
+    struct Parser<'a> {
+        text: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Parser<'a> {
+        fn parse(text: &'a str) -> Json {
+            let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+            parser.value()
+        }
+
+        fn skip_whitespace(&mut self) {
+            while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+        }
+
+        fn expect(&mut self, byte: u8) {
+            self.skip_whitespace();
+            assert_eq!(self.text[self.pos], byte, "malformed JSON at byte {}", self.pos);
+            self.pos += 1;
+        }
+
+        /// Consume the byte if it comes next
+        fn accept(&mut self, byte: u8) -> bool {
+            self.skip_whitespace();
+            if self.text.get(self.pos) == Some(&byte) {
+                self.pos += 1;
+                return true;
+            }
+            false
+        }
+
+        fn value(&mut self) -> Json {
+            self.skip_whitespace();
+            match self.text[self.pos] {
+                b'{' => {
+                    self.pos += 1;
+                    let mut fields = Vec::new();
+                    if !self.accept(b'}') {
+                        loop {
+                            self.skip_whitespace();
+                            let key = self.string();
+                            self.expect(b':');
+                            fields.push((key, self.value()));
+                            if !self.accept(b',') {
+                                break;
+                            }
+                        }
+                        self.expect(b'}');
+                    }
+                    Json::Object(fields)
+                }
+                b'[' => {
+                    self.pos += 1;
+                    let mut items = Vec::new();
+                    if !self.accept(b']') {
+                        loop {
+                            items.push(self.value());
+                            if !self.accept(b',') {
+                                break;
+                            }
+                        }
+                        self.expect(b']');
+                    }
+                    Json::Array(items)
+                }
+                b'"' => Json::String(self.string()),
+                b't' => {
+                    self.pos += 4;
+                    Json::Bool(true)
+                }
+                b'f' => {
+                    self.pos += 5;
+                    Json::Bool(false)
+                }
+                b'n' => {
+                    self.pos += 4;
+                    Json::Null
+                }
+                _ => {
+                    let start = self.pos;
+                    while self.pos < self.text.len() && b"+-.eE0123456789".contains(&self.text[self.pos]) {
+                        self.pos += 1;
+                    }
+                    let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+                    Json::Number(number.parse().expect("malformed JSON number"))
+                }
+            }
+        }
+
+        /// Strings in the vectors are names and access kinds, escapes are kept as is
+        fn string(&mut self) -> String {
+            self.expect(b'"');
+            let start = self.pos;
+            while self.text[self.pos] != b'"' {
+                if self.text[self.pos] == b'\\' {
+                    self.pos += 1;
+                }
+                self.pos += 1;
+            }
+            let s = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+            self.pos += 1;
+            s
+        }
+    }
+
+    /// Registers and the RAM bytes listed by a test
+    struct State {
+        pc: usize,
+        sp: u8,
+        a: u8,
+        x: u8,
+        y: u8,
+        p: u8,
+        ram: Vec<(usize, u8)>,
+    }
+
+    impl State {
+        fn from_json(json: &Json) -> State {
+            State {
+                pc: json.get("pc").number(),
+                sp: json.get("s").number() as u8,
+                a: json.get("a").number() as u8,
+                x: json.get("x").number() as u8,
+                y: json.get("y").number() as u8,
+                p: json.get("p").number() as u8,
+                ram: json.get("ram").items().iter()
+                    .map(|entry| (entry.items()[0].number(), entry.items()[1].number() as u8))
+                    .collect(),
+            }
+        }
+    }
+
+    /// Run one test, returning what went wrong
+    fn run_test(variant: Variant, test: &Json) -> Result<(), String> {
+        let initial = State::from_json(test.get("initial"));
+        let expected = State::from_json(test.get("final"));
+        let cycles: Vec<BusAccess> = test.get("cycles").items().iter()
+            .map(|entry| BusAccess {
+                address: entry.items()[0].number(),
+                value: entry.items()[1].number() as u8,
+                write: entry.items()[2].string() == "write",
+            })
+            .collect();
+
+        let mut bus = FlatMemory::new();
+        for &(address, value) in &initial.ram {
+            bus.memory[address] = value;
+        }
+        let mut cpu = CPU::with_variant(variant);
+        cpu.set_cycle_accurate(true);
+        cpu.set_pc(initial.pc);
+        cpu.set_registers(initial.a, initial.x, initial.y, initial.sp);
+        cpu.set_status(Status::from_pulled(initial.p));
+
+        bus.set_access_log(true);
+        cpu.tick(&mut bus);
+        let mut ticks = 1;
+        while cpu.cycles > 0 && ticks < 16 {
+            cpu.tick(&mut bus);
+            ticks += 1;
+        }
+        let accesses = bus.take_access_log();
+        bus.set_access_log(false);
+
+        let mut errors = Vec::new();
+        let registers = [
+            ("PC", cpu.pc(), expected.pc),
+            ("S", cpu.sp() as usize, expected.sp as usize),
+            ("A", cpu.a() as usize, expected.a as usize),
+            ("X", cpu.x() as usize, expected.x as usize),
+            ("Y", cpu.y() as usize, expected.y as usize),
+            // B and the unused bit only exist on the stack
+            ("P", (cpu.status().bits() | 0x30) as usize, (expected.p | 0x30) as usize),
+        ];
+        for (name, actual, wanted) in registers {
+            if actual != wanted {
+                errors.push(format!("{} is ${:02X}, expected ${:02X}", name, actual, wanted));
+            }
+        }
+        for &(address, wanted) in &expected.ram {
+            let actual = bus.peek(address);
+            if actual != wanted {
+                errors.push(format!("${:04X} is ${:02X}, expected ${:02X}", address, actual, wanted));
+            }
+        }
+        if accesses != cycles {
+            errors.push(format!("bus accesses {:?}, expected {:?}", accesses, cycles));
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(errors.join("; "))
+    }
+
+    /// Run every opcode file of a set, returning the opcodes with failures
+    fn run_set(variant: Variant, directory: &Path) -> Vec<u8> {
+        assert!(directory.is_dir(), "{}: not found", directory.display());
+
+        let mut results: BTreeMap<u8, (usize, usize, Option<String>)> = BTreeMap::new();
+        for opcode in 0..=0xFF_u8 {
+            let path = directory.join(format!("{:02x}.json", opcode));
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            let json = Parser::parse(&text);
+            let entry = results.entry(opcode).or_insert((0, 0, None));
+            for test in json.items() {
+                match run_test(variant, test) {
+                    Ok(()) => entry.0 += 1,
+                    Err(error) => {
+                        entry.1 += 1;
+                        if entry.2.is_none() {
+                            entry.2 = Some(format!("{}: {}", test.get("name").string(), error));
+                        }
+                    }
+                }
+            }
+        }
+
+        println!("{}: {:?}", directory.display(), variant);
+        let mut failed = Vec::new();
+        for (opcode, (passed, failures, first)) in &results {
+            println!("  {:02X} {:<4} {:>6} passed {:>6} failed", opcode, OPCODES[*opcode as usize].mnemonic, passed, failures);
+            if let Some(first) = first {
+                println!("      first failure {}", first);
+                if !UNSTABLE.contains(opcode) {
+                    failed.push(*opcode);
+                }
+            }
+        }
+        failed
+    }
+
+    fn tests_root() -> PathBuf {
+        match std::env::var_os("PROCESSOR_TESTS") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("ProcessorTests"),
+        }
+    }
+
+    #[test]
+    pub fn test_json_parser() {
+        let json = Parser::parse(r#"[{"name": "a9 \"x\"", "initial": {"pc": 512, "ram": [[512, 169], [513, 0]]},
+                                      "cycles": [[512, 169, "read"]], "ok": true, "none": null, "n": -1.5e1}]"#);
+        let test = &json.items()[0];
+        assert_eq!(test.get("name").string(), r#"a9 \"x\""#);
+        assert_eq!(test.get("initial").get("pc").number(), 512);
+        assert_eq!(test.get("initial").get("ram").items()[1].items()[0].number(), 513);
+        assert_eq!(test.get("cycles").items()[0].items()[2].string(), "read");
+        assert!(matches!(test.get("ok"), Json::Bool(true)));
+        assert!(matches!(test.get("none"), Json::Null));
+        assert!(matches!(test.get("n"), Json::Number(n) if *n == -15.0));
+        assert!(matches!(test.get("missing"), Json::Null));
+    }
+
+    #[test]
+    pub fn test_single_step_vectors() {
+        let vectors = [
+            // LDA #$42
+            r#"{"name": "a9 42",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[768, 169], [769, 66]]},
+                "final": {"pc": 770, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36, "ram": [[768, 169], [769, 66]]},
+                "cycles": [[768, 169, "read"], [769, 66, "read"]]}"#,
+            // INX, with the dummy read of the next byte
+            r#"{"name": "e8",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 255, "y": 0, "p": 36, "ram": [[768, 232], [769, 0]]},
+                "final": {"pc": 769, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38, "ram": [[768, 232], [769, 0]]},
+                "cycles": [[768, 232, "read"], [769, 0, "read"]]}"#,
+            // INC $10, writing the old value back before the new one
+            r#"{"name": "e6 10",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                            "ram": [[768, 230], [769, 16], [16, 127]]},
+                "final": {"pc": 770, "s": 253, "a": 0, "x": 0, "y": 0, "p": 164,
+                          "ram": [[768, 230], [769, 16], [16, 128]]},
+                "cycles": [[768, 230, "read"], [769, 16, "read"], [16, 127, "read"],
+                           [16, 127, "write"], [16, 128, "write"]]}"#,
+            // LDA $F0,X wrapping in page zero, after a dummy read of $F0
+            r#"{"name": "b5 f0",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 32, "y": 0, "p": 36,
+                            "ram": [[768, 181], [769, 240], [240, 0], [16, 85]]},
+                "final": {"pc": 770, "s": 253, "a": 85, "x": 32, "y": 0, "p": 36,
+                          "ram": [[768, 181], [769, 240], [240, 0], [16, 85]]},
+                "cycles": [[768, 181, "read"], [769, 240, "read"], [240, 0, "read"], [16, 85, "read"]]}"#,
+            // STA $12F0,X crossing a page, the dummy read is in the old page
+            r#"{"name": "9d f0 12",
+                "initial": {"pc": 768, "s": 253, "a": 51, "x": 32, "y": 0, "p": 36,
+                            "ram": [[768, 157], [769, 240], [770, 18], [4624, 0], [4880, 0]]},
+                "final": {"pc": 771, "s": 253, "a": 51, "x": 32, "y": 0, "p": 36,
+                          "ram": [[768, 157], [769, 240], [770, 18], [4624, 0], [4880, 51]]},
+                "cycles": [[768, 157, "read"], [769, 240, "read"], [770, 18, "read"],
+                           [4624, 0, "read"], [4880, 51, "write"]]}"#,
+            // ASL $1200,X, read twice and written twice
+            r#"{"name": "1e 00 12",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 1, "y": 0, "p": 36,
+                            "ram": [[768, 30], [769, 0], [770, 18], [4609, 129]]},
+                "final": {"pc": 771, "s": 253, "a": 0, "x": 1, "y": 0, "p": 37,
+                          "ram": [[768, 30], [769, 0], [770, 18], [4609, 2]]},
+                "cycles": [[768, 30, "read"], [769, 0, "read"], [770, 18, "read"], [4609, 129, "read"],
+                           [4609, 129, "read"], [4609, 129, "write"], [4609, 2, "write"]]}"#,
+            // LDA ($FE,X) with the pointer at $FF wrapping to $00
+            r#"{"name": "a1 fe",
+                "initial": {"pc": 768, "s": 253, "a": 1, "x": 1, "y": 0, "p": 36,
+                            "ram": [[768, 161], [769, 254], [254, 0], [255, 52], [0, 18], [4660, 0]]},
+                "final": {"pc": 770, "s": 253, "a": 0, "x": 1, "y": 0, "p": 38,
+                          "ram": [[768, 161], [769, 254], [254, 0], [255, 52], [0, 18], [4660, 0]]},
+                "cycles": [[768, 161, "read"], [769, 254, "read"], [254, 0, "read"], [255, 52, "read"],
+                           [0, 18, "read"], [4660, 0, "read"]]}"#,
+            // LDA ($FF),Y with the pointer wrapping in page zero and a page crossing
+            r#"{"name": "b1 ff",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 0, "y": 16, "p": 36,
+                            "ram": [[768, 177], [769, 255], [255, 248], [0, 18], [4872, 128]]},
+                "final": {"pc": 770, "s": 253, "a": 128, "x": 0, "y": 16, "p": 164,
+                          "ram": [[768, 177], [769, 255], [255, 248], [0, 18], [4872, 128]]},
+                "cycles": [[768, 177, "read"], [769, 255, "read"], [255, 248, "read"],
+                           [0, 18, "read"], [4616, 0, "read"], [4872, 128, "read"]]}"#,
+            // STA ($10),Y crossing a page
+            r#"{"name": "91 10",
+                "initial": {"pc": 768, "s": 253, "a": 119, "x": 0, "y": 32, "p": 36,
+                            "ram": [[768, 145], [769, 16], [16, 240], [17, 18], [4624, 0], [4880, 0]]},
+                "final": {"pc": 770, "s": 253, "a": 119, "x": 0, "y": 32, "p": 36,
+                          "ram": [[768, 145], [769, 16], [16, 240], [17, 18], [4624, 0], [4880, 119]]},
+                "cycles": [[768, 145, "read"], [769, 16, "read"], [16, 240, "read"], [17, 18, "read"],
+                           [4624, 0, "read"], [4880, 119, "write"]]}"#,
+            // BNE taken across a page
+            r#"{"name": "d0 04",
+                "initial": {"pc": 764, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                            "ram": [[764, 208], [765, 4], [766, 0], [514, 0]]},
+                "final": {"pc": 770, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                          "ram": [[764, 208], [765, 4], [766, 0], [514, 0]]},
+                "cycles": [[764, 208, "read"], [765, 4, "read"], [766, 0, "read"], [514, 0, "read"]]}"#,
+            // JSR $0400
+            r#"{"name": "20 00 04",
+                "initial": {"pc": 768, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                            "ram": [[768, 32], [769, 0], [770, 4], [509, 0], [508, 0]]},
+                "final": {"pc": 1024, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36,
+                          "ram": [[768, 32], [769, 0], [770, 4], [509, 3], [508, 2]]},
+                "cycles": [[768, 32, "read"], [769, 0, "read"], [509, 0, "read"], [509, 3, "write"],
+                           [508, 2, "write"], [770, 4, "read"]]}"#,
+        ];
+        for vector in vectors {
+            let test = Parser::parse(vector);
+            assert_eq!(run_test(Variant::Ricoh2A03, &test), Ok(()), "{}", test.get("name").string());
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the ProcessorTests vectors in tests/ProcessorTests or PROCESSOR_TESTS"]
+    pub fn test_processor_tests() {
+        let root = tests_root();
+        let mut failed = run_set(Variant::Ricoh2A03, &root.join("nes6502").join("v1"));
+        failed.extend(run_set(Variant::Nmos6502, &root.join("6502").join("v1")));
+        assert!(failed.is_empty(), "opcodes with failures: {:02X?}", failed);
+    }
+}